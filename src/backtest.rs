@@ -0,0 +1,41 @@
+use serde::Deserialize;
+use crate::models::BookTicker;
+
+// One best bid/ask update read from the recorded file
+pub struct Tick {
+    pub top_bid: f64,
+    pub top_ask: f64,
+    // Event time in ms, 0 when the file has none
+    pub time: u64
+}
+
+// Accepts either raw bookTicker websocket messages (one JSON per line, with or without the
+// combined stream wrapper) or the Binance public data CSV:
+// update_id,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty,transaction_time,event_time
+pub fn read_ticks(path: &str) -> Vec<Tick> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("\x1b[91mERROR: Failed to read backtest file {}\x1b[0m", path));
+    let mut ticks: Vec<Tick> = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('{') {
+            let line_json: serde_json::Value = match serde_json::from_str(line) {
+                Ok(line_json) => line_json,
+                Err(_) => continue
+            };
+            let data = line_json.get("data").unwrap_or(&line_json);
+            if let Ok(ticker) = BookTicker::deserialize(data) {
+                ticks.push(Tick { top_bid: ticker.best_bid, top_ask: ticker.best_ask, time: ticker.event_time });
+            }
+        } else {
+            let columns: Vec<&str> = line.split(',').collect();
+            if columns.len() >= 4 {
+                if let (Ok(top_bid), Ok(top_ask)) = (columns[1].parse::<f64>(), columns[3].parse::<f64>()) {
+                    let time = columns.get(5).and_then(|time| time.trim().parse::<u64>().ok()).unwrap_or(0);
+                    ticks.push(Tick { top_bid, top_ask, time });
+                }
+            }
+        }
+    }
+    ticks
+}
+
@@ -0,0 +1,437 @@
+use async_trait::async_trait;
+use hex::encode as hex_encode;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT, CONTENT_TYPE};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+use serde::de::DeserializeOwned;
+use crate::error::{ApiCode, GateError};
+use crate::exchange::Exchange;
+use crate::models::{AccountInfo, ApiError, ExchangeInfo, Income, LeverageBracket, LeverageBrackets, LeverageResponse, ListenKey, OrderResponse, OrderResult, OrderResults, OrderStatus, PremiumIndex};
+use crate::order_manager::Order;
+use crate::request_log::{LoggedRequest, RequestLog};
+use crate::rate_limiter::{Cost, Priority, RateLimitSettings, RateLimiter};
+use crate::retry::RetrySettings;
+
+// Binance allows at most 5 orders per batchOrders call
+const BATCH_SIZE: usize = 5;
+
+#[derive(Clone)]
+pub struct BinanceExchange {
+    url_request: String,
+    client: reqwest::Client,
+    header: HeaderMap,
+    signature: Hmac<Sha256>,
+    retry: RetrySettings,
+    limiter: Arc<RateLimiter>,
+    // Set for the dry run: order writes are signed and logged, never sent
+    request_log: Option<Arc<RequestLog>>
+}
+
+impl BinanceExchange {
+    pub fn new(url_request: String, api_key: &str, api_secret: &str, retry: RetrySettings, rate_limit: RateLimitSettings) -> Self {
+        let mut header = HeaderMap::new();
+        header.insert(USER_AGENT, HeaderValue::from_static("binance-rs"));
+        header.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+        header.insert(HeaderName::from_static("x-mbx-apikey"), HeaderValue::from_str(api_key).unwrap());
+
+        BinanceExchange {
+            url_request,
+            client: reqwest::Client::builder().pool_idle_timeout(None).build().unwrap(),
+            header,
+            signature: Hmac::<Sha256>::new_from_slice(api_secret.as_bytes()).unwrap(),
+            retry,
+            limiter: Arc::new(RateLimiter::new(rate_limit)),
+            request_log: None
+        }
+    }
+
+    pub fn with_request_log(mut self, request_log: RequestLog) -> Self {
+        self.request_log = Some(Arc::new(request_log));
+        self
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let mut signature = self.signature.clone();
+        signature.update(payload.as_bytes());
+        hex_encode(signature.finalize().into_bytes())
+    }
+
+    fn signed_url(&self, endpoint: &str, payload: &str) -> String {
+        format!("{}{}?{}&signature={}", self.url_request, endpoint, payload, self.sign(payload))
+    }
+
+    // Signs every request exactly as it would be sent and writes them to the request log
+    fn log_requests(&self, request_log: &RequestLog, method: Method, requests: &[BatchRequest], decimal_counts: Option<(i64, i64)>) {
+        let signed: Vec<(u128, String)> = requests.iter().map(|request| {
+            let timestamp = get_timestamp();
+            (timestamp, self.sign(&format!("{}&timestamp={}", request.payload, timestamp)))
+        }).collect();
+        let logged: Vec<LoggedRequest> = requests.iter().zip(signed.iter()).map(|(request, (timestamp, signature))| LoggedRequest {
+            endpoint: request.endpoint,
+            orders: &request.orders,
+            payload: &request.payload,
+            timestamp: *timestamp,
+            signature
+        }).collect();
+        request_log.record(method.as_str(), &logged, decimal_counts);
+    }
+
+    // Signs the request again on every attempt so a retried -1021 gets a fresh timestamp
+    async fn send_request(&self, method: Method, endpoint: &str, params: &str) -> Result<String, GateError> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.send_once(method.clone(), endpoint, params).await {
+                Ok(resp) => return Ok(resp),
+                Err(error) => error
+            };
+            match self.retry.delay(&error, attempt) {
+                Some(delay) => {
+                    println!("\x1b[93mRetrying {} {} in {}ms after {}\x1b[0m", method, endpoint, delay.as_millis(), error);
+                    sleep(delay).await;
+                    attempt += 1;
+                },
+                None => return Err(error)
+            }
+        }
+    }
+
+    async fn send_once(&self, method: Method, endpoint: &str, params: &str) -> Result<String, GateError> {
+        self.limiter.acquire(request_cost(&method, endpoint)).await;
+        let url = self.signed_url(endpoint, &format!("{}timestamp={}", params, get_timestamp()));
+        let resp = self.client.request(method, url).headers(self.header.clone()).send().await?;
+        let status = resp.status();
+        self.limiter.update(status, resp.headers());
+        check_response(status, resp.text().await?)
+    }
+
+    async fn delete_request(&self, endpoint: &str, params: &str) -> Result<String, GateError> {
+        self.send_request(Method::DELETE, endpoint, params).await
+    }
+
+    async fn get_request(&self, endpoint: &str, params: &str) -> Result<String, GateError> {
+        self.send_request(Method::GET, endpoint, params).await
+    }
+
+    async fn post_request(&self, endpoint: &str, params: &str) -> Result<String, GateError> {
+        self.send_request(Method::POST, endpoint, params).await
+    }
+
+    async fn put_request(&self, endpoint: &str) -> Result<String, GateError> {
+        self.send_request(Method::PUT, endpoint, "").await
+    }
+
+    // Close orders are batched first, then the open levels in the order the grid lists them, nearest first
+    fn post_order_requests(&self, pair: &str, mut orders: Vec<Order>, price_decimal_count: i64, quantity_decimal_count: i64) -> Vec<BatchRequest> {
+        orders.sort_by_key(|order| order.is_open());
+        let mut requests: Vec<BatchRequest> = vec![];
+        for batch in chunk_real_orders(orders) {
+            let priority = if batch.iter().any(|order| !order.is_open()) { Priority::High } else { Priority::Normal };
+            if batch.len() == 1 {
+                let payload = batch[0].to_single_url_string(pair, price_decimal_count, quantity_decimal_count);
+                requests.push(BatchRequest { endpoint: "/fapi/v1/order", payload, cost: Cost::new(1, 1, priority), orders: batch });
+            } else {
+                let batch_orders = format!("[{}]", batch.iter().map(|order| order.to_url_string(pair, price_decimal_count, quantity_decimal_count)).collect::<Vec<String>>().join(","));
+                let payload = format!("batchOrders={}", urlencoding::encode(&batch_orders));
+                requests.push(BatchRequest { endpoint: "/fapi/v1/batchOrders", payload, cost: Cost::new(5, batch.len() as u64, priority), orders: batch });
+            }
+        }
+        requests
+    }
+
+    fn cancel_order_requests(&self, pair: &str, orders: Vec<Order>) -> Vec<BatchRequest> {
+        let mut requests: Vec<BatchRequest> = vec![];
+        for batch in chunk_real_orders(orders) {
+            if batch.len() == 1 {
+                let payload = format!("symbol={}&origClientOrderId={}", pair, batch[0].get_id());
+                requests.push(BatchRequest { endpoint: "/fapi/v1/order", payload, cost: Cost::new(1, 0, Priority::Normal), orders: batch });
+            } else {
+                let order_ids = format!("[{}]", batch.iter().map(|order| format!("\"{}\"", order.get_id())).collect::<Vec<String>>().join(","));
+                let payload = format!("symbol={}&origClientOrderIdList={}", pair, urlencoding::encode(&order_ids));
+                requests.push(BatchRequest { endpoint: "/fapi/v1/batchOrders", payload, cost: Cost::new(1, 0, Priority::Normal), orders: batch });
+            }
+        }
+        requests
+    }
+
+    // Every batch waits for the budget before being signed and sent, so the timestamp does not age in the queue
+    async fn send_batches(&self, method: Method, requests: Vec<BatchRequest>) -> Vec<Result<String, GateError>> {
+        let mut handles = Vec::with_capacity(requests.len());
+        for request in requests {
+            self.limiter.acquire(request.cost).await;
+            let exchange = self.clone();
+            let method = method.clone();
+            handles.push(tokio::spawn(async move {
+                exchange.send_batch(method, request).await
+            }));
+        }
+        let mut bodies = Vec::with_capacity(handles.len());
+        for handle in handles {
+            bodies.push(handle.await.unwrap_or_else(|error| Err(GateError::Network(error.to_string()))));
+        }
+        bodies
+    }
+
+    // Retried like any other request, the budget is only taken again from the second attempt on
+    async fn send_batch(&self, method: Method, request: BatchRequest) -> Result<String, GateError> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.send_batch_once(method.clone(), &request).await {
+                Ok(resp) => return Ok(resp),
+                Err(error) => error
+            };
+            match self.retry.delay(&error, attempt) {
+                Some(delay) => {
+                    println!("\x1b[93mRetrying {} {} in {}ms after {}\x1b[0m", method, request.endpoint, delay.as_millis(), error);
+                    sleep(delay).await;
+                    self.limiter.acquire(request.cost).await;
+                    attempt += 1;
+                },
+                None => return Err(error)
+            }
+        }
+    }
+
+    async fn send_batch_once(&self, method: Method, request: &BatchRequest) -> Result<String, GateError> {
+        let url = self.signed_url(request.endpoint, &format!("{}&timestamp={}", request.payload, get_timestamp()));
+        let resp = self.client.request(method, url).headers(self.header.clone()).send().await?;
+        let status = resp.status();
+        self.limiter.update(status, resp.headers());
+        check_response(status, resp.text().await?)
+    }
+
+    async fn parallel_post_requests(&self, requests: Vec<BatchRequest>) -> bool {
+        let results: Vec<bool> = self.send_batches(Method::POST, requests).await.into_iter().map(|resp| {
+            let my_text = match resp {
+                Ok(my_text) => my_text,
+                Err(GateError::Api(api_code, _)) if is_not_posted(api_code) => return true,
+                Err(error) => {
+                    println!("\x1b[91mFailed to post order: {}\x1b[0m", error);
+                    return false
+                }
+            };
+            let failed = match serde_json::from_str::<OrderResults>(&my_text) {
+                Ok(results) => post_response_failed(results),
+                Err(_) => true
+            };
+            if failed {
+                println!("\x1b[91mFailed to post order\x1b[0m");
+                println!("\x1b[91m{}\x1b[0m", my_text);
+            }
+            !failed
+        }).collect();
+        results.into_iter().all(|success| success)
+    }
+
+    async fn parallel_delete_requests(&self, requests: Vec<BatchRequest>) -> bool {
+        let results: Vec<bool> = self.send_batches(Method::DELETE, requests).await.into_iter().map(|resp| {
+            let failed = match resp {
+                Ok(my_text) => match serde_json::from_str::<OrderResults>(&my_text) {
+                    Ok(results) => cancel_response_failed(results),
+                    Err(_) => true
+                },
+                Err(error) => {
+                    println!("\x1b[91m{}\x1b[0m", error);
+                    true
+                }
+            };
+            if failed {
+                println!("\x1b[91mFailed to cancel order\x1b[0m");
+            }
+            !failed
+        }).collect();
+        results.into_iter().all(|success| success)
+    }
+}
+
+// A single or batch order request, signed only when it is sent
+struct BatchRequest {
+    endpoint: &'static str,
+    payload: String,
+    cost: Cost,
+    orders: Vec<Order>
+}
+
+#[async_trait]
+impl Exchange for BinanceExchange {
+    async fn get_exchange_info(&self) -> Result<ExchangeInfo, GateError> {
+        parse_response(&self.get_request("/fapi/v1/exchangeInfo", "").await?)
+    }
+
+    async fn get_account_info(&self) -> Result<AccountInfo, GateError> {
+        parse_response(&self.get_request("/fapi/v2/account", "").await?)
+    }
+
+    async fn get_leverage_brackets(&self, pair: &str) -> Result<Vec<LeverageBracket>, GateError> {
+        let leverage_brackets: LeverageBrackets = parse_response(&self.get_request("/fapi/v1/leverageBracket", &format!("symbol={}&", pair)).await?)?;
+        Ok(leverage_brackets.brackets)
+    }
+
+    async fn get_premium_index(&self, pair: &str) -> Result<PremiumIndex, GateError> {
+        parse_response(&self.get_request("/fapi/v1/premiumIndex", &format!("symbol={}&", pair)).await?)
+    }
+
+    async fn get_funding_income(&self, pair: &str, start_time: u64) -> Result<Vec<Income>, GateError> {
+        parse_response(&self.get_request("/fapi/v1/income", &format!("symbol={}&incomeType=FUNDING_FEE&startTime={}&limit=1000&", pair, start_time)).await?)
+    }
+
+    async fn get_listen_key(&self) -> Result<String, GateError> {
+        let listen_key: ListenKey = parse_response(&self.post_request("/fapi/v1/listenKey", "").await?)?;
+        Ok(listen_key.listen_key)
+    }
+
+    async fn keepalive_listen_key(&self) -> Result<(), GateError> {
+        self.put_request("/fapi/v1/listenKey").await.map(|_| ())
+    }
+
+    async fn set_leverage(&self, pair: &str, leverage: u64) -> Result<(), GateError> {
+        let resp = self.post_request("/fapi/v1/leverage", &format!("symbol={}&leverage={}&", pair, leverage)).await?;
+        let leverage_resp: LeverageResponse = parse_response(&resp)?;
+        if leverage_resp.leverage != leverage {
+            return Err(GateError::Parse(format!("leverage set to {} instead of {}", leverage_resp.leverage, leverage)))
+        }
+        Ok(())
+    }
+
+    async fn set_position_mode(&self) -> Result<(), GateError> {
+        ignore_code(self.post_request("/fapi/v1/positionSide/dual", "dualSidePosition=true&").await, ApiCode::PositionModeUnchanged)
+    }
+
+    async fn set_multiassets_mode(&self) -> Result<(), GateError> {
+        ignore_code(self.post_request("/fapi/v1/multiAssetsMargin", "multiAssetsMargin=false&").await, ApiCode::MultiAssetsUnchanged)
+    }
+
+    async fn set_margin_mode(&self, pair: &str) -> Result<(), GateError> {
+        ignore_code(self.post_request("/fapi/v1/marginType", &format!("symbol={}&marginType=CROSSED&", pair)).await, ApiCode::MarginTypeUnchanged)
+    }
+
+    async fn cancel_all_orders(&self, pair: &str) -> Result<(), GateError> {
+        if let Some(request_log) = &self.request_log {
+            let request = BatchRequest { endpoint: "/fapi/v1/allOpenOrders", payload: format!("symbol={}", pair), cost: request_cost(&Method::DELETE, "/fapi/v1/allOpenOrders"), orders: vec![] };
+            self.log_requests(request_log, Method::DELETE, &[request], None);
+            return Ok(())
+        }
+        self.delete_request("/fapi/v1/allOpenOrders", &format!("symbol={}&", pair)).await.map(|_| ())
+    }
+
+    async fn get_open_orders(&self, pair: &str) -> Result<Vec<OrderResponse>, GateError> {
+        parse_response(&self.get_request("/fapi/v1/openOrders", &format!("symbol={}&", pair)).await?)
+    }
+
+    async fn get_order(&self, pair: &str, order_id: u32) -> Result<OrderResponse, GateError> {
+        parse_response(&self.get_request("/fapi/v1/order", &format!("symbol={}&origClientOrderId={}&", pair, order_id)).await?)
+    }
+
+    async fn close_position(&self, pair: &str, is_long: bool, quantity: f64, quantity_decimal_count: i64) -> Result<(), GateError> {
+        let params = format!("symbol={0}&side={1}&positionSide={2}&type=MARKET&quantity={3:.4$}&", pair, if is_long {"SELL"} else {"BUY"}, if is_long {"LONG"} else {"SHORT"}, quantity, quantity_decimal_count as usize);
+        if let Some(request_log) = &self.request_log {
+            let request = BatchRequest { endpoint: "/fapi/v1/order", payload: params.trim_end_matches('&').to_string(), cost: request_cost(&Method::POST, "/fapi/v1/order"), orders: vec![] };
+            self.log_requests(request_log, Method::POST, &[request], None);
+            return Ok(())
+        }
+        parse_response::<OrderResponse>(&self.post_request("/fapi/v1/order", &params).await?).map(|_| ())
+    }
+
+    async fn place_order(&self, pair: &str, order: &Order, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        self.place_orders(pair, vec![order.clone()], price_decimal_count, quantity_decimal_count).await
+    }
+
+    async fn cancel_order(&self, pair: &str, order: &Order) -> bool {
+        self.cancel_orders(pair, vec![order.clone()]).await
+    }
+
+    async fn place_orders(&self, pair: &str, orders: Vec<Order>, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        let requests = self.post_order_requests(pair, orders, price_decimal_count, quantity_decimal_count);
+        if requests.is_empty() {
+            return true
+        }
+        if let Some(request_log) = &self.request_log {
+            self.log_requests(request_log, Method::POST, &requests, Some((price_decimal_count, quantity_decimal_count)));
+            return true
+        }
+        self.parallel_post_requests(requests).await
+    }
+
+    async fn cancel_orders(&self, pair: &str, orders: Vec<Order>) -> bool {
+        let requests = self.cancel_order_requests(pair, orders);
+        if requests.is_empty() {
+            return true
+        }
+        if let Some(request_log) = &self.request_log {
+            self.log_requests(request_log, Method::DELETE, &requests, None);
+            return true
+        }
+        self.parallel_delete_requests(requests).await
+    }
+}
+
+// Request weights of the endpoints the bot calls, closing positions and cancelling everything go first
+fn request_cost(method: &Method, endpoint: &str) -> Cost {
+    match (method.as_str(), endpoint) {
+        ("GET", "/fapi/v2/account") => Cost::new(5, 0, Priority::Normal),
+        ("POST", "/fapi/v1/order") => Cost::new(1, 1, Priority::High),
+        ("DELETE", "/fapi/v1/allOpenOrders") => Cost::new(1, 0, Priority::High),
+        _ => Cost::new(1, 0, Priority::Normal)
+    }
+}
+
+fn get_timestamp() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - 1000
+}
+
+fn chunk_real_orders(orders: Vec<Order>) -> Vec<Vec<Order>> {
+    let real_orders: Vec<Order> = orders.into_iter().filter(|order| order.is_real()).collect();
+    real_orders.chunks(BATCH_SIZE).map(|chunk| chunk.to_vec()).collect()
+}
+
+// HTTP 429 and 418 are rate limits whatever the body says, otherwise a negative code is an API error
+fn check_response(status: StatusCode, resp: String) -> Result<String, GateError> {
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::IM_A_TEAPOT {
+        return Err(GateError::Api(ApiCode::RateLimit, resp))
+    }
+    match serde_json::from_str::<ApiError>(&resp) {
+        Ok(api_error) if api_error.code < 0 => Err(GateError::Api(ApiCode::from_code(api_error.code), api_error.msg)),
+        _ => Ok(resp)
+    }
+}
+
+fn parse_response<T: DeserializeOwned>(resp: &str) -> Result<T, GateError> {
+    serde_json::from_str::<T>(resp).map_err(|error| GateError::Parse(format!("{} in {}", error, resp)))
+}
+
+// Some error codes only mean there was nothing to change
+fn ignore_code(result: Result<String, GateError>, api_code: ApiCode) -> Result<(), GateError> {
+    match result {
+        Err(error) if !error.is_api(api_code) => Err(error),
+        _ => Ok(())
+    }
+}
+
+// A close order rejected with -2022 (ReduceOnly Order is rejected) or an open order rejected with -5022
+// (GTX would take) was only not posted, the grid posts it again on its next pass
+fn is_not_posted(api_code: ApiCode) -> bool {
+    matches!(api_code, ApiCode::ReduceOnlyRejected | ApiCode::GtxRejected)
+}
+
+fn post_response_failed(results: OrderResults) -> bool {
+    results.into_vec().into_iter().any(|result| match result {
+        OrderResult::Order(order) => order.status != OrderStatus::New,
+        OrderResult::Error(api_error) => {
+            if !is_not_posted(ApiCode::from_code(api_error.code)) {
+                println!("\x1b[91mFailed to post order: {}\x1b[0m", api_error.msg);
+                return true
+            }
+            false
+        }
+    })
+}
+
+fn cancel_response_failed(results: OrderResults) -> bool {
+    results.into_vec().into_iter().any(|result| match result {
+        OrderResult::Order(order) => order.status != OrderStatus::Canceled,
+        OrderResult::Error(_) => true
+    })
+}
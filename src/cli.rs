@@ -0,0 +1,95 @@
+use crate::request_log::DEFAULT_REQUEST_LOG_PATH;
+
+pub const USAGE: &str = "Usage: program [command] [--config <path>] [--request-log <path>]
+
+Commands:
+  run                    Trade every grid of the config (default)
+  dry-run                Trade on live data without sending any order or setting, the signed
+                         order requests go to --request-log (ressources/dry_run_requests.jsonl)
+  paper                  Trade on the mainnet market streams against a local matching engine
+  status                 Print the account, positions and open orders of every grid
+  cancel-all             Cancel the open orders of every grid
+  flatten                Cancel the open orders and market close the positions of every grid
+  exchange-info <pair>   Print the trading rules of a pair
+  check-config           Validate the config and exit
+  backtest <file>        Replay a bookTicker file against the first grid
+  mock-server            Only run the local mock exchange
+  keystore <create|rotate> [--keystore <path>]
+  help                   Print this message";
+
+pub enum Command {
+    Run,
+    DryRun,
+    Paper,
+    Status,
+    CancelAll,
+    Flatten,
+    ExchangeInfo(String),
+    CheckConfig,
+    Backtest(String),
+    MockServer,
+    Keystore,
+    Help
+}
+
+impl Command {
+    // The simulated venues and the commands that never reach the account sign nothing
+    pub fn needs_secrets(&self) -> bool {
+        !matches!(self, Command::Paper | Command::Backtest(_) | Command::MockServer | Command::CheckConfig | Command::Help | Command::Keystore)
+    }
+}
+
+// Flags followed by a value, which is not a positional argument
+const VALUE_FLAGS: [&str; 4] = ["--config", "--keystore", "--backtest", "--request-log"];
+
+// The first positional argument is the command, the --backtest, --mock-server and --check-config flags are still accepted
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help)
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--backtest") {
+        let path = args.get(index + 1).filter(|arg| !arg.starts_with("--")).ok_or("--backtest expects the path of a bookTicker file")?;
+        return Ok(Command::Backtest(path.clone()))
+    }
+    if args.iter().any(|arg| arg == "--mock-server") {
+        return Ok(Command::MockServer)
+    }
+    if args.iter().any(|arg| arg == "--check-config") {
+        return Ok(Command::CheckConfig)
+    }
+    let mut positionals: Vec<&str> = vec![];
+    let mut skip_value = false;
+    for arg in args.iter().skip(1) {
+        if skip_value {
+            skip_value = false;
+        } else if arg.starts_with("--") {
+            skip_value = VALUE_FLAGS.contains(&arg.as_str());
+        } else {
+            positionals.push(arg);
+        }
+    }
+    let argument = |name: &str| positionals.get(1).map(|arg| String::from(*arg)).ok_or(format!("{} expects {}", positionals[0], name));
+    match positionals.first().copied().unwrap_or("run") {
+        "run" => Ok(Command::Run),
+        "dry-run" => Ok(Command::DryRun),
+        "paper" => Ok(Command::Paper),
+        "status" => Ok(Command::Status),
+        "cancel-all" => Ok(Command::CancelAll),
+        "flatten" => Ok(Command::Flatten),
+        "exchange-info" => Ok(Command::ExchangeInfo(argument("a pair")?.to_uppercase())),
+        "check-config" => Ok(Command::CheckConfig),
+        "backtest" => Ok(Command::Backtest(argument("the path of a bookTicker file")?)),
+        "mock-server" => Ok(Command::MockServer),
+        "keystore" => Ok(Command::Keystore),
+        "help" => Ok(Command::Help),
+        command => Err(format!("Unknown command {}", command))
+    }
+}
+
+// Where the dry run writes the order requests it would have sent
+pub fn request_log_path(args: &[String]) -> String {
+    match args.iter().position(|arg| arg == "--request-log") {
+        Some(index) => args.get(index + 1).cloned().unwrap_or_else(|| String::from(DEFAULT_REQUEST_LOG_PATH)),
+        None => String::from(DEFAULT_REQUEST_LOG_PATH)
+    }
+}
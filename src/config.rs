@@ -0,0 +1,537 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::funding::FundingSettings;
+use crate::grid::{CloseLevel, QuantityMode, QuantityProfile, Spacing};
+use crate::inventory::InventorySettings;
+use crate::margin::LiquidationSettings;
+use crate::risk::RiskSettings;
+use crate::mock_server::MockSettings;
+use crate::rate_limiter::RateLimitSettings;
+use crate::retry::RetrySettings;
+use crate::secrets::SecretsSettings;
+use crate::simulator::SimulatorConfig;
+
+pub const DEFAULT_CONFIG_PATH: &str = "ressources/config.json";
+pub const CONFIG_ENV: &str = "GRID_CONFIG";
+// Binance USDⓈ-M futures never allow more than this on any symbol
+const MAX_LEVERAGE: u64 = 125;
+
+// What happens to the positions once the grid is cancelled on shutdown
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownMode {
+    Leave,
+    Close,
+    Flatten
+}
+
+// One grid once the "grids" entry is merged over the top level and the defaults are applied
+#[derive(Clone)]
+pub struct GridConfig {
+    pub pair: String,
+    // Multiplier set on the symbol at startup
+    pub leverage: u64,
+    pub close_only: bool,
+    // margin, increment and close_diff are in quote currency with fixed and atr spacing,
+    // and in percent of the market price with percentage and geometric spacing
+    pub spacing: Spacing,
+    // Distance between the market price and the first open level
+    pub margin: f64,
+    // Distance between two open levels before acceleration. Geometric spacing keeps the ratio
+    // between the levels instead of the distance, atr spacing uses it as a floor.
+    pub increment: f64,
+    // Growth of the increment per level, 0.1 widens each level by 10%
+    pub acceleration: f64,
+    // Distance between the entry price and the nearest close level
+    pub close_diff: f64,
+    // Take-profit ladder of each side, nearest first, a single level of the whole position by default
+    pub close_levels: Vec<CloseLevel>,
+    // Bars averaged by the atr spacing, their length in seconds, and the multiple of the average range between two levels
+    pub atr_period: u64,
+    pub atr_interval: u64,
+    pub atr_multiplier: f64,
+    // Open levels on each side
+    pub order_amount: u64,
+    pub quantity_mode: QuantityMode,
+    // Quantity of one level, base asset or quote currency depending on quantity_mode
+    pub order_quantity: f64,
+    // Pyramid mode, each level further adds this times order_quantity
+    pub pyramid_factor: f64,
+    // Multiplier of order_quantity per level of each open ladder, quantity_profile sets both
+    pub long_quantity_profile: QuantityProfile,
+    pub short_quantity_profile: QuantityProfile,
+    // Base asset, the grid stops opening past this position on a side
+    pub max_position: f64,
+    // Base asset, the increment starts widening past this position on a side
+    pub soft_position: f64,
+    // Seconds between two order reconciliations, 0 disables them
+    pub reconcile_interval: u64,
+    pub shutdown: ShutdownMode,
+    // Drawdown, daily loss and exposure limits, see RiskSettings
+    pub risk: RiskSettings,
+    // Margin ratios where the open ladders shrink and the grid goes close-only
+    pub liquidation: LiquidationSettings,
+    // Skew and trim of the side paying the coming funding settlement
+    pub funding: FundingSettings,
+    // Shift and reshaping of the open ladders by the net position
+    pub inventory_skew: InventorySettings,
+    pub journal: String,
+    pub stats_file: String,
+    // Days covered by the rolling stats
+    pub stats_window: u64
+}
+
+pub struct Config {
+    pub testnet: bool,
+    pub encrypted_key: Option<String>,
+    pub encrypted_secret: Option<String>,
+    pub encrypted_token: Option<String>,
+    pub encrypted_channel: Option<String>,
+    pub secrets: SecretsSettings,
+    pub mock_server: Option<MockSettings>,
+    pub backtest: SimulatorConfig,
+    // Wallet and fees of the paper trading engines, the filters come from the real symbols
+    pub paper: SimulatorConfig,
+    pub retry: RetrySettings,
+    pub rate_limit: RateLimitSettings,
+    pub grids: Vec<GridConfig>
+}
+
+// The grid keys as written at the top level or in a "grids" entry, anything else ends in unknown
+#[derive(Clone, Default, Deserialize)]
+struct GridFile {
+    pair: Option<String>,
+    leverage: Option<u64>,
+    close_only: Option<bool>,
+    spacing: Option<Spacing>,
+    margin: Option<f64>,
+    increment: Option<f64>,
+    acceleration: Option<f64>,
+    close_diff: Option<f64>,
+    close_levels: Option<Vec<CloseLevel>>,
+    atr_period: Option<u64>,
+    atr_interval: Option<u64>,
+    atr_multiplier: Option<f64>,
+    order_amount: Option<u64>,
+    quantity_mode: Option<QuantityMode>,
+    order_quantity: Option<f64>,
+    pyramid_factor: Option<f64>,
+    quantity_profile: Option<QuantityProfile>,
+    long_quantity_profile: Option<QuantityProfile>,
+    short_quantity_profile: Option<QuantityProfile>,
+    max_position: Option<f64>,
+    soft_position: Option<f64>,
+    reconcile_interval: Option<u64>,
+    shutdown: Option<ShutdownMode>,
+    risk: Option<RiskSettings>,
+    liquidation: Option<LiquidationSettings>,
+    funding: Option<FundingSettings>,
+    inventory_skew: Option<InventorySettings>,
+    journal: Option<String>,
+    stats_file: Option<String>,
+    stats_window: Option<u64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    testnet: bool,
+    #[serde(default)]
+    encrypted_key: Option<String>,
+    #[serde(default)]
+    encrypted_secret: Option<String>,
+    #[serde(default)]
+    encrypted_token: Option<String>,
+    #[serde(default)]
+    encrypted_channel: Option<String>,
+    #[serde(default)]
+    secrets: SecretsSettings,
+    #[serde(default)]
+    mock_server: Option<MockSettings>,
+    #[serde(default)]
+    backtest: SimulatorConfig,
+    #[serde(default)]
+    paper: SimulatorConfig,
+    #[serde(default)]
+    retry: RetrySettings,
+    #[serde(default)]
+    rate_limit: RateLimitSettings,
+    #[serde(default)]
+    grids: Option<Vec<GridFile>>,
+    #[serde(flatten)]
+    defaults: GridFile
+}
+
+// --config <path> first, then the environment, then the default path
+pub fn config_path(args: &[String]) -> Result<String, String> {
+    if let Some(index) = args.iter().position(|arg| arg == "--config") {
+        return args.get(index + 1).cloned().ok_or_else(|| String::from("--config expects the path of a config file"))
+    }
+    Ok(std::env::var(CONFIG_ENV).unwrap_or_else(|_| String::from(DEFAULT_CONFIG_PATH)))
+}
+
+// Reads and validates the whole config, every problem found is returned at once
+pub fn load(path: &str) -> Result<Config, Vec<String>> {
+    let config_str = std::fs::read_to_string(path).map_err(|error| vec![format!("Cannot read {}: {}", path, error)])?;
+    parse(path, &config_str)
+}
+
+// path only names the config in the errors
+pub fn parse(path: &str, config_str: &str) -> Result<Config, Vec<String>> {
+    let file: ConfigFile = serde_json::from_str(config_str).map_err(|error| vec![format!("Invalid {}: {}", path, error)])?;
+    let mut errors: Vec<String> = vec![];
+
+    check_unknown_keys(&file.defaults.unknown, "", &mut errors);
+    if let Some(mock_server) = &file.mock_server {
+        check_unknown_keys(&mock_server.simulator.unknown, "mock_server.", &mut errors);
+    }
+    check_unknown_keys(&file.backtest.unknown, "backtest.", &mut errors);
+    check_unknown_keys(&file.paper.unknown, "paper.", &mut errors);
+
+    let grids: Vec<GridConfig> = match &file.grids {
+        Some(grid_files) => grid_files.iter().enumerate().filter_map(|(index, grid_file)| {
+            let location = format!("grids[{}].", index);
+            check_unknown_keys(&grid_file.unknown, &location, &mut errors);
+            let mut grid = resolve(grid_file, &file.defaults, &location, &mut errors)?;
+            // Each grid keeps its own stats checkpoint
+            if grid_file.stats_file.is_none() {
+                grid.stats_file = format!("ressources/stats_{}.json", grid.pair);
+            }
+            Some(grid)
+        }).collect(),
+        None => resolve(&GridFile::default(), &file.defaults, "", &mut errors).into_iter().collect()
+    };
+
+    if file.grids.as_ref().is_some_and(|grid_files| grid_files.is_empty()) {
+        errors.push(String::from("grids: at least one grid is required"));
+    }
+    let mut pairs: Vec<&str> = grids.iter().map(|grid| grid.pair.as_str()).collect();
+    pairs.sort_unstable();
+    if let Some(window) = pairs.windows(2).find(|window| window[0] == window[1]) {
+        errors.push(format!("grids: pair {} is used by more than one grid", window[0]));
+    }
+    for (index, grid) in grids.iter().enumerate() {
+        let location = if file.grids.is_some() { format!("grids[{}].", index) } else { String::new() };
+        validate_grid(grid, &location, &mut errors);
+    }
+    if !(0.0..1.0).contains(&file.rate_limit.reserve) {
+        errors.push(format!("rate_limit.reserve: {} must be at least 0 and below 1", file.rate_limit.reserve));
+    }
+    if file.secrets.file.is_some() && file.secrets.keystore.is_some() {
+        errors.push(String::from("secrets: set either file or keystore, not both"));
+    }
+    if let Some(mock_server) = &file.mock_server {
+        if mock_server.tick_interval == 0 {
+            errors.push(String::from("mock_server.tick_interval: must be above 0"));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+    Ok(Config {
+        testnet: file.testnet,
+        encrypted_key: file.encrypted_key,
+        encrypted_secret: file.encrypted_secret,
+        encrypted_token: file.encrypted_token,
+        encrypted_channel: file.encrypted_channel,
+        secrets: file.secrets,
+        mock_server: file.mock_server,
+        backtest: file.backtest,
+        paper: file.paper,
+        retry: file.retry,
+        rate_limit: file.rate_limit,
+        grids
+    })
+}
+
+// Warnings do not stop the bot, they point at settings that are valid but probably not intended
+pub fn warnings(config: &Config) -> Vec<String> {
+    let mut warnings: Vec<String> = vec![];
+    for grid in config.grids.iter().filter(|grid| grid.quantity_mode != QuantityMode::Quote) {
+        for (profile, side) in [(&grid.long_quantity_profile, "long"), (&grid.short_quantity_profile, "short")] {
+            let ladder: f64 = (0..grid.order_amount as usize).map(|level| grid.order_quantity * profile.multiplier(level)).sum();
+            if ladder > grid.max_position {
+                warnings.push(format!("{}: the {} ladder adds up to {} but max_position is {}, the farthest levels are never posted", grid.pair, side, ladder, grid.max_position));
+            }
+        }
+    }
+    warnings
+}
+
+// The grid against the trading rules of its pair from the exchange info
+pub fn check_symbol(grid: &GridConfig, tick_size: f64, min_quantity: f64, max_quantity: f64, max_order_amount: u64) -> Vec<String> {
+    let mut errors: Vec<String> = vec![];
+    if grid.quantity_mode != QuantityMode::Quote && (grid.order_quantity < min_quantity || grid.order_quantity > max_quantity) {
+        errors.push(format!("{}: order_quantity {} must be between the min {} and the max {} quantity of the pair", grid.pair, grid.order_quantity, min_quantity, max_quantity));
+    }
+    // The scaled spacings are rounded to the tick size instead
+    if matches!(grid.spacing, Spacing::Fixed | Spacing::Atr) && grid.increment <= tick_size {
+        errors.push(format!("{}: increment {} must be above the tick size {} of the pair", grid.pair, grid.increment, tick_size));
+    }
+    if grid.order_amount as f64 > max_order_amount as f64 / 4.0 {
+        errors.push(format!("{}: order_amount {} must be at most a quarter of the {} open orders allowed on the pair", grid.pair, grid.order_amount, max_order_amount));
+    }
+    errors
+}
+
+fn check_unknown_keys(unknown: &BTreeMap<String, Value>, location: &str, errors: &mut Vec<String>) {
+    for key in unknown.keys() {
+        errors.push(format!("{}{}: unknown key", location, key));
+    }
+}
+
+// The "grids" entry wins over the top level, the required keys have no default
+fn resolve(grid_file: &GridFile, defaults: &GridFile, location: &str, errors: &mut Vec<String>) -> Option<GridConfig> {
+    let missing_count = errors.len();
+    let mut required = |value: Option<f64>, key: &str| -> f64 {
+        value.unwrap_or_else(|| {
+            errors.push(format!("{}{}: missing key", location, key));
+            0.0
+        })
+    };
+    let margin = required(grid_file.margin.or(defaults.margin), "margin");
+    let increment = required(grid_file.increment.or(defaults.increment), "increment");
+    let acceleration = required(grid_file.acceleration.or(defaults.acceleration), "acceleration");
+    let close_diff = required(grid_file.close_diff.or(defaults.close_diff), "close_diff");
+    let order_quantity = required(grid_file.order_quantity.or(defaults.order_quantity), "order_quantity");
+    let max_position = required(grid_file.max_position.or(defaults.max_position), "max_position");
+    let soft_position = required(grid_file.soft_position.or(defaults.soft_position), "soft_position");
+    let pair = grid_file.pair.clone().or_else(|| defaults.pair.clone());
+    let leverage = grid_file.leverage.or(defaults.leverage);
+    let close_only = grid_file.close_only.or(defaults.close_only);
+    let order_amount = grid_file.order_amount.or(defaults.order_amount);
+    let quantity_mode = grid_file.quantity_mode.or(defaults.quantity_mode).unwrap_or(QuantityMode::Base);
+    let pyramid_factor = grid_file.pyramid_factor.or(defaults.pyramid_factor).unwrap_or(1.0);
+    let quantity_profile = grid_file.quantity_profile.clone().or_else(|| defaults.quantity_profile.clone());
+    if quantity_mode == QuantityMode::Pyramid && quantity_profile.is_some() {
+        errors.push(format!("{}quantity_profile: cannot be combined with the pyramid quantity_mode, use base with a linear profile", location));
+    }
+    let quantity_profile = quantity_profile.unwrap_or(match quantity_mode {
+        QuantityMode::Pyramid => QuantityProfile::Linear { step: pyramid_factor },
+        _ => QuantityProfile::Flat
+    });
+    for (value, key) in [(pair.is_some(), "pair"), (leverage.is_some(), "leverage"), (close_only.is_some(), "close_only"), (order_amount.is_some(), "order_amount")] {
+        if !value {
+            errors.push(format!("{}{}: missing key", location, key));
+        }
+    }
+    if errors.len() > missing_count {
+        return None
+    }
+    Some(GridConfig {
+        pair: pair.unwrap(),
+        leverage: leverage.unwrap(),
+        close_only: close_only.unwrap(),
+        spacing: grid_file.spacing.or(defaults.spacing).unwrap_or(Spacing::Fixed),
+        margin,
+        increment,
+        acceleration,
+        close_diff,
+        close_levels: grid_file.close_levels.clone().or_else(|| defaults.close_levels.clone()).unwrap_or_else(CloseLevel::single),
+        atr_period: grid_file.atr_period.or(defaults.atr_period).unwrap_or(14),
+        atr_interval: grid_file.atr_interval.or(defaults.atr_interval).unwrap_or(60),
+        atr_multiplier: grid_file.atr_multiplier.or(defaults.atr_multiplier).unwrap_or(1.0),
+        order_amount: order_amount.unwrap(),
+        quantity_mode,
+        order_quantity,
+        pyramid_factor,
+        long_quantity_profile: grid_file.long_quantity_profile.clone().or_else(|| defaults.long_quantity_profile.clone()).unwrap_or_else(|| quantity_profile.clone()),
+        short_quantity_profile: grid_file.short_quantity_profile.clone().or_else(|| defaults.short_quantity_profile.clone()).unwrap_or(quantity_profile),
+        max_position,
+        soft_position,
+        reconcile_interval: grid_file.reconcile_interval.or(defaults.reconcile_interval).unwrap_or(60),
+        shutdown: grid_file.shutdown.or(defaults.shutdown).unwrap_or(ShutdownMode::Leave),
+        risk: grid_file.risk.clone().or_else(|| defaults.risk.clone()).unwrap_or_default(),
+        liquidation: grid_file.liquidation.clone().or_else(|| defaults.liquidation.clone()).unwrap_or_default(),
+        funding: grid_file.funding.clone().or_else(|| defaults.funding.clone()).unwrap_or_default(),
+        inventory_skew: grid_file.inventory_skew.clone().or_else(|| defaults.inventory_skew.clone()).unwrap_or_default(),
+        journal: grid_file.journal.clone().or_else(|| defaults.journal.clone()).unwrap_or_else(|| String::from("ressources/journal.jsonl")),
+        stats_file: grid_file.stats_file.clone().or_else(|| defaults.stats_file.clone()).unwrap_or_else(|| String::from("ressources/stats.json")),
+        stats_window: grid_file.stats_window.or(defaults.stats_window).unwrap_or(30)
+    })
+}
+
+// Cross-field checks, the ones needing the symbol filters are done on the exchange info
+fn validate_grid(grid: &GridConfig, location: &str, errors: &mut Vec<String>) {
+    let mut check = |valid: bool, key: &str, message: String| {
+        if !valid {
+            errors.push(format!("{}{}: {}", location, key, message));
+        }
+    };
+    check(!grid.pair.is_empty(), "pair", String::from("must not be empty"));
+    check((1..=MAX_LEVERAGE).contains(&grid.leverage), "leverage", format!("{} must be between 1 and {}", grid.leverage, MAX_LEVERAGE));
+    check(grid.margin >= 0.0, "margin", format!("{} must not be negative", grid.margin));
+    check(grid.increment > 0.0, "increment", format!("{} must be above 0", grid.increment));
+    check(grid.acceleration >= 0.0, "acceleration", format!("{} must not be negative", grid.acceleration));
+    check(grid.close_diff >= 0.0, "close_diff", format!("{} must not be negative", grid.close_diff));
+    check(!grid.close_levels.is_empty(), "close_levels", String::from("needs at least one level"));
+    for (i, level) in grid.close_levels.iter().enumerate() {
+        check(level.offset >= 0.0, &format!("close_levels[{}].offset", i), format!("{} must not be negative", level.offset));
+        check(level.weight > 0.0, &format!("close_levels[{}].weight", i), format!("{} must be above 0", level.weight));
+        if i > 0 {
+            check(level.offset > grid.close_levels[i - 1].offset, &format!("close_levels[{}].offset", i), String::from("must be above the offset of the previous level"));
+        }
+    }
+    check(grid.order_amount >= 1, "order_amount", String::from("must be at least 1"));
+    check(grid.order_quantity > 0.0, "order_quantity", format!("{} must be above 0", grid.order_quantity));
+    check(grid.max_position > 0.0, "max_position", format!("{} must be above 0", grid.max_position));
+    // A notional can only be compared to max_position once the price is known
+    if grid.quantity_mode != QuantityMode::Quote {
+        check(grid.order_quantity <= grid.max_position, "order_quantity", format!("{} is above max_position {}, no level could ever be posted", grid.order_quantity, grid.max_position));
+    }
+    check(grid.pyramid_factor >= 0.0, "pyramid_factor", format!("{} must not be negative", grid.pyramid_factor));
+    for (profile, key) in [(&grid.long_quantity_profile, "long_quantity_profile"), (&grid.short_quantity_profile, "short_quantity_profile")] {
+        if let Err(error) = profile.validate() {
+            check(false, key, error);
+        }
+    }
+    if matches!(grid.spacing, Spacing::Percentage | Spacing::Geometric) {
+        for (value, key) in [(grid.margin, "margin"), (grid.increment, "increment"), (grid.close_diff, "close_diff")] {
+            check(value < 100.0, key, format!("{} must be below 100 percent", value));
+        }
+        if let Some(farthest) = grid.close_levels.last() {
+            check(grid.close_diff + farthest.offset < 100.0, "close_levels", format!("close_diff plus the last offset is {}, it must be below 100 percent", grid.close_diff + farthest.offset));
+        }
+    }
+    if grid.spacing == Spacing::Atr {
+        check(grid.atr_period >= 1, "atr_period", String::from("must be at least 1 bar"));
+        check(grid.atr_interval >= 1, "atr_interval", String::from("must be at least 1 second"));
+        check(grid.atr_multiplier > 0.0, "atr_multiplier", format!("{} must be above 0", grid.atr_multiplier));
+    }
+    check(grid.soft_position >= 0.0, "soft_position", format!("{} must not be negative", grid.soft_position));
+    check(grid.soft_position < grid.max_position, "soft_position", format!("{} must be below max_position {}", grid.soft_position, grid.max_position));
+    check(grid.stats_window >= 1, "stats_window", String::from("must be at least 1 day"));
+    for (limit, key) in [(grid.risk.max_drawdown, "risk.max_drawdown"), (grid.risk.max_daily_loss, "risk.max_daily_loss"), (grid.risk.max_net_notional, "risk.max_net_notional")] {
+        if let Some(limit) = limit {
+            check(limit > 0.0, key, format!("{} must be above 0", limit));
+        }
+    }
+    check(!grid.risk.escalation.is_empty(), "risk.escalation", String::from("needs at least one action"));
+    check(grid.liquidation.reduce_ratio > 0.0, "liquidation.reduce_ratio", format!("{} must be above 0", grid.liquidation.reduce_ratio));
+    check(grid.liquidation.close_only_ratio <= 1.0, "liquidation.close_only_ratio", format!("{} must not be above 1, the exchange liquidates there", grid.liquidation.close_only_ratio));
+    check(grid.liquidation.reduce_ratio < grid.liquidation.close_only_ratio, "liquidation.reduce_ratio", format!("{} must be below close_only_ratio {}", grid.liquidation.reduce_ratio, grid.liquidation.close_only_ratio));
+    check((0.0..1.0).contains(&grid.funding.skew), "funding.skew", format!("{} must be at least 0 and below 1", grid.funding.skew));
+    check(grid.funding.min_rate >= 0.0, "funding.min_rate", format!("{} must not be negative", grid.funding.min_rate));
+    check(grid.inventory_skew.shift >= 0.0, "inventory_skew.shift", format!("{} must not be negative", grid.inventory_skew.shift));
+    check((0.0..1.0).contains(&grid.inventory_skew.quantity), "inventory_skew.quantity", format!("{} must be at least 0 and below 1", grid.inventory_skew.quantity));
+    if let Some(max_net) = grid.inventory_skew.max_net {
+        check(max_net > 0.0, "inventory_skew.max_net", format!("{} must be above 0", max_net));
+    }
+    check_unknown_keys(&grid.risk.unknown, &format!("{}risk.", location), errors);
+    check_unknown_keys(&grid.liquidation.unknown, &format!("{}liquidation.", location), errors);
+    check_unknown_keys(&grid.funding.unknown, &format!("{}funding.", location), errors);
+    check_unknown_keys(&grid.inventory_skew.unknown, &format!("{}inventory_skew.", location), errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"{
+        "testnet": true,
+        "pair": "BTCUSDT",
+        "leverage": 20,
+        "close_only": false,
+        "margin": 1,
+        "increment": 1,
+        "acceleration": 0.1,
+        "close_diff": 6,
+        "order_amount": 20,
+        "order_quantity": 0.002,
+        "max_position": 2.0,
+        "soft_position": 0.75
+    }"#;
+
+    // The minimal config with some keys replaced or added
+    fn parse_with(changes: &[(&str, Value)]) -> Result<Config, Vec<String>> {
+        let mut config: Value = serde_json::from_str(MINIMAL).unwrap();
+        for (key, value) in changes.iter() {
+            config[*key] = value.clone();
+        }
+        parse("test.json", &config.to_string())
+    }
+
+    fn errors_of(changes: &[(&str, Value)]) -> Vec<String> {
+        match parse_with(changes) {
+            Ok(_) => vec![],
+            Err(errors) => errors
+        }
+    }
+
+    #[test]
+    fn minimal_config_gets_the_defaults() {
+        let config = parse_with(&[]).unwrap_or_else(|errors| panic!("{:?}", errors));
+        assert_eq!(config.grids.len(), 1);
+        let grid = &config.grids[0];
+        assert_eq!(grid.pair, "BTCUSDT");
+        assert!(grid.spacing == Spacing::Fixed);
+        assert!(grid.quantity_mode == QuantityMode::Base);
+        assert_eq!(grid.reconcile_interval, 60);
+        assert_eq!(grid.stats_file, "ressources/stats.json");
+        assert!(grid.close_levels == CloseLevel::single());
+    }
+
+    #[test]
+    fn missing_keys_are_all_reported() {
+        let mut config: Value = serde_json::from_str(MINIMAL).unwrap();
+        config.as_object_mut().unwrap().remove("margin");
+        config.as_object_mut().unwrap().remove("leverage");
+        let errors = parse("test.json", &config.to_string()).err().unwrap();
+        assert!(errors.contains(&String::from("margin: missing key")));
+        assert!(errors.contains(&String::from("leverage: missing key")));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert_eq!(errors_of(&[("incremnet", Value::from(1))]), vec![String::from("incremnet: unknown key")]);
+    }
+
+    #[test]
+    fn cross_field_checks() {
+        assert_eq!(errors_of(&[("leverage", Value::from(0))]), vec![String::from("leverage: 0 must be between 1 and 125")]);
+        assert_eq!(errors_of(&[("soft_position", Value::from(3.0))]), vec![String::from("soft_position: 3 must be below max_position 2")]);
+        assert_eq!(errors_of(&[("order_quantity", Value::from(5.0))]), vec![String::from("order_quantity: 5 is above max_position 2, no level could ever be posted")]);
+        assert_eq!(errors_of(&[("spacing", Value::from("percentage")), ("increment", Value::from(100))]), vec![String::from("increment: 100 must be below 100 percent")]);
+    }
+
+    #[test]
+    fn grids_are_located_in_the_errors() {
+        let grids = serde_json::json!([{ "pair": "ETHUSDT" }, { "pair": "BTCUSDT", "leverage": 200 }]);
+        assert_eq!(errors_of(&[("grids", grids)]), vec![String::from("grids[1].leverage: 200 must be between 1 and 125")]);
+        let grids = serde_json::json!([{ "pair": "ETHUSDT" }, { "pair": "ETHUSDT" }]);
+        assert_eq!(errors_of(&[("grids", grids)]), vec![String::from("grids: pair ETHUSDT is used by more than one grid")]);
+    }
+
+    #[test]
+    fn each_grid_keeps_its_own_stats_file() {
+        let grids = serde_json::json!([{ "pair": "ETHUSDT" }, { "pair": "BTCUSDT", "stats_file": "btc.json" }]);
+        let config = parse_with(&[("grids", grids)]).unwrap_or_else(|errors| panic!("{:?}", errors));
+        assert_eq!(config.grids[0].stats_file, "ressources/stats_ETHUSDT.json");
+        assert_eq!(config.grids[1].stats_file, "btc.json");
+    }
+
+    #[test]
+    fn config_path_needs_a_value() {
+        let args = |args: &[&str]| args.iter().map(|arg| String::from(*arg)).collect::<Vec<String>>();
+        assert_eq!(config_path(&args(&["program", "run", "--config", "a.json"])), Ok(String::from("a.json")));
+        assert!(config_path(&args(&["program", "run", "--config"])).is_err());
+    }
+
+    #[test]
+    fn symbol_checks() {
+        let grid = parse_with(&[]).unwrap_or_else(|errors| panic!("{:?}", errors)).grids.remove(0);
+        assert!(check_symbol(&grid, 0.1, 0.001, 1000.0, 200).is_empty());
+        assert_eq!(check_symbol(&grid, 1.0, 0.001, 1000.0, 200), vec![String::from("BTCUSDT: increment 1 must be above the tick size 1 of the pair")]);
+        assert_eq!(check_symbol(&grid, 0.1, 0.01, 1000.0, 200), vec![String::from("BTCUSDT: order_quantity 0.002 must be between the min 0.01 and the max 1000 quantity of the pair")]);
+        assert_eq!(check_symbol(&grid, 0.1, 0.001, 1000.0, 40), vec![String::from("BTCUSDT: order_amount 20 must be at most a quarter of the 40 open orders allowed on the pair")]);
+    }
+
+    #[test]
+    fn symbol_checks_skip_what_the_spacing_and_mode_round_away() {
+        let grid = parse_with(&[("spacing", Value::from("percentage")), ("increment", Value::from(0.05)), ("quantity_mode", Value::from("quote")), ("order_quantity", Value::from(0.5))]).unwrap_or_else(|errors| panic!("{:?}", errors)).grids.remove(0);
+        assert!(check_symbol(&grid, 0.1, 0.001, 0.002, 200).is_empty());
+    }
+}
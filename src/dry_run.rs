@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::binance::BinanceExchange;
+use crate::error::{ApiCode, GateError};
+use crate::exchange::Exchange;
+use crate::models::{AccountInfo, ExchangeInfo, Income, LeverageBracket, OrderResponse, OrderStatus, PremiumIndex};
+use crate::order_manager::Order;
+use crate::request_log::RequestLog;
+
+// Reads go to the real exchange, every request that would change the account is only printed
+// and treated as accepted. The accepted orders are kept so the reconciler finds them again.
+// Order requests are still built and signed by the inner exchange, which logs them instead of sending.
+pub struct DryRunExchange {
+    inner: BinanceExchange,
+    resting_orders: Mutex<HashMap<String, Vec<Order>>>
+}
+
+impl DryRunExchange {
+    pub fn new(inner: BinanceExchange, request_log: RequestLog) -> Self {
+        println!("\x1b[94mDry run: order requests are logged to {}\x1b[0m", request_log.get_path());
+        DryRunExchange {
+            inner: inner.with_request_log(request_log),
+            resting_orders: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn accept(&self, pair: &str, orders: Vec<Order>) {
+        let resting_orders = &mut self.resting_orders.lock().unwrap();
+        let pair_orders = resting_orders.entry(String::from(pair)).or_default();
+        for order in orders.into_iter().filter(|order| order.is_real()) {
+            pair_orders.retain(|resting| resting.get_id() != order.get_id());
+            pair_orders.push(order);
+        }
+    }
+
+    fn remove(&self, pair: &str, orders: &[Order]) {
+        if let Some(pair_orders) = self.resting_orders.lock().unwrap().get_mut(pair) {
+            pair_orders.retain(|resting| orders.iter().all(|order| order.get_id() != resting.get_id()));
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for DryRunExchange {
+    async fn get_exchange_info(&self) -> Result<ExchangeInfo, GateError> {
+        self.inner.get_exchange_info().await
+    }
+
+    async fn get_account_info(&self) -> Result<AccountInfo, GateError> {
+        self.inner.get_account_info().await
+    }
+
+    async fn get_leverage_brackets(&self, pair: &str) -> Result<Vec<LeverageBracket>, GateError> {
+        self.inner.get_leverage_brackets(pair).await
+    }
+
+    async fn get_premium_index(&self, pair: &str) -> Result<PremiumIndex, GateError> {
+        self.inner.get_premium_index(pair).await
+    }
+
+    async fn get_funding_income(&self, pair: &str, start_time: u64) -> Result<Vec<Income>, GateError> {
+        self.inner.get_funding_income(pair, start_time).await
+    }
+
+    async fn get_listen_key(&self) -> Result<String, GateError> {
+        self.inner.get_listen_key().await
+    }
+
+    async fn keepalive_listen_key(&self) -> Result<(), GateError> {
+        self.inner.keepalive_listen_key().await
+    }
+
+    async fn set_leverage(&self, pair: &str, leverage: u64) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: set leverage of {} to {}\x1b[0m", pair, leverage);
+        Ok(())
+    }
+
+    async fn set_position_mode(&self) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: set hedge position mode\x1b[0m");
+        Ok(())
+    }
+
+    async fn set_multiassets_mode(&self) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: disable multi-assets mode\x1b[0m");
+        Ok(())
+    }
+
+    async fn set_margin_mode(&self, pair: &str) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: set cross margin on {}\x1b[0m", pair);
+        Ok(())
+    }
+
+    async fn cancel_all_orders(&self, pair: &str) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: cancel all orders on {}\x1b[0m", pair);
+        self.resting_orders.lock().unwrap().remove(pair);
+        self.inner.cancel_all_orders(pair).await
+    }
+
+    async fn get_open_orders(&self, pair: &str) -> Result<Vec<OrderResponse>, GateError> {
+        let resting_orders = self.resting_orders.lock().unwrap();
+        Ok(resting_orders.get(pair).map(|orders| orders.iter().map(|order| OrderResponse {
+            symbol: String::from(pair),
+            status: OrderStatus::New,
+            client_order_id: order.get_id().to_string(),
+            price: order.get_price(),
+            orig_qty: order.get_quantity(),
+            executed_qty: 0.0,
+            avg_price: 0.0,
+            update_time: 0,
+            side: order.get_side(),
+            position_side: order.get_position_side()
+        }).collect()).unwrap_or_default())
+    }
+
+    // Only the accepted orders are known, nothing is ever filled
+    async fn get_order(&self, pair: &str, order_id: u32) -> Result<OrderResponse, GateError> {
+        let client_order_id = order_id.to_string();
+        self.get_open_orders(pair).await?.into_iter().find(|order| order.client_order_id == client_order_id)
+            .ok_or_else(|| GateError::Api(ApiCode::OrderNotFound, String::from("Order does not exist.")))
+    }
+
+    async fn close_position(&self, pair: &str, is_long: bool, quantity: f64, quantity_decimal_count: i64) -> Result<(), GateError> {
+        println!("\x1b[94mDry run: market close {0:.1$} {2} {3}\x1b[0m", quantity, quantity_decimal_count as usize, pair, if is_long { "long" } else { "short" });
+        self.inner.close_position(pair, is_long, quantity, quantity_decimal_count).await
+    }
+
+    async fn place_order(&self, pair: &str, order: &Order, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        self.place_orders(pair, vec![order.clone()], price_decimal_count, quantity_decimal_count).await
+    }
+
+    async fn cancel_order(&self, pair: &str, order: &Order) -> bool {
+        self.cancel_orders(pair, vec![order.clone()]).await
+    }
+
+    async fn place_orders(&self, pair: &str, orders: Vec<Order>, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        for order in orders.iter().filter(|order| order.is_real()) {
+            println!("\x1b[94mDry run: post {0} {1} {2:.3$} {4} at {5:.6$}\x1b[0m", if order.is_open() { "open" } else { "close" }, if order.is_long() { "long" } else { "short" }, order.get_quantity(), quantity_decimal_count as usize, pair, order.get_price(), price_decimal_count as usize);
+        }
+        self.accept(pair, orders.clone());
+        self.inner.place_orders(pair, orders, price_decimal_count, quantity_decimal_count).await
+    }
+
+    async fn cancel_orders(&self, pair: &str, orders: Vec<Order>) -> bool {
+        let real_orders: Vec<Order> = orders.into_iter().filter(|order| order.is_real()).collect();
+        if !real_orders.is_empty() {
+            println!("\x1b[94mDry run: cancel {} order(s) on {}\x1b[0m", real_orders.len(), pair);
+        }
+        self.remove(pair, &real_orders);
+        self.inner.cancel_orders(pair, real_orders).await
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+// Binance error codes the bot reacts to, everything else is kept as Other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiCode {
+    // -1003 too many requests, -1015 too many orders, or an HTTP 429/418
+    RateLimit,
+    // -1021 timestamp outside of the recvWindow
+    Timestamp,
+    // -2013 Order does not exist
+    OrderNotFound,
+    // -2022 ReduceOnly Order is rejected
+    ReduceOnlyRejected,
+    // -4046 no need to change margin type
+    MarginTypeUnchanged,
+    // -4059 no need to change position side
+    PositionModeUnchanged,
+    // -4171 multi-assets mode already set
+    MultiAssetsUnchanged,
+    // -5022 GTX order would immediately match and take
+    GtxRejected,
+    Other(i64)
+}
+
+impl ApiCode {
+    pub fn from_code(code: i64) -> Self {
+        match code {
+            -1003 | -1015 => ApiCode::RateLimit,
+            -1021 => ApiCode::Timestamp,
+            -2013 => ApiCode::OrderNotFound,
+            -2022 => ApiCode::ReduceOnlyRejected,
+            -4046 => ApiCode::MarginTypeUnchanged,
+            -4059 => ApiCode::PositionModeUnchanged,
+            -4171 => ApiCode::MultiAssetsUnchanged,
+            -5022 => ApiCode::GtxRejected,
+            _ => ApiCode::Other(code)
+        }
+    }
+
+    pub fn code(&self) -> i64 {
+        match self {
+            ApiCode::RateLimit => -1003,
+            ApiCode::Timestamp => -1021,
+            ApiCode::OrderNotFound => -2013,
+            ApiCode::ReduceOnlyRejected => -2022,
+            ApiCode::MarginTypeUnchanged => -4046,
+            ApiCode::PositionModeUnchanged => -4059,
+            ApiCode::MultiAssetsUnchanged => -4171,
+            ApiCode::GtxRejected => -5022,
+            ApiCode::Other(code) => *code
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GateError {
+    // The request could not be sent or its body could not be read
+    Network(String),
+    // Binance answered with an error code
+    Api(ApiCode, String),
+    // The response did not have the expected shape
+    Parse(String)
+}
+
+// Errors sharing a class share a retry policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    Network,
+    RateLimit,
+    Timestamp,
+    Fatal
+}
+
+impl GateError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            GateError::Network(_) => ErrorClass::Network,
+            GateError::Api(ApiCode::RateLimit, _) => ErrorClass::RateLimit,
+            GateError::Api(ApiCode::Timestamp, _) => ErrorClass::Timestamp,
+            _ => ErrorClass::Fatal
+        }
+    }
+
+    pub fn is_api(&self, api_code: ApiCode) -> bool {
+        matches!(self, GateError::Api(code, _) if *code == api_code)
+    }
+}
+
+impl fmt::Display for GateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GateError::Network(msg) => write!(f, "network error: {}", msg),
+            GateError::Api(code, msg) => write!(f, "Binance error {}: {}", code.code(), msg),
+            GateError::Parse(msg) => write!(f, "unexpected response: {}", msg)
+        }
+    }
+}
+
+impl From<reqwest::Error> for GateError {
+    fn from(error: reqwest::Error) -> Self {
+        GateError::Network(error.to_string())
+    }
+}
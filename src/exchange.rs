@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use crate::error::GateError;
+use crate::models::{AccountInfo, ExchangeInfo, Income, LeverageBracket, OrderResponse, PremiumIndex};
+use crate::order_manager::Order;
+
+// Everything the trader needs from a venue. The grid logic only talks to this trait,
+// so a mock or another exchange can be plugged in without touching trader_loop.
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    // Transient errors are already retried by the implementation when they come back here
+    async fn get_exchange_info(&self) -> Result<ExchangeInfo, GateError>;
+
+    async fn get_account_info(&self) -> Result<AccountInfo, GateError>;
+
+    // Maintenance margin tiers of the pair, the lowest notional first
+    async fn get_leverage_brackets(&self, pair: &str) -> Result<Vec<LeverageBracket>, GateError>;
+
+    // Mark price, funding rate of the coming settlement and its time
+    async fn get_premium_index(&self, pair: &str) -> Result<PremiumIndex, GateError>;
+
+    // Funding fees of the pair settled from start_time on, in milliseconds, the oldest first
+    async fn get_funding_income(&self, pair: &str, start_time: u64) -> Result<Vec<Income>, GateError>;
+
+    async fn get_listen_key(&self) -> Result<String, GateError>;
+
+    async fn keepalive_listen_key(&self) -> Result<(), GateError>;
+
+    async fn set_leverage(&self, pair: &str, leverage: u64) -> Result<(), GateError>;
+
+    async fn set_position_mode(&self) -> Result<(), GateError>;
+
+    async fn set_multiassets_mode(&self) -> Result<(), GateError>;
+
+    async fn set_margin_mode(&self, pair: &str) -> Result<(), GateError>;
+
+    async fn cancel_all_orders(&self, pair: &str) -> Result<(), GateError>;
+
+    async fn get_open_orders(&self, pair: &str) -> Result<Vec<OrderResponse>, GateError>;
+
+    // Any order of the pair, open or not, by client order id. ApiCode::OrderNotFound if the
+    // exchange never accepted it.
+    async fn get_order(&self, pair: &str, order_id: u32) -> Result<OrderResponse, GateError>;
+
+    // Market order reducing the long or short position by quantity
+    async fn close_position(&self, pair: &str, is_long: bool, quantity: f64, quantity_decimal_count: i64) -> Result<(), GateError>;
+
+    // Returns false if the exchange did not accept the order
+    async fn place_order(&self, pair: &str, order: &Order, price_decimal_count: i64, quantity_decimal_count: i64) -> bool;
+
+    // Returns false if the exchange did not cancel the order
+    async fn cancel_order(&self, pair: &str, order: &Order) -> bool;
+
+    // Returns false if at least one of the orders was not accepted
+    async fn place_orders(&self, pair: &str, orders: Vec<Order>, price_decimal_count: i64, quantity_decimal_count: i64) -> bool;
+
+    // Returns false if at least one of the orders was not canceled
+    async fn cancel_orders(&self, pair: &str, orders: Vec<Order>) -> bool;
+}
@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Fees are only listed by the income endpoint a little after the settlement
+const INCOME_DELAY_MS: u64 = 30_000;
+
+// The optional "funding" key of a grid, rates are per settlement: 0.0001 is 0.01%
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FundingSettings {
+    // Share taken off the open quantities of the side paying the coming settlement, 0 is off
+    pub skew: f64,
+    // Seconds before the settlement the paying side stops opening and closes at its entry price, 0 is off
+    pub trim_before: u64,
+    // The skew and the trim only act on a predicted rate above this, either way
+    pub min_rate: f64,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>
+}
+
+impl Default for FundingSettings {
+    fn default() -> Self {
+        FundingSettings {
+            skew: 0.0,
+            trim_before: 0,
+            min_rate: 0.0001,
+            unknown: BTreeMap::new()
+        }
+    }
+}
+
+// How the coming settlement reshapes the grid: a positive rate is paid by the longs
+#[derive(Clone, Copy, PartialEq)]
+pub struct FundingBias {
+    // Multipliers of the open quantities
+    pub long_scale: f64,
+    pub short_scale: f64,
+    // The side stops opening and its close ladder falls back to the entry price
+    pub trim_long: bool,
+    pub trim_short: bool
+}
+
+impl FundingBias {
+    pub fn neutral() -> Self {
+        FundingBias { long_scale: 1.0, short_scale: 1.0, trim_long: false, trim_short: false }
+    }
+}
+
+// Follows the predicted rate and the settlements of a pair, times are unix milliseconds
+pub struct FundingMonitor {
+    settings: FundingSettings,
+    // None until the premium index or the mark price stream was seen
+    rate: Option<f64>,
+    next_funding_time: u64,
+    // Latest settlement known to be over, and the latest one whose fees were fetched
+    passed_settlement: u64,
+    fetched_settlement: u64,
+    // Fees up to this time are already counted
+    last_income_time: u64
+}
+
+impl FundingMonitor {
+    // Fees settled before the start are left to the history of the previous session
+    pub fn new(settings: FundingSettings, now: u64) -> Self {
+        FundingMonitor {
+            settings,
+            rate: None,
+            next_funding_time: 0,
+            passed_settlement: 0,
+            fetched_settlement: 0,
+            last_income_time: now
+        }
+    }
+
+    pub fn apply(&mut self, rate: f64, next_funding_time: u64) {
+        if self.next_funding_time > 0 && next_funding_time > self.next_funding_time {
+            self.passed_settlement = self.next_funding_time;
+        }
+        self.rate = Some(rate);
+        self.next_funding_time = next_funding_time;
+    }
+
+    // No rate yet, or the settlement it predicted is over: the mark price stream is missing or lagging
+    pub fn is_stale(&self, now: u64) -> bool {
+        self.rate.is_none() || now >= self.next_funding_time
+    }
+
+    // Predicted rate and time of the coming settlement
+    pub fn get_prediction(&self) -> Option<(f64, u64)> {
+        self.rate.map(|rate| (rate, self.next_funding_time))
+    }
+
+    pub fn bias(&self, now: u64) -> FundingBias {
+        let rate = match self.rate {
+            Some(rate) if rate.abs() > self.settings.min_rate => rate,
+            _ => return FundingBias::neutral()
+        };
+        let longs_pay = rate > 0.0;
+        let paying_scale = 1.0 - self.settings.skew;
+        let trim = self.settings.trim_before > 0 && now < self.next_funding_time && now + self.settings.trim_before * 1000 >= self.next_funding_time;
+        FundingBias {
+            long_scale: if longs_pay { paying_scale } else { 1.0 },
+            short_scale: if longs_pay { 1.0 } else { paying_scale },
+            trim_long: trim && longs_pay,
+            trim_short: trim && !longs_pay
+        }
+    }
+
+    // Start time of the fees to fetch once a settlement is over and listed, None before
+    pub fn income_due(&mut self, now: u64) -> Option<u64> {
+        if self.next_funding_time > 0 && now >= self.next_funding_time {
+            self.passed_settlement = self.passed_settlement.max(self.next_funding_time);
+        }
+        if self.passed_settlement > self.fetched_settlement && now >= self.passed_settlement + INCOME_DELAY_MS {
+            Some(self.last_income_time + 1)
+        } else {
+            None
+        }
+    }
+
+    // Returns the fees not counted yet. A fee listed late is picked up after the next settlement.
+    pub fn apply_income(&mut self, incomes: Vec<(u64, f64)>) -> Vec<f64> {
+        self.fetched_settlement = self.passed_settlement;
+        let mut new_incomes: Vec<f64> = vec![];
+        for (time, income) in incomes {
+            if time > self.last_income_time {
+                self.last_income_time = time;
+                new_incomes.push(income);
+            }
+        }
+        new_incomes
+    }
+}
@@ -0,0 +1,441 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt;
+
+// How the distance between two levels is derived from increment, see GridConfig for the units
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spacing {
+    Fixed,
+    Percentage,
+    Geometric,
+    Atr
+}
+
+// What order_quantity is measured in
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantityMode {
+    // Base asset, the same quantity on every level
+    Base,
+    // Quote currency, every level gets the same notional at its own price
+    Quote,
+    // Base asset, shorthand for a linear quantity_profile of pyramid_factor
+    Pyramid
+}
+
+// Distance between two neighbouring levels of a ladder
+#[derive(Clone, Copy, PartialEq)]
+pub enum Step {
+    // Quote currency
+    Absolute(f64),
+    // Ratio between the prices, 0.001 puts each level 0.1% away from the previous one
+    Ratio(f64)
+}
+
+impl Step {
+    pub fn below(&self, price: f64, levels: u64) -> f64 {
+        match self {
+            Step::Absolute(size) => price - levels as f64 * size,
+            Step::Ratio(ratio) => price / (1.0 + ratio).powi(levels as i32)
+        }
+    }
+
+    pub fn above(&self, price: f64, levels: u64) -> f64 {
+        match self {
+            Step::Absolute(size) => price + levels as f64 * size,
+            Step::Ratio(ratio) => price * (1.0 + ratio).powi(levels as i32)
+        }
+    }
+
+    // Quote currency between a level at this price and the next one
+    pub fn size_at(&self, price: f64) -> f64 {
+        match self {
+            Step::Absolute(size) => *size,
+            Step::Ratio(ratio) => price * ratio
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Absolute(size) => write!(f, "{}", trim_decimals(*size)),
+            Step::Ratio(ratio) => write!(f, "{}%", trim_decimals(ratio * 100.0))
+        }
+    }
+}
+
+// Steps rounded to the tick size carry float noise, 1.7000000000000002 is printed 1.7
+fn trim_decimals(value: f64) -> String {
+    let formatted = format!("{:.8}", value);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// Average high-low range of the mid price over the last period bars of interval each, the bars
+// follow the event time of the ticks so a replayed backtest closes them like live trading does
+pub struct AtrEstimator {
+    // Milliseconds
+    interval: u64,
+    period: usize,
+    bar_start: Option<u64>,
+    high: f64,
+    low: f64,
+    ranges: VecDeque<f64>
+}
+
+impl AtrEstimator {
+    pub fn new(period: u64, interval: u64) -> Self {
+        AtrEstimator {
+            interval: interval * 1000,
+            period: period as usize,
+            bar_start: None,
+            high: 0.0,
+            low: 0.0,
+            ranges: VecDeque::with_capacity(period as usize)
+        }
+    }
+
+    pub fn update(&mut self, price: f64, time: u64) {
+        let bar_start = match self.bar_start {
+            Some(bar_start) => bar_start,
+            None => {
+                self.start_bar(price, time);
+                return
+            }
+        };
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        if time.saturating_sub(bar_start) >= self.interval {
+            if self.ranges.len() == self.period {
+                self.ranges.pop_front();
+            }
+            self.ranges.push_back(self.high - self.low);
+            self.start_bar(price, time);
+        }
+    }
+
+    fn start_bar(&mut self, price: f64, time: u64) {
+        self.bar_start = Some(time);
+        self.high = price;
+        self.low = price;
+    }
+
+    // None until the first bar is complete
+    pub fn value(&self) -> Option<f64> {
+        if self.ranges.is_empty() {
+            None
+        } else {
+            Some(self.ranges.iter().sum::<f64>() / self.ranges.len() as f64)
+        }
+    }
+}
+
+// Keeps the increments of the scaled spacings from changing, and reposting the ladder, on every tick
+pub fn round_significant(value: f64, digits: i32) -> f64 {
+    if value <= 0.0 || !value.is_finite() {
+        return value
+    }
+    let scale = 10_f64.powi(digits - 1 - value.log10().floor() as i32);
+    (value * scale).round() / scale
+}
+
+// Shape of an open ladder: the quantity of a level is multiplied by this, level 0 is the nearest
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum QuantityProfile {
+    Flat,
+    // 1 + step × level
+    Linear { step: f64 },
+    // factor ^ level, never above cap
+    Martingale { factor: f64, cap: f64 },
+    // One multiplier per level, the last one repeats on the levels past the end
+    Custom(Vec<f64>)
+}
+
+impl QuantityProfile {
+    pub fn multiplier(&self, level: usize) -> f64 {
+        match self {
+            QuantityProfile::Flat => 1.0,
+            QuantityProfile::Linear { step } => 1.0 + step * level as f64,
+            QuantityProfile::Martingale { factor, cap } => factor.powi(level as i32).min(*cap),
+            QuantityProfile::Custom(multipliers) => *multipliers.get(level).or(multipliers.last()).unwrap_or(&1.0)
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            QuantityProfile::Flat => Ok(()),
+            QuantityProfile::Linear { step } if *step < 0.0 => Err(format!("linear step {} must not be negative", step)),
+            QuantityProfile::Linear { .. } => Ok(()),
+            QuantityProfile::Martingale { factor, .. } if *factor < 1.0 => Err(format!("martingale factor {} must be at least 1", factor)),
+            QuantityProfile::Martingale { cap, .. } if *cap < 1.0 => Err(format!("martingale cap {} must be at least 1", cap)),
+            QuantityProfile::Martingale { .. } => Ok(()),
+            QuantityProfile::Custom(multipliers) if multipliers.iter().any(|multiplier| *multiplier < 0.0) => Err(String::from("custom multipliers must not be negative")),
+            QuantityProfile::Custom(multipliers) if !multipliers.iter().any(|multiplier| *multiplier > 0.0) => Err(String::from("custom needs at least one multiplier above 0")),
+            QuantityProfile::Custom(_) => Ok(())
+        }
+    }
+}
+
+// LOT_SIZE and MIN_NOTIONAL of the symbol, zero until the exchange info is known
+#[derive(Clone, Copy, Default)]
+pub struct LotFilters {
+    pub step_size: f64,
+    pub min_quantity: f64,
+    pub max_quantity: f64,
+    pub min_notional: f64
+}
+
+impl LotFilters {
+    fn floor_to_step(&self, quantity: f64) -> f64 {
+        if self.step_size <= 0.0 {
+            return quantity
+        }
+        (quantity / self.step_size + 1e-9).floor() * self.step_size
+    }
+
+    fn ceil_to_step(&self, quantity: f64) -> f64 {
+        if self.step_size <= 0.0 {
+            return quantity
+        }
+        (quantity / self.step_size - 1e-9).ceil() * self.step_size
+    }
+
+    // Smallest quantity the exchange accepts at this price
+    fn minimum(&self, price: f64) -> f64 {
+        self.ceil_to_step(self.min_quantity.max(self.min_notional / price))
+    }
+
+    // A level too small is raised to the minimum, one too large is capped, nothing is posted for 0
+    pub fn fit(&self, quantity: f64, price: f64) -> f64 {
+        if quantity <= 0.0 {
+            return 0.0
+        }
+        let quantity = self.floor_to_step(quantity).max(self.minimum(price));
+        if self.max_quantity > 0.0 {
+            quantity.min(self.floor_to_step(self.max_quantity))
+        } else {
+            quantity
+        }
+    }
+
+    fn round_to_step(&self, quantity: f64) -> f64 {
+        if self.step_size <= 0.0 {
+            return quantity
+        }
+        (quantity / self.step_size).round() * self.step_size
+    }
+
+    // Splits a position over the close levels by weight. A share below the minimum is left to the
+    // farthest level, which takes whatever remains so the whole position is always covered.
+    pub fn split_close(&self, position: f64, levels: &[CloseLevel], prices: &[f64]) -> Vec<f64> {
+        let mut quantities: Vec<f64> = vec![0.0; levels.len()];
+        let total_weight: f64 = levels.iter().map(|level| level.weight).sum();
+        let mut left = position;
+        for i in 0..levels.len() - 1 {
+            let quantity = self.floor_to_step(position * levels[i].weight / total_weight);
+            if quantity > 0.0 && quantity >= self.minimum(prices[i]) && quantity < left {
+                quantities[i] = quantity;
+                left -= quantity;
+            }
+        }
+        quantities[levels.len() - 1] = self.round_to_step(left).max(0.0);
+        quantities
+    }
+
+    // The remainder of the pool is only posted if it is a valid order on its own
+    pub fn remainder(&self, pool: f64, price: f64) -> f64 {
+        let quantity = self.floor_to_step(pool);
+        if quantity > 0.0 && quantity >= self.minimum(price) - self.step_size / 2.0 {
+            quantity
+        } else {
+            0.0
+        }
+    }
+}
+
+// One take-profit level: its distance beyond close_diff, in the same unit, and its share of the position
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloseLevel {
+    pub offset: f64,
+    pub weight: f64
+}
+
+impl CloseLevel {
+    // The single close order of each side the grid always had
+    pub fn single() -> Vec<CloseLevel> {
+        vec![CloseLevel { offset: 0.0, weight: 1.0 }]
+    }
+}
+
+// Quantity of every open level of one side, before and after the position left to open is applied
+#[derive(Clone)]
+pub struct LadderQuantities {
+    mode: QuantityMode,
+    order_quantity: f64,
+    profile: QuantityProfile,
+    filters: LotFilters
+}
+
+impl LadderQuantities {
+    pub fn new(mode: QuantityMode, order_quantity: f64, profile: QuantityProfile, filters: LotFilters) -> Self {
+        LadderQuantities { mode, order_quantity, profile, filters }
+    }
+
+    // Every level multiplied by scale before it is fitted to the filters
+    pub fn scaled(mut self, scale: f64) -> Self {
+        self.order_quantity *= scale;
+        self
+    }
+
+    // Quantity wanted at a level, the nearest one is level 0
+    pub fn target(&self, level: usize, price: f64) -> f64 {
+        let unit = match self.mode {
+            QuantityMode::Base | QuantityMode::Pyramid => self.order_quantity,
+            QuantityMode::Quote => self.order_quantity / price
+        };
+        self.filters.fit(unit * self.profile.multiplier(level), price)
+    }
+
+    // Levels get their target nearest first until the pool runs out, then one remainder order
+    pub fn distribute(&self, prices: &[f64], mut pool: f64) -> Vec<f64> {
+        let mut quantities: Vec<f64> = vec![0.0; prices.len()];
+        for (level, price) in prices.iter().enumerate() {
+            let target = self.target(level, *price);
+            if pool > target {
+                quantities[level] = target;
+                pool -= target;
+            } else {
+                quantities[level] = self.filters.remainder(pool, *price);
+                break;
+            }
+        }
+        quantities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-9, "{} is not {}", value, expected);
+    }
+
+    fn btc_filters() -> LotFilters {
+        LotFilters { step_size: 0.001, min_quantity: 0.001, max_quantity: 1.0, min_notional: 5.0 }
+    }
+
+    #[test]
+    fn absolute_step_moves_by_the_size() {
+        let step = Step::Absolute(10.0);
+        assert_near(step.below(100.0, 3), 70.0);
+        assert_near(step.above(100.0, 2), 120.0);
+        assert_near(step.size_at(50000.0), 10.0);
+    }
+
+    #[test]
+    fn ratio_step_compounds() {
+        let step = Step::Ratio(0.01);
+        assert_near(step.above(100.0, 2), 102.01);
+        assert_near(step.below(102.01, 2), 100.0);
+        assert_near(step.size_at(200.0), 2.0);
+    }
+
+    #[test]
+    fn round_significant_keeps_the_digits() {
+        assert_near(round_significant(0.012345, 2), 0.012);
+        assert_near(round_significant(1234.5, 3), 1230.0);
+        assert_near(round_significant(0.0, 3), 0.0);
+    }
+
+    #[test]
+    fn profile_multipliers() {
+        assert_near(QuantityProfile::Flat.multiplier(7), 1.0);
+        assert_near(QuantityProfile::Linear { step: 0.5 }.multiplier(2), 2.0);
+        let martingale = QuantityProfile::Martingale { factor: 2.0, cap: 5.0 };
+        let multipliers: Vec<f64> = (0..5).map(|level| martingale.multiplier(level)).collect();
+        assert_eq!(multipliers, vec![1.0, 2.0, 4.0, 5.0, 5.0]);
+        // The last multiplier repeats past the end
+        assert_near(QuantityProfile::Custom(vec![1.0, 2.0]).multiplier(5), 2.0);
+    }
+
+    #[test]
+    fn profile_validation() {
+        assert!(QuantityProfile::Linear { step: -0.1 }.validate().is_err());
+        assert!(QuantityProfile::Martingale { factor: 0.5, cap: 4.0 }.validate().is_err());
+        assert!(QuantityProfile::Martingale { factor: 2.0, cap: 0.5 }.validate().is_err());
+        assert!(QuantityProfile::Custom(vec![-1.0, 1.0]).validate().is_err());
+        assert!(QuantityProfile::Custom(vec![0.0, 0.0]).validate().is_err());
+        assert!(QuantityProfile::Custom(vec![0.0, 1.0]).validate().is_ok());
+    }
+
+    #[test]
+    fn fit_raises_to_the_minimum_and_caps_at_the_maximum() {
+        let filters = btc_filters();
+        assert_near(filters.fit(0.0001, 10000.0), 0.001);
+        assert_near(filters.fit(0.0025, 10000.0), 0.002);
+        assert_near(filters.fit(3.0, 10000.0), 1.0);
+        assert_near(filters.fit(0.0, 10000.0), 0.0);
+        // 5 of notional at 1000 needs 0.005
+        assert_near(filters.fit(0.002, 1000.0), 0.005);
+    }
+
+    #[test]
+    fn split_close_by_weight() {
+        let filters = btc_filters();
+        let levels = vec![CloseLevel { offset: 0.0, weight: 1.0 }, CloseLevel { offset: 5.0, weight: 1.0 }];
+        let quantities = filters.split_close(0.010, &levels, &[100000.0, 100005.0]);
+        assert_near(quantities[0], 0.005);
+        assert_near(quantities[1], 0.005);
+    }
+
+    #[test]
+    fn split_close_leaves_a_small_share_to_the_farthest_level() {
+        let filters = btc_filters();
+        let levels = vec![CloseLevel { offset: 0.0, weight: 1.0 }, CloseLevel { offset: 5.0, weight: 1.0 }];
+        let quantities = filters.split_close(0.001, &levels, &[100000.0, 100005.0]);
+        assert_near(quantities[0], 0.0);
+        assert_near(quantities[1], 0.001);
+    }
+
+    #[test]
+    fn remainder_is_only_posted_when_valid() {
+        let filters = btc_filters();
+        assert_near(filters.remainder(0.0015, 100000.0), 0.001);
+        assert_near(filters.remainder(0.0004, 100000.0), 0.0);
+    }
+
+    #[test]
+    fn distribute_fills_the_nearest_levels_first() {
+        let ladder = LadderQuantities::new(QuantityMode::Base, 0.002, QuantityProfile::Flat, btc_filters());
+        let prices = [100000.0, 99990.0, 99980.0, 99970.0];
+        let quantities = ladder.distribute(&prices, 0.005);
+        assert_near(quantities[0], 0.002);
+        assert_near(quantities[1], 0.002);
+        assert_near(quantities[2], 0.001);
+        assert_near(quantities[3], 0.0);
+        assert!(ladder.distribute(&prices, 1.0).iter().all(|quantity| (quantity - 0.002).abs() < 1e-9));
+    }
+
+    #[test]
+    fn distribute_follows_the_profile_and_the_scale() {
+        let ladder = LadderQuantities::new(QuantityMode::Base, 0.002, QuantityProfile::Linear { step: 1.0 }, btc_filters());
+        let quantities = ladder.clone().distribute(&[100000.0, 99990.0, 99980.0], 1.0);
+        assert_near(quantities[0], 0.002);
+        assert_near(quantities[1], 0.004);
+        assert_near(quantities[2], 0.006);
+        let halved = ladder.scaled(0.5).distribute(&[100000.0, 99990.0, 99980.0], 1.0);
+        assert_near(halved[0], 0.001);
+        assert_near(halved[2], 0.003);
+    }
+
+    #[test]
+    fn quote_mode_targets_the_same_notional() {
+        let ladder = LadderQuantities::new(QuantityMode::Quote, 100.0, QuantityProfile::Flat, btc_filters());
+        assert_near(ladder.target(0, 50000.0), 0.002);
+        assert_near(ladder.target(3, 25000.0), 0.004);
+    }
+}
@@ -0,0 +1,44 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// The optional "inventory_skew" key of a grid, leans the open ladders against the net position
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InventorySettings {
+    // Move of the ladder centre at full imbalance, in the unit of margin, 0 is off
+    pub shift: f64,
+    // Share taken off the open quantities of the heavier side, and added to the lighter one, at
+    // full imbalance, 0 is off
+    pub quantity: f64,
+    // Net position, in base asset, counted as full imbalance, max_position when missing
+    pub max_net: Option<f64>,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>
+}
+
+impl InventorySettings {
+    fn is_enabled(&self) -> bool {
+        self.shift > 0.0 || self.quantity > 0.0
+    }
+
+    // Longs minus shorts over max_net, from -1 all short to 1 all long, in steps of 10%. It only
+    // leaves the current step a full step away, so fills back and forth around a boundary do not
+    // repost the ladders every time.
+    pub fn imbalance(&self, longs: f64, shorts: f64, max_position: f64, current: f64) -> f64 {
+        if !self.is_enabled() {
+            return 0.0
+        }
+        let imbalance = ((longs - shorts) / self.max_net.unwrap_or(max_position)).clamp(-1.0, 1.0);
+        if (imbalance - current).abs() < 0.1 - 1e-9 {
+            current
+        } else {
+            (imbalance * 10.0).round() / 10.0
+        }
+    }
+
+    // Multipliers of the open long then open short quantities
+    pub fn quantity_scales(&self, imbalance: f64) -> (f64, f64) {
+        (1.0 - self.quantity * imbalance, 1.0 + self.quantity * imbalance)
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use crate::models::{OrderStatus, OrderUpdate, PositionSide, Side};
+
+// One line of the journal. Price and quantity are the ones of the last fill for fills,
+// and the ones of the order otherwise.
+#[derive(Serialize)]
+struct JournalRecord<'a> {
+    timestamp: i64,
+    symbol: &'a str,
+    client_order_id: &'a str,
+    side: Side,
+    position_side: PositionSide,
+    status: OrderStatus,
+    price: f64,
+    quantity: f64,
+    commission: f64,
+    realized_profit: f64
+}
+
+// Append-only JSONL file of every ORDER_TRADE_UPDATE received, kept across restarts
+pub struct Journal {
+    path: String,
+    file: Option<File>
+}
+
+impl Journal {
+    // An empty path disables the journal
+    pub fn open(path: &str) -> Self {
+        let file = if path.is_empty() {
+            None
+        } else {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(file),
+                Err(error) => {
+                    println!("\x1b[91mFailed to open journal {}: {}, orders will not be journaled\x1b[0m", path, error);
+                    None
+                }
+            }
+        };
+        Journal {
+            path: String::from(path),
+            file
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Journal {
+            path: String::new(),
+            file: None
+        }
+    }
+
+    pub fn record(&mut self, order: &OrderUpdate) {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return
+        };
+        let is_fill = order.last_quantity > 0.0;
+        let record = JournalRecord {
+            timestamp: if order.trade_time > 0 { order.trade_time as i64 } else { chrono::Utc::now().timestamp_millis() },
+            symbol: &order.symbol,
+            client_order_id: &order.client_order_id,
+            side: order.side,
+            position_side: order.position_side,
+            status: order.status,
+            price: if is_fill { order.last_price } else { order.price },
+            quantity: if is_fill { order.last_quantity } else { order.quantity },
+            commission: order.commission,
+            realized_profit: order.realized_profit
+        };
+        let line = format!("{}\n", serde_json::to_string(&record).unwrap());
+        if let Err(error) = file.write_all(line.as_bytes()) {
+            println!("\x1b[91mFailed to write journal {}: {}\x1b[0m", self.path, error);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+pub mod backtest;
+pub mod binance;
+pub mod cli;
+pub mod config;
+pub mod dry_run;
+pub mod error;
+pub mod exchange;
+pub mod funding;
+pub mod grid;
+pub mod inventory;
+pub mod journal;
+pub mod margin;
+pub mod memory_manager;
+pub mod mock_server;
+pub mod models;
+pub mod order_manager;
+pub mod paper;
+pub mod rate_limiter;
+pub mod reconciler;
+pub mod request_log;
+pub mod retry;
+pub mod risk;
+pub mod secrets;
+pub mod simulator;
+pub mod state_actor;
+pub mod stats;
+
+use tokio::time::Duration;
+
+// Shared by the market websocket of the trader and the one of paper trading
+// Binance pings every 3 minutes, a socket silent for longer than this is considered dead
+pub const PING_TIMEOUT: Duration = Duration::from_secs(600);
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
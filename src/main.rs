@@ -1,96 +1,477 @@
-mod memory_manager;
-pub mod order_manager;
+use program::{backtest, binance, cli, config, dry_run, error, exchange, grid, memory_manager, mock_server, models, order_manager, paper, reconciler, request_log, risk, secrets, simulator, state_actor, MAX_RECONNECT_DELAY, PING_TIMEOUT};
 use chrono::{Timelike, Local};
-use hmac::Mac;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use simulator::{SimulatedExchange, SimulatorConfig, SimulatorSettings};
+use error::{ApiCode, GateError};
+use exchange::Exchange;
+use models::{AccountInfo, BookTicker, MarkPriceUpdate, OrderStatus, StreamMessage, SymbolFilter, UserDataEvent};
+use serde::Deserialize;
 use order_manager::Order;
-use std::sync::Mutex;
-use hex::encode as hex_encode; 
-use std::time::{SystemTime, UNIX_EPOCH};
-use futures::{stream, StreamExt};
-use tokio::time::{sleep, Duration};
+use reconciler::Reconciler;
+use risk::RiskAction;
+use std::sync::Arc;
+use futures::StreamExt;
+use binance::BinanceExchange;
+use cli::Command;
+use config::{Config, ShutdownMode};
+use memory_manager::Venue;
+use dry_run::DryRunExchange;
+use paper::PaperExchange;
+use request_log::RequestLog;
+use secrets::Secrets;
+use state_actor::{MemHandle, StateEvent};
+use tokio::net::TcpStream;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, timeout_at, Duration, Instant};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use std::collections::HashMap;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
 
-static MEM: Lazy<Mutex<memory_manager::MemoryManager>> = Lazy::new(|| Mutex::new(memory_manager::MemoryManager::new()));
+static SETUP: OnceCell<Setup> = OnceCell::new();
+static GRIDS: Lazy<Vec<MemHandle>> = Lazy::new(|| {
+    let setup = SETUP.get().unwrap();
+    memory_manager::load_grids(&setup.config, &setup.secrets, &setup.venue)
+});
+static SHUTDOWN: Lazy<Notify> = Lazy::new(Notify::new);
+const FINAL_STATS_PATH: &str = "ressources/final_stats.txt";
+
+// Everything the grids are built from, set once the command line is parsed
+struct Setup {
+    config: Config,
+    secrets: Secrets,
+    venue: Venue
+}
 
 #[tokio::main]
 async fn main() {
-    let _ = tokio::spawn(task_websocket());
-    let _ = tokio::spawn(task_trader());
-    let _ = tokio::spawn(task_stats());
-    let _ = tokio::spawn(task_keepalive());
-    let _ = tokio::spawn(task_fullrestart());
-    loop {
-        sleep(Duration::MAX).await;
+    let args: Vec<String> = std::env::args().collect();
+    let command = cli::parse(&args).unwrap_or_else(|error| {
+        println!("{}", cli::USAGE);
+        exit_with_errors(&[error])
+    });
+    match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return
+        },
+        Command::Keystore => {
+            if let Err(error) = secrets::run_keystore_command(&args) {
+                exit_with_errors(&[error]);
+            }
+            return
+        },
+        _ => ()
+    }
+    let config_path = config::config_path(&args).unwrap_or_else(|error| exit_with_errors(&[error]));
+    let config = match config::load(&config_path) {
+        Ok(config) => config,
+        Err(errors) => {
+            println!("\x1b[91m{} error(s) in {}\x1b[0m", errors.len(), config_path);
+            exit_with_errors(&errors);
+        }
+    };
+    for warning in config::warnings(&config) {
+        println!("\x1b[93mWARNING: {}\x1b[0m", warning);
     }
+    if let Command::CheckConfig = command {
+        let errors = check_exchange_info(&config).await;
+        if !errors.is_empty() {
+            println!("\x1b[91m{} error(s) against the exchange info\x1b[0m", errors.len());
+            exit_with_errors(&errors);
+        }
+        let pairs: Vec<&str> = config.grids.iter().map(|grid| grid.pair.as_str()).collect();
+        println!("\x1b[92m{} is valid, {} grid(s): {}\x1b[0m", config_path, pairs.len(), pairs.join(", "));
+        return
+    }
+    let pairs: Vec<String> = config.grids.iter().map(|grid| grid.pair.clone()).collect();
+    let mock_settings = config.mock_server.clone();
+    let backtest_settings = config.backtest.clone();
+    // The backtest and the standalone mock server never sign real requests, paper trading only uses discord
+    let secrets = if command.needs_secrets() {
+        Secrets::load(&config).unwrap_or_else(|error| exit_with_errors(&[error]))
+    } else if let Command::Paper = command {
+        Secrets::load(&config).unwrap_or_default()
+    } else {
+        Secrets::default()
+    };
+    match command {
+        Command::MockServer => {
+            mock_server::start(pairs, mock_settings.unwrap_or_default()).await;
+            loop {
+                sleep(Duration::MAX).await;
+            }
+        },
+        Command::Backtest(_) => (),
+        _ => if let Some(mock_settings) = &mock_settings {
+            mock_server::start(pairs.clone(), mock_settings.clone()).await;
+        }
+    }
+    // Paper trading follows the mainnet market, or the mock one when it is configured
+    let mut paper_market = None;
+    let venue = match command {
+        Command::DryRun => {
+            let request_log = RequestLog::create(&cli::request_log_path(&args)).unwrap_or_else(|error| exit_with_errors(&[error]));
+            let (url_request, _) = memory_manager::endpoints(&config);
+            let inner = BinanceExchange::new(url_request, &secrets.api_key, &secrets.api_secret, config.retry, config.rate_limit);
+            Venue::DryRun(Arc::new(DryRunExchange::new(inner, request_log)))
+        },
+        Command::Paper => {
+            let (url_request, url_websocket) = match &mock_settings {
+                Some(mock_settings) => (mock_settings.get_url_request(), mock_settings.get_url_websocket()),
+                None => (String::from(paper::URL_REQUEST), String::from(paper::URL_WEBSOCKET))
+            };
+            let (paper, events) = PaperExchange::new(&url_request, &pairs, &config.paper).await.unwrap_or_else(|error| exit_with_errors(&[error]));
+            let paper = Arc::new(paper);
+            paper_market = Some((paper.clone(), events, url_websocket));
+            Venue::Paper(paper)
+        },
+        _ => Venue::Live
+    };
+    if SETUP.set(Setup { config, secrets, venue }).is_err() {
+        panic!("\x1b[91mERROR: The config was already loaded\x1b[0m");
+    }
+    if let Command::Backtest(path) = &command {
+        run_backtest(path, &backtest_settings).await;
+        return
+    }
+    match command {
+        Command::Status => print_status().await,
+        Command::CancelAll => for mem in GRIDS.iter() {
+            let pair = mem.query(|local_mem| local_mem.get_pair()).await;
+            println!("{} canceling all orders", pair);
+            cancel_all_orders(mem).await;
+        },
+        Command::Flatten => for mem in GRIDS.iter() {
+            get_exchange_info(mem).await.unwrap_or_else(|errors| exit_with_errors(&errors));
+            shutdown_grid(mem, ShutdownMode::Flatten).await;
+        },
+        Command::ExchangeInfo(pair) => print_exchange_info(&pair).await,
+        _ => run(paper_market).await
+    }
+}
+
+// run, dry-run and paper: one trader per grid until a shutdown signal
+async fn run(paper_market: Option<(Arc<PaperExchange>, tokio::sync::mpsc::UnboundedReceiver<UserDataEvent>, String)>) {
+    match paper_market {
+        Some((paper, events, url_websocket)) => {
+            tokio::spawn(paper::task_market(paper, events, url_websocket, GRIDS.clone()));
+        },
+        None => {
+            tokio::spawn(task_websocket());
+        }
+    }
+    let traders: Vec<JoinHandle<()>> = GRIDS.iter().map(|mem| tokio::spawn(task_trader(mem.clone()))).collect();
+    tokio::spawn(task_stats());
+    tokio::spawn(task_risk());
+    tokio::spawn(task_funding());
+    tokio::spawn(task_keepalive());
+    tokio::spawn(task_fullrestart());
+    wait_shutdown_signal().await;
+    shutdown(traders).await;
 }
 
-async fn task_set_last_long_open(price: f64) {
-    sleep(Duration::from_secs(1)).await;
-    {
-        MEM.lock().unwrap().set_last_long_open(price);
+// check-config: every grid against the trading rules of its pair, the mock server serves its own
+async fn check_exchange_info(config: &Config) -> Vec<String> {
+    let exchange_info = match &config.mock_server {
+        Some(mock_settings) => {
+            let mut exchange_info = models::ExchangeInfo { symbols: vec![] };
+            for grid in config.grids.iter() {
+                let engine = SimulatedExchange::new(grid.pair.clone(), SimulatorSettings::new(&grid.pair, &mock_settings.simulator));
+                exchange_info.symbols.extend(engine.get_exchange_info().await.map(|info| info.symbols).unwrap_or_default());
+            }
+            Ok(exchange_info)
+        },
+        None => {
+            let (url_request, _) = memory_manager::endpoints(config);
+            // The exchange info is public, no key is needed
+            BinanceExchange::new(url_request, "", "", config.retry, config.rate_limit).get_exchange_info().await
+        }
+    };
+    let exchange_info = match exchange_info {
+        Ok(exchange_info) => exchange_info,
+        Err(error) => return vec![format!("Failed to get_exchange_info(): {}", error)]
+    };
+    let mut errors: Vec<String> = vec![];
+    for grid in config.grids.iter() {
+        match symbol_settings(exchange_info.clone(), &grid.pair) {
+            Ok(symbol) => errors.extend(config::check_symbol(grid, symbol.price_decimal, symbol.min_quantity, symbol.max_quantity, symbol.max_order_amount)),
+            Err(error) => errors.push(error)
+        }
     }
+    errors
 }
 
-async fn task_set_last_short_open(price: f64) {
-    sleep(Duration::from_secs(1)).await;
-    {
-        MEM.lock().unwrap().set_last_short_open(price);
+fn exit_with_errors(errors: &[String]) -> ! {
+    for error in errors.iter() {
+        println!("\x1b[91mERROR: {}\x1b[0m", error);
     }
+    std::process::exit(1);
+}
+
+// SIGINT, SIGTERM or the discord kill command
+async fn wait_shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => println!("\x1b[91mReceived SIGINT\x1b[0m"),
+        _ = sigterm.recv() => println!("\x1b[91mReceived SIGTERM\x1b[0m"),
+        _ = SHUTDOWN.notified() => ()
+    }
+}
+
+// Stops the traders, then shuts every grid down and writes the stats of all of them
+async fn shutdown(traders: Vec<JoinHandle<()>>) {
+    println!("\x1b[91mShutting down\x1b[0m");
+    let mut shutdown_modes: Vec<ShutdownMode> = vec![];
+    for mem in GRIDS.iter() {
+        shutdown_modes.push(mem.update(|local_mem| local_mem.start_shutdown()).await);
+    }
+    let deadline = Instant::now() + Duration::from_secs(30);
+    for mut trader in traders {
+        tokio::select! {
+            _ = &mut trader => (),
+            _ = tokio::time::sleep_until(deadline) => {
+                println!("\x1b[91mTrader did not stop in time, aborting it\x1b[0m");
+                trader.abort();
+            }
+        }
+    }
+    for (mem, shutdown_mode) in GRIDS.iter().zip(shutdown_modes) {
+        shutdown_grid(mem, shutdown_mode).await;
+    }
+    write_final_stats().await;
+}
+
+// Cancels the grid, then leaves, closes or flattens the positions depending on the config
+async fn shutdown_grid(mem: &MemHandle, shutdown_mode: ShutdownMode) {
+    println!("Canceling all orders");
+    cancel_all_orders(mem).await;
+    if let Err(error) = verify_account_info(mem).await {
+        println!("\x1b[91m{}\x1b[0m", error);
+    }
+    let (exchange, pair, quantity_decimal_count, (current_longs, current_shorts), (long_close_levels, short_close_levels)) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair(), local_mem.get_quantity_decimal_count(), local_mem.get_positions(), local_mem.get_close_levels())).await;
+    match shutdown_mode {
+        ShutdownMode::Leave => println!("Leaving positions open"),
+        ShutdownMode::Close => {
+            println!("Posting close orders");
+            let close_orders = long_close_levels.into_iter().map(|(price, quantity)| Order::new(price, quantity, false, true))
+                .chain(short_close_levels.into_iter().map(|(price, quantity)| Order::new(price, quantity, false, false)))
+                .collect();
+            post_multiple_orders(mem, close_orders).await;
+        },
+        ShutdownMode::Flatten => {
+            println!("Flattening positions");
+            for (is_long, quantity) in [(true, current_longs), (false, current_shorts)] {
+                if quantity > 0.0 {
+                    if let Err(error) = exchange.close_position(&pair, is_long, quantity, quantity_decimal_count).await {
+                        println!("\x1b[91mFailed to close_position(): {}\x1b[0m", error);
+                    }
+                }
+            }
+            if let Err(error) = verify_account_info(mem).await {
+                println!("\x1b[91m{}\x1b[0m", error);
+            }
+        }
+    }
+}
+
+async fn write_final_stats() {
+    let mut messages: Vec<String> = vec![];
+    for mem in GRIDS.iter() {
+        mem.query(|local_mem| local_mem.save_history()).await;
+        messages.push(stats_message(mem).await);
+    }
+    let msg_str = messages.join("\n");
+    println!("\x1b[96m{}\x1b[0m", msg_str);
+    send_discord_msg(&format!("```{}```", msg_str)).await;
+    match std::fs::write(FINAL_STATS_PATH, format!("{}\n", msg_str)) {
+        Ok(()) => println!("Final stats written to {}", FINAL_STATS_PATH),
+        Err(error) => println!("\x1b[91mFailed to write {}: {}\x1b[0m", FINAL_STATS_PATH, error)
+    }
+}
+
+async fn run_backtest(path: &str, settings: &SimulatorConfig) {
+    let ticks = backtest::read_ticks(path);
+    println!("Loaded {} ticks from {}", ticks.len(), path);
+    // Only the first grid is backtested
+    let mem = &GRIDS[0];
+    let pair = mem.query(|local_mem| local_mem.get_pair()).await;
+    let exchange = Arc::new(SimulatedExchange::new(pair.clone(), SimulatorSettings::new(&pair, settings)));
+    let backtest_exchange = exchange.clone();
+    mem.update(move |local_mem| local_mem.set_backtest_exchange(backtest_exchange)).await;
+    tokio::spawn(task_trader(mem.clone()));
+    wait_token(mem).await;
+    for tick in ticks.iter() {
+        exchange.apply_tick(tick.top_bid, tick.top_ask);
+        mem.send(StateEvent::Tick { top_ask: tick.top_ask, top_bid: tick.top_bid, time: tick.time });
+        for event in exchange.take_events() {
+            mem.send(StateEvent::User(event));
+        }
+        mem.wait_pass().await;
+        for event in exchange.take_events() {
+            mem.send(StateEvent::User(event));
+        }
+    }
+    exchange.print_report();
+    print_stats(mem).await;
 }
 
 async fn task_stats() {
     loop {
         sleep(Duration::from_secs(50)).await;
-        verify_account_info().await;
+        for mem in GRIDS.iter() {
+            if let Err(error) = verify_account_info(mem).await {
+                println!("\x1b[91m{}\x1b[0m", error);
+            }
+            mem.query(|local_mem| local_mem.save_history()).await;
+        }
+    }
+}
+
+// Takes the next step of the escalation of every grid over one of its risk limits, and sends a
+// grid close to liquidation into close-only. A new margin scale wakes the trader to resize its levels.
+async fn task_risk() {
+    loop {
+        sleep(RISK_CHECK_INTERVAL).await;
+        for mem in GRIDS.iter() {
+            if let Some((action, breaches)) = mem.query(|local_mem| local_mem.check_risk()).await {
+                apply_risk_action(mem, action, &breaches).await;
+            }
+            let (open_scale, reason) = mem.query(|local_mem| local_mem.check_margin()).await;
+            if let Some(open_scale) = open_scale {
+                mem.update(move |local_mem| local_mem.set_open_scale(open_scale)).await;
+            }
+            if let Some(reason) = reason {
+                apply_risk_action(mem, RiskAction::CloseOnly, &reason).await;
+            }
+        }
     }
 }
 
-async fn print_stats() {
-    let (duration, volume, order_overflow, balance, commission, trading_delta, long_quantity, short_quantity, price_decimal_count, quantity_decimal_count, current_price, long_close_price, short_close_price, long_entry_price, short_entry_price, long_un_pnl, short_un_pnl, vip_level, long_increment, short_increment) = { MEM.lock().unwrap().get_stats() };
+// Polls the premium index of a grid whose funding rate went stale, where there is no mark price
+// stream like in paper trading, and records the fees of every grid once a settlement is over
+async fn task_funding() {
+    loop {
+        sleep(FUNDING_CHECK_INTERVAL).await;
+        for mem in GRIDS.iter() {
+            if mem.query(|local_mem| local_mem.is_funding_stale()).await {
+                get_premium_index(mem).await;
+            }
+            let start_time = match mem.query(|local_mem| local_mem.funding_income_due()).await {
+                Some(start_time) => start_time,
+                None => continue
+            };
+            let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+            match exchange.get_funding_income(&pair, start_time).await {
+                Ok(incomes) => mem.query(move |local_mem| local_mem.apply_funding_income(incomes)).await,
+                Err(error) => println!("\x1b[91mFailed to get_funding_income(): {}\x1b[0m", error)
+            }
+        }
+    }
+}
+
+async fn apply_risk_action(mem: &MemHandle, action: RiskAction, breaches: &str) {
+    let pair = mem.query(|local_mem| local_mem.get_pair()).await;
+    let msg = format!("{} risk limit breached: {}, taking action {}", pair, breaches, action);
+    println!("\x1b[91m{}\x1b[0m", msg);
+    send_discord_msg(&msg).await;
+    match action {
+        RiskAction::Notify => (),
+        RiskAction::CloseOnly => mem.update(|local_mem| local_mem.activate_close_only()).await,
+        RiskAction::CancelAll | RiskAction::Flatten => {
+            // The trader finishes its pass first, so nothing it posts survives the cancel
+            mem.update(|local_mem| local_mem.halt()).await;
+            if timeout(RISK_PASS_TIMEOUT, mem.wait_pass()).await.is_err() {
+                println!("\x1b[91m{} trader did not finish its pass in {}s, cancelling anyway\x1b[0m", pair, RISK_PASS_TIMEOUT.as_secs());
+            }
+            if action == RiskAction::Flatten {
+                shutdown_grid(mem, ShutdownMode::Flatten).await;
+            } else {
+                cancel_all_orders(mem).await;
+            }
+        }
+    }
+}
+
+async fn print_stats(mem: &MemHandle) {
+    let msg_str = stats_message(mem).await;
+    println!("\x1b[96m{}\x1b[0m", msg_str);
+
+    let input_msg = get_discord_msg().await;
+
+    send_discord_msg(&format!("```{}```XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXaaGGGGGGGGGGGGGGGGGGGGGGYYYYYYYYYYYYYYY", &msg_str)).await;
+    read_user_input(&input_msg).await;
+}
+
+async fn stats_message(mem: &MemHandle) -> String {
+    let (duration, volume, order_overflow, balance, commission, trading_delta, long_quantity, short_quantity, price_decimal_count, quantity_decimal_count, current_price, long_close_price, short_close_price, long_entry_price, short_entry_price, long_un_pnl, short_un_pnl, vip_level, long_increment, short_increment) = mem.query(|local_mem| local_mem.get_stats()).await;
+    let (window_duration, window_totals, all_time_duration, all_time_totals) = mem.query(|local_mem| local_mem.get_history()).await;
+    let (pair, margin_estimate, (funding_prediction, funding_paid)) = mem.query(|local_mem| (local_mem.get_pair(), local_mem.get_margin_estimate(), local_mem.get_funding())).await;
     let mut str_list: Vec<String> = Vec::with_capacity(15);
     let now = Local::now();
-    str_list.push(format!("------------- Stats -------------"));
+    str_list.push(format!("{:-^33}", format!(" {} Stats ", pair)));
     str_list.push(format!("Current session: {}:{}.{}", (duration.as_secs() / 60) / 60, (duration.as_secs() / 60) % 60, duration.as_secs() % 60));
     str_list.push(format!("Current time: {}:{}.{}", now.hour(), now.minute(), now.second()));
     str_list.push(format!("Vip Level: {}", vip_level));
     str_list.push(format!("Balance: {0:.1$}", balance, price_decimal_count as usize));
     str_list.push(format!("Commission: {0:.1$}", commission, price_decimal_count as usize));
     str_list.push(format!("Trading delta: {0:.1$}", trading_delta, price_decimal_count as usize));
+    str_list.push(format!("Funding: {0:.1$}", funding_paid, price_decimal_count as usize));
     str_list.push(format!("Volume: {0:.1$}", volume, price_decimal_count as usize));
     str_list.push(format!("Order overflow: {}", order_overflow));
-    str_list.push(format!("---------------------------------"));
-    str_list.push(format!("30d Volume: {:.2}", volume / duration.as_secs() as f64 * 2.592));
-    let salary = (commission + trading_delta) / duration.as_secs() as f64 * 3600.0;
+    str_list.push(String::from("---------------------------------"));
+    // Projected from the rolling window, which is restored across restarts
+    let window_secs = window_duration.as_secs().max(1) as f64;
+    str_list.push(format!("30d Volume: {:.2}", window_totals.volume / window_secs * 2.592));
+    let salary = (window_totals.realized_profit - window_totals.commission + window_totals.funding) / window_secs * 3600.0;
     str_list.push(format!("$/h: {0:.1$}", salary, price_decimal_count as usize));
     str_list.push(format!("$/year: {:.1}k", salary * 8.760));
-    str_list.push(format!("---------------------------------"));
+    str_list.push(String::from("---------------------------------"));
+    str_list.push(format!("All-time: {}d {}h", all_time_duration.as_secs() / 86400, (all_time_duration.as_secs() / 3600) % 24));
+    str_list.push(format!("All-time volume: {:.2}", all_time_totals.volume));
+    str_list.push(format!("All-time commission: {0:.1$}", -all_time_totals.commission, price_decimal_count as usize));
+    str_list.push(format!("All-time trading delta: {0:.1$}", all_time_totals.realized_profit, price_decimal_count as usize));
+    str_list.push(format!("All-time funding: {0:.1$}", all_time_totals.funding, price_decimal_count as usize));
+    str_list.push(String::from("---------------------------------"));
     str_list.push(format!("Current price: {:.2}", current_price));
     str_list.push(format!("Long entry price: {}", if long_entry_price < 1.0 { String::from("No longs") } else { format!("{:.2}", long_entry_price) }));
     str_list.push(format!("Long close price: {}", if long_entry_price < 1.0 { String::from("No longs") } else { format!("{:.2}", long_close_price) }));
     str_list.push(format!("Short entry price: {}", if short_entry_price < 1.0 { String::from("No shorts") } else { format!("{:.2}", short_entry_price) }));
     str_list.push(format!("Short close price: {}", if short_entry_price < 1.0 { String::from("No shorts") } else { format!("{:.2}", short_close_price) }));
-    str_list.push(format!("---------------------------------"));
+    str_list.push(String::from("---------------------------------"));
     str_list.push(format!("Current longs: {}", if long_entry_price < 1.0 { String::from("No longs") } else { format!("{0:.1$}", long_quantity, quantity_decimal_count as usize) }));
     str_list.push(format!("Longs Un-PNL: {}", if long_entry_price < 1.0 { String::from("No longs") } else { format!("{:.2}", long_un_pnl) }));
     str_list.push(format!("Current shorts: {}", if short_entry_price < 1.0 { String::from("No shorts") } else { format!("{0:.1$}", short_quantity, quantity_decimal_count as usize) }));
     str_list.push(format!("Shorts Un-PNL: {}", if short_entry_price < 1.0 { String::from("No shorts") } else { format!("{:.2}", short_un_pnl) }));
-    str_list.push(format!("---------------------------------"));
+    str_list.push(String::from("---------------------------------"));
+    match margin_estimate {
+        Some(estimate) => {
+            str_list.push(format!("Margin ratio: {:.2}%", estimate.margin_ratio * 100.0));
+            str_list.push(format!("Liquidation price: {}", estimate.liquidation_price.map_or(String::from("None"), |price| format!("{:.2}", price))));
+        },
+        None => str_list.push(String::from("Margin ratio: Unknown"))
+    }
+    match funding_prediction {
+        Some((rate, next_funding_time)) => {
+            let minutes_left = (next_funding_time as i64 - Local::now().timestamp_millis()).max(0) / 60000;
+            str_list.push(format!("Funding rate: {:.4}%", rate * 100.0));
+            str_list.push(format!("Next funding: {}h {}m", minutes_left / 60, minutes_left % 60));
+        },
+        None => str_list.push(String::from("Funding rate: Unknown"))
+    }
+    str_list.push(String::from("---------------------------------"));
     str_list.push(format!("Long increment: {:.1}", long_increment));
     str_list.push(format!("Short increment: {:.1}", short_increment));
-    str_list.push(format!("---------------------------------"));
-    let msg_str = str_list.join("\n");
-    println!("\x1b[96m{}\x1b[0m", msg_str);
-
-    let input_msg = get_discord_msg().await;
-
-    send_discord_msg(&format!("```{}```XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXaaGGGGGGGGGGGGGGGGGGGGGGYYYYYYYYYYYYYYY", &msg_str)).await;
-    read_user_input(&input_msg).await;
+    str_list.push(String::from("---------------------------------"));
+    str_list.join("\n")
 }
 
 async fn get_discord_msg() -> String {
-    let (token, channel) = { MEM.lock().unwrap().get_discord()};
+    let (token, channel) = GRIDS[0].query(|local_mem| local_mem.get_discord()).await;
+    if token.is_empty() {
+        return String::from("")
+    }
 
     let url = format!("https://discord.com/api/v9/channels/{}/messages?limit=1", channel);
 
@@ -104,7 +485,10 @@ async fn get_discord_msg() -> String {
 }
 
 async fn send_discord_msg(msg: &str) {
-    let (token, channel) = { MEM.lock().unwrap().get_discord()};
+    let (token, channel) = GRIDS[0].query(|local_mem| local_mem.get_discord()).await;
+    if token.is_empty() {
+        return
+    }
 
     let url = format!("https://discord.com/api/v9/channels/{}/messages", channel);
 
@@ -124,12 +508,14 @@ async fn read_user_input(input: &str) {
         "kill" => {
             println!("\x1b[91mUser input: Kill\x1b[0m");
             send_discord_msg("Okay buddy, I will kill my process!012345678901").await;
-            std::process::exit(1);
+            SHUTDOWN.notify_one();
         },
         "close" => {
             println!("\x1b[91mUser input: Close\x1b[0m");
             send_discord_msg("Okay buddy, I will set myself in close_only mode!012345678901").await;
-            { MEM.lock().unwrap().activate_close_only() };
+            for mem in GRIDS.iter() {
+                mem.update(|local_mem| local_mem.activate_close_only()).await;
+            }
         },
         _ => ()
     }
@@ -147,385 +533,457 @@ async fn task_fullrestart() {
     loop {
         sleep(Duration::from_secs(82000)).await;
         println!("\x1b[94mFull restart\x1b[0m");
-        { MEM.lock().unwrap().set_need_restart_true() };
+        for mem in GRIDS.iter() {
+            mem.update(|local_mem| local_mem.set_need_restart_true()).await;
+        }
     }
 }
 
+// The limits are checked against the book, which moves faster than the account updates
+const RISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// A trader that exited or panicked never finishes its pass, the other grids must still be checked
+const RISK_PASS_TIMEOUT: Duration = Duration::from_secs(30);
+const FUNDING_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const ACCOUNT_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// One combined stream for every grid: the bookTickers and mark prices of all the pairs and the
+// shared user data stream
 async fn task_websocket() {
+    let base_url = GRIDS[0].query(|local_mem| local_mem.get_url_websocket()).await;
+    let mut book_tickers: Vec<String> = vec![];
+    let mut mark_prices: Vec<String> = vec![];
+    for mem in GRIDS.iter() {
+        let pair = mem.query(|local_mem| local_mem.get_pair()).await.to_lowercase();
+        book_tickers.push(format!("{}@bookTicker", pair));
+        mark_prices.push(format!("{}@markPrice", pair));
+    }
+    let streams = &book_tickers.iter().chain(mark_prices.iter()).cloned().collect::<Vec<String>>().join("/");
+    let mut socket = connect_websocket(&base_url, streams).await;
+    for mem in GRIDS.iter() {
+        wait_token(mem).await;
+    }
+    let mut last_ping = Instant::now();
     loop {
-        let (base_url, pair) = {
-            let local_mem = &MEM.lock().unwrap();
-            (local_mem.get_url_websocket(), local_mem.get_pair())
+        let msg = match timeout_at(last_ping + PING_TIMEOUT, socket.next()).await {
+            Ok(Some(Ok(msg))) if !msg.is_close() => Ok(msg),
+            Ok(Some(Ok(_))) | Ok(None) => Err(String::from("closed by the server")),
+            Ok(Some(Err(error))) => Err(error.to_string()),
+            Err(_) => Err(format!("no ping for {}s", PING_TIMEOUT.as_secs()))
         };
-        let book_ticker = &format!("{}@bookTicker", pair.to_lowercase());
-        let url = url::Url::parse(&format!("{}/stream?streams={}/{}", base_url, book_ticker, get_listen_key().await)).unwrap();
-        println!("Initiating websocket");
-        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
-        println!("Connected to websocket");
-        let token = wait_token().await;
-        let (quantity_decimal_count, price_decimal_count) = {
-            let local_mem = &MEM.lock().unwrap();
-            (local_mem.get_quantity_decimal_count() as usize, local_mem.get_price_decimal_count() as usize)
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(reason) => {
+                println!("\x1b[91mWebsocket lost: {}\x1b[0m", reason);
+                socket = reconnect_websocket(&base_url, streams).await;
+                last_ping = Instant::now();
+                continue
+            }
         };
-        loop {
-            let msg = socket.next().await.unwrap().unwrap();
-            if msg.is_ping() {
-                if MEM.lock().unwrap().is_restart_needed() {
-                    let url = url::Url::parse(&format!("{}/stream?streams={}/{}", base_url, book_ticker, get_listen_key().await)).unwrap();
-                    println!("Initiating websocket");
-                    (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
-                    println!("Connected to websocket");
-                    { MEM.lock().unwrap().set_need_restart_false() };
+        if msg.is_ping() {
+            last_ping = Instant::now();
+            let mut restart_needed = false;
+            for mem in GRIDS.iter() {
+                restart_needed |= mem.query(|local_mem| local_mem.is_restart_needed()).await;
+            }
+            if restart_needed {
+                socket = reconnect_websocket(&base_url, streams).await;
+                for mem in GRIDS.iter() {
+                    mem.update(|local_mem| local_mem.set_need_restart_false()).await;
+                }
+            }
+        } else if msg.is_text() {
+            let stream_message: StreamMessage = match serde_json::from_str(msg.to_text().unwrap()) {
+                Ok(stream_message) => stream_message,
+                Err(error) => {
+                    println!("\x1b[91mSkipping malformed websocket message ({}): {}\x1b[0m", error, msg);
+                    continue
+                }
+            };
+            if let Some(index) = book_tickers.iter().position(|book_ticker| *book_ticker == stream_message.stream) {
+                match BookTicker::deserialize(&stream_message.data) {
+                    Ok(ticker) => GRIDS[index].send(StateEvent::Tick { top_ask: ticker.best_ask, top_bid: ticker.best_bid, time: ticker.event_time }),
+                    Err(error) => println!("\x1b[91mSkipping malformed bookTicker ({}): {}\x1b[0m", error, stream_message.data)
+                }
+            } else if let Some(index) = mark_prices.iter().position(|mark_price| *mark_price == stream_message.stream) {
+                match MarkPriceUpdate::deserialize(&stream_message.data) {
+                    Ok(update) => GRIDS[index].send(StateEvent::Funding { rate: update.funding_rate, next_funding_time: update.next_funding_time }),
+                    Err(error) => println!("\x1b[91mSkipping malformed markPrice ({}): {}\x1b[0m", error, stream_message.data)
                 }
             } else {
-                let msg_json = json::parse(msg.to_text().unwrap()).unwrap();
-                if msg_json["stream"].as_str().unwrap() == book_ticker {
-                    MEM.lock().unwrap().set_marketprice(msg_json["data"]["a"].as_str().unwrap().parse::<f64>().unwrap(), msg_json["data"]["b"].as_str().unwrap().parse::<f64>().unwrap());
-                } else {
-                    match msg_json["data"]["e"].as_str().unwrap() {
-                        "ORDER_TRADE_UPDATE" => {
-                            match msg_json["data"]["o"]["X"].as_str().unwrap() {
-                                "EXPIRED" => {
-                                    if msg_json["data"]["o"]["ps"].as_str().unwrap() == "LONG" {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "BUY" {
-                                            println!("\x1b[95mOpen long order expired\x1b[0m");
-                                            MEM.lock().unwrap().new_open_long_expired();
-                                        } else {
-                                            println!("\x1b[95mClose long order expired\x1b[0m");
-                                            MEM.lock().unwrap().new_close_long_expired();
-                                        }
-                                    } else {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "SELL" {
-                                            println!("\x1b[95mOpen short order expired\x1b[0m");
-                                            MEM.lock().unwrap().new_open_short_expired();
-                                        } else {
-                                            println!("\x1b[95mClose short order expired\x1b[0m");
-                                            MEM.lock().unwrap().new_close_short_expired();
-                                        }
-                                    }
-                                },
-                                "FILLED" => {
-                                    let comission = msg_json["data"]["o"]["n"].as_str().unwrap().parse::<f64>().unwrap();
-                                    let pnl = msg_json["data"]["o"]["rp"].as_str().unwrap().parse::<f64>().unwrap();
-                                    if msg_json["data"]["o"]["ps"].as_str().unwrap() == "LONG" {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "BUY" {
-                                            println!("\x1b[92mOrder filled OPEN {0:.1$} LONG at {2:.3$} with {4:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, comission * -1.0);
-                                            MEM.lock().unwrap().new_open_long_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                            let _ = tokio::spawn(task_set_last_long_open(msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap()));
-                                        } else {
-                                            println!("\x1b[92mOrder filled CLOSE {0:.1$} LONG at {2:.3$} with {4:.3$} PNL and {5:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, pnl, comission * -1.0);
-                                            MEM.lock().unwrap().new_close_long_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        }
-                                    } else {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "SELL" {
-                                            println!("\x1b[92mOrder filled OPEN {0:.1$} SHORT at {2:.3$} with {4:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, comission * -1.0);
-                                            MEM.lock().unwrap().new_open_short_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                            let _ = tokio::spawn(task_set_last_short_open(msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap()));
-                                        } else {
-                                            println!("\x1b[92mOrder filled CLOSE {0:.1$} SHORT at {2:.3$} with {4:.3$} PNL and {5:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, pnl, comission * -1.0);
-                                            MEM.lock().unwrap().new_close_short_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        }
-                                    }
-                                },
-                                "PARTIALLY_FILLED" => {
-                                    let comission = msg_json["data"]["o"]["n"].as_str().unwrap().parse::<f64>().unwrap();
-                                    let pnl = msg_json["data"]["o"]["rp"].as_str().unwrap().parse::<f64>().unwrap();
-                                    if msg_json["data"]["o"]["ps"].as_str().unwrap() == "LONG" {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "BUY" {
-                                            println!("\x1b[92mOrder PARTIALLY filled OPEN {0:.1$} LONG at {2:.3$} with {4:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, comission * -1.0);
-                                            MEM.lock().unwrap().new_open_long_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        } else {
-                                            println!("\x1b[92mOrder PARTIALLY filled CLOSE {0:.1$} LONG at {2:.3$} with {4:.3$} PNL and {5:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, pnl, comission * -1.0);
-                                            MEM.lock().unwrap().new_close_long_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        }
-                                    } else {
-                                        if msg_json["data"]["o"]["S"].as_str().unwrap() == "SELL" {
-                                            println!("\x1b[92mOrder PARTIALLY filled OPEN {0:.1$} SHORT at {2:.3$} with {4:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, comission * -1.0);
-                                            MEM.lock().unwrap().new_open_short_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        } else {
-                                            println!("\x1b[92mOrder PARTIALLY filled CLOSE {0:.1$} SHORT at {2:.3$} with {4:.3$} PNL and {5:.4} comission\x1b[0m", msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap(), quantity_decimal_count, msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), price_decimal_count, pnl, comission * -1.0);
-                                            MEM.lock().unwrap().new_close_short_filled(msg_json["data"]["o"]["l"].as_str().unwrap().parse::<f64>().unwrap() * msg_json["data"]["o"]["L"].as_str().unwrap().parse::<f64>().unwrap(), comission, pnl);
-                                        }
-                                    }
-                                },
-                                _ => ()
-                            }
-                        },
-                        "ACCOUNT_UPDATE" => {
-                            for i in 0..msg_json["data"]["a"]["B"].len() {
-                                if msg_json["data"]["a"]["B"][i]["a"].as_str().unwrap() == token {
-                                    { MEM.lock().unwrap().set_balance(msg_json["data"]["a"]["B"][i]["wb"].as_str().unwrap().parse::<f64>().unwrap()) };
-                                    break;
-                                }
-                            }
-                            for i in 0..msg_json["data"]["a"]["P"].len() {
-                                if msg_json["data"]["a"]["P"][i]["s"].as_str().unwrap() == pair {
-                                    match msg_json["data"]["a"]["P"][i]["ps"].as_str().unwrap() {
-                                        "LONG" => {
-                                            MEM.lock().unwrap().set_current_longs(
-                                                msg_json["data"]["a"]["P"][i]["pa"].as_str().unwrap().parse::<f64>().unwrap(),
-                                                msg_json["data"]["a"]["P"][i]["ep"].as_str().unwrap().parse::<f64>().unwrap(),
-                                                msg_json["data"]["a"]["P"][i]["up"].as_str().unwrap().parse::<f64>().unwrap()
-                                            );
-                                        },
-                                        "SHORT" => {
-                                            MEM.lock().unwrap().set_current_shorts(
-                                                msg_json["data"]["a"]["P"][i]["pa"].as_str().unwrap().parse::<f64>().unwrap() * -1.0,
-                                                msg_json["data"]["a"]["P"][i]["ep"].as_str().unwrap().parse::<f64>().unwrap(),
-                                                msg_json["data"]["a"]["P"][i]["up"].as_str().unwrap().parse::<f64>().unwrap()
-                                            );
-                                        },
-                                        _ => ()
-                                    }
-                                }
-                            }
-                        },
-                        _ => {
-                            println!("Unknown update: {}", msg_json["data"]);
-                        }
-                    }
+                match UserDataEvent::deserialize(&stream_message.data) {
+                    Ok(UserDataEvent::Unknown) => println!("Unknown update: {}", stream_message.data),
+                    // Every grid picks the parts of the event concerning its pair
+                    Ok(event) => for mem in GRIDS.iter() {
+                        mem.send(StateEvent::User(event.clone()));
+                    },
+                    Err(error) => println!("\x1b[91mSkipping malformed update ({}): {}\x1b[0m", error, stream_message.data)
                 }
             }
         }
     }
 }
 
-async fn task_trader() {
-    println!("Canceling all orders");
-    cancel_all_orders().await;
+// Fills may be missed until the traders have cancelled everything and resynced over REST, so
+// every reconnection goes through a resync
+async fn reconnect_websocket(base_url: &str, streams: &str) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    for mem in GRIDS.iter() {
+        mem.update(|local_mem| local_mem.start_resync()).await;
+    }
+    let socket = connect_websocket(base_url, streams).await;
+    for mem in GRIDS.iter() {
+        mem.update(|local_mem| local_mem.end_resync()).await;
+    }
+    socket
+}
+
+// Retries with backoff until connected, a new listen key is fetched on every attempt
+async fn connect_websocket(base_url: &str, streams: &str) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let connection = match get_listen_key().await {
+            Ok(listen_key) => {
+                let url = url::Url::parse(&format!("{}/stream?streams={}/{}", base_url, streams, listen_key)).unwrap();
+                println!("Initiating websocket");
+                tokio_tungstenite::connect_async(url).await.map_err(|error| error.to_string())
+            },
+            Err(error) => Err(error.to_string())
+        };
+        match connection {
+            Ok((socket, _)) => {
+                println!("Connected to websocket");
+                return socket
+            },
+            Err(error) => {
+                println!("\x1b[91mFailed to connect websocket: {}, retrying in {}s\x1b[0m", error, delay.as_secs());
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+async fn task_trader(mem: MemHandle) {
+    let mem = &mem;
+    let pair = mem.query(|local_mem| local_mem.get_pair()).await;
+    println!("{} canceling all orders", pair);
+    cancel_all_orders(mem).await;
     println!("Getting exchange infos");
-    get_exchange_info().await;
+    get_exchange_info(mem).await.unwrap_or_else(|errors| exit_with_errors(&errors));
+    get_leverage_brackets(mem).await;
+    get_premium_index(mem).await;
     println!("Applying inital settings");
-    apply_intial_settings().await;
+    apply_intial_settings(mem).await.unwrap_or_else(|error| exit_with_errors(&[error]));
     println!("Getting account infos");
-    verify_account_info().await;
+    verify_account_info(mem).await.unwrap_or_else(|error| exit_with_errors(&[error]));
     println!("Posting initial orders");
-    let current_orders = post_initial_orders().await;
-    println!("Bot ready and listening");
-    trader_loop(current_orders).await;
+    let current_orders = post_initial_orders(mem).await;
+    println!("{} bot ready and listening", pair);
+    trader_loop(mem, current_orders).await;
 }
 
-async fn get_exchange_info() {
-    let pair = { MEM.lock().unwrap().get_pair() };
-    let resp = get_request("/fapi/v1/exchangeInfo").await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to get_exchange_info(). Got the following response from server: {}\x1b[0m", resp));
-    let mut triggered = false;
-    let mut can_limit = false;
-    let mut can_gtx = false;
-    let mut token = String::from("");
+async fn get_exchange_info(mem: &MemHandle) -> Result<(), Vec<String>> {
+    let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+    let exchange_info = exchange.get_exchange_info().await.map_err(|error| vec![format!("Failed to get_exchange_info(): {}", error)])?;
+    let symbol = symbol_settings(exchange_info, &pair).map_err(|error| vec![error])?;
+    if let Some(grid) = SETUP.get().unwrap().config.grids.iter().find(|grid| grid.pair == pair) {
+        let errors = config::check_symbol(grid, symbol.price_decimal, symbol.min_quantity, symbol.max_quantity, symbol.max_order_amount);
+        if !errors.is_empty() {
+            return Err(errors)
+        }
+    }
+    mem.update(move |local_mem| local_mem.set_exchange_info(symbol.margin_asset, symbol.price_decimal, symbol.price_precision, symbol.quantity_decimal, symbol.quantity_precision, symbol.min_quantity, symbol.max_quantity, symbol.min_notional)).await;
+    Ok(())
+}
+
+// Without them the margin of the pair is not re-estimated between two account updates, and there
+// is no liquidation price
+async fn get_leverage_brackets(mem: &MemHandle) {
+    let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+    match exchange.get_leverage_brackets(&pair).await {
+        Ok(brackets) => mem.update(move |local_mem| local_mem.set_leverage_brackets(brackets)).await,
+        Err(error) => println!("\x1b[91mFailed to get_leverage_brackets(): {}\x1b[0m", error)
+    }
+}
+
+// The funding rate is known before the first orders, the mark price stream keeps it up to date
+async fn get_premium_index(mem: &MemHandle) {
+    let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+    match exchange.get_premium_index(&pair).await {
+        // Like the mark price stream, the trader is only woken when the bias changed
+        Ok(premium_index) => mem.send(StateEvent::Funding { rate: premium_index.funding_rate, next_funding_time: premium_index.next_funding_time }),
+        Err(error) => println!("\x1b[91mFailed to get_premium_index(): {}\x1b[0m", error)
+    }
+}
+
+// The trading rules of a pair the grid depends on
+struct SymbolSettings {
+    status: String,
+    margin_asset: String,
+    price_decimal: f64,
+    price_precision: i64,
+    quantity_decimal: f64,
+    quantity_precision: i64,
+    min_quantity: f64,
+    max_quantity: f64,
+    max_order_amount: u64,
+    min_notional: f64
+}
+
+// Errors if the pair cannot be traded by the grid
+fn symbol_settings(exchange_info: models::ExchangeInfo, pair: &str) -> Result<SymbolSettings, String> {
+    let symbol_info = match exchange_info.symbols.into_iter().find(|symbol_info| symbol_info.symbol == pair) {
+        Some(symbol_info) => symbol_info,
+        None => return Err(format!("The pair {} does not exists", pair))
+    };
+    if symbol_info.status != "TRADING" {
+        return Err(format!("The pair {} exists but is not available for trading", pair))
+    }
     let mut price_decimal = 0.0;
-    let mut price_decimal_count = 0;
     let mut quantity_decimal = 0.0;
-    let mut quantity_decimal_count = 0;
     let mut min_quantity = 0.0;
     let mut max_quantity = 0.0;
     let mut max_order_amount = 0;
-    for i in 0..json_resp["symbols"].len() {
-        if json_resp["symbols"][i]["symbol"].as_str().unwrap() == pair {
-            triggered = true;
-            if json_resp["symbols"][i]["status"].as_str().unwrap() != "TRADING" {
-                panic!("\x1b[91mERROR: The pair {} exists but is not available for trading\x1b[0m", pair);
-            }
-            token = String::from(json_resp["symbols"][i]["marginAsset"].as_str().unwrap());
-            price_decimal_count = json_resp["symbols"][i]["pricePrecision"].as_i64().unwrap();
-            quantity_decimal_count = json_resp["symbols"][i]["quantityPrecision"].as_i64().unwrap();
-            for j in 0..json_resp["symbols"][i]["filters"].len() {
-                match json_resp["symbols"][i]["filters"][j]["filterType"].as_str().unwrap() {
-                    "PRICE_FILTER" => {
-                        price_decimal = json_resp["symbols"][i]["filters"][j]["tickSize"].as_str().unwrap().parse::<f64>().unwrap();
-                    },
-                    "LOT_SIZE" => {
-                        quantity_decimal = json_resp["symbols"][i]["filters"][j]["stepSize"].as_str().unwrap().parse::<f64>().unwrap();
-                        min_quantity = json_resp["symbols"][i]["filters"][j]["minQty"].as_str().unwrap().parse::<f64>().unwrap();
-                        max_quantity = json_resp["symbols"][i]["filters"][j]["maxQty"].as_str().unwrap().parse::<f64>().unwrap();
-                    },
-                    "MAX_NUM_ORDERS" => {
-                        max_order_amount = json_resp["symbols"][i]["filters"][j]["limit"].as_u64().unwrap();
-                    },
-                    _ => ()
-                }
-            }
-            for j in 0..json_resp["symbols"][i]["orderTypes"].len() {
-                if json_resp["symbols"][i]["orderTypes"][j].as_str().unwrap() == "LIMIT" {
-                    can_limit = true;
-                    break;
-                }
-            }
-            for j in 0..json_resp["symbols"][i]["timeInForce"].len() {
-                if json_resp["symbols"][i]["timeInForce"][j].as_str().unwrap() == "GTX" {
-                    can_gtx = true;
-                    break;
-                }
-            }
-            break;
+    let mut min_notional = 0.0;
+    for filter in symbol_info.filters.iter() {
+        match filter {
+            SymbolFilter::PriceFilter { tick_size } => {
+                price_decimal = *tick_size;
+            },
+            SymbolFilter::LotSize { step_size, min_qty, max_qty } => {
+                quantity_decimal = *step_size;
+                min_quantity = *min_qty;
+                max_quantity = *max_qty;
+            },
+            SymbolFilter::MaxNumOrders { limit } => {
+                max_order_amount = *limit;
+            },
+            SymbolFilter::MinNotional { notional } => {
+                min_notional = *notional;
+            },
+            _ => ()
         }
     }
-    if !triggered {
-        panic!("\x1b[91mERROR: The pair {} does not exists\x1b[0m", pair);
-    }
-    if !can_limit {
-        panic!("\x1b[91mERROR: The pair {} does not support limit orders\x1b[0m", pair);
+    if !symbol_info.order_types.iter().any(|order_type| order_type == "LIMIT") {
+        return Err(format!("The pair {} does not support limit orders", pair))
     }
-    if !can_gtx {
-        panic!("\x1b[91mERROR: The pair {} does not support GTX orders\x1b[0m", pair);
+    if !symbol_info.time_in_force.iter().any(|time_in_force| time_in_force == "GTX") {
+        return Err(format!("The pair {} does not support GTX orders", pair))
     }
-    { MEM.lock().unwrap().set_exchange_info(token, price_decimal, price_decimal_count, quantity_decimal, quantity_decimal_count, min_quantity, max_quantity, max_order_amount) };
+    Ok(SymbolSettings {
+        status: symbol_info.status,
+        margin_asset: symbol_info.margin_asset,
+        price_decimal,
+        price_precision: symbol_info.price_precision,
+        quantity_decimal,
+        quantity_precision: symbol_info.quantity_precision,
+        min_quantity,
+        max_quantity,
+        max_order_amount,
+        min_notional
+    })
 }
 
-async fn apply_intial_settings() {
-    set_leverage().await;
-    set_position_mode().await;
-    set_multiassets_mode().await;
-    set_margin_mode().await;
+// `exchange-info <pair>`, any pair listed by the exchange, not only the ones of the config
+async fn print_exchange_info(pair: &str) {
+    let exchange = GRIDS[0].query(|local_mem| local_mem.get_exchange()).await;
+    let exchange_info = exchange.get_exchange_info().await.unwrap_or_else(|error| exit_with_errors(&[format!("Failed to get_exchange_info(): {}", error)]));
+    let symbol = symbol_settings(exchange_info, pair).unwrap_or_else(|error| exit_with_errors(&[error]));
+    println!("{:-^33}", format!(" {} ", pair));
+    println!("Status: {}", symbol.status);
+    println!("Margin asset: {}", symbol.margin_asset);
+    println!("Tick size: {} ({} decimals)", symbol.price_decimal, symbol.price_precision);
+    println!("Step size: {} ({} decimals)", symbol.quantity_decimal, symbol.quantity_precision);
+    println!("Min quantity: {}", symbol.min_quantity);
+    println!("Max quantity: {}", symbol.max_quantity);
+    println!("Min notional: {}", symbol.min_notional);
+    println!("Max open orders: {}", symbol.max_order_amount);
 }
 
-async fn verify_account_info() {
-    let account_info = get_account_info().await;
-    let (pair, _quantity_decimal_count, _price_decimal_count, token, _max_position) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_pair(), local_mem.get_quantity_decimal_count(), local_mem.get_price_decimal_count(), local_mem.get_token(), local_mem.get_max_position())
-    };
-    {
-        MEM.lock().unwrap().set_vip_level(
-            account_info["feeTier"].as_u64().unwrap()
-        );
-    }
-    for i in 0..account_info["assets"].len() {
-        if account_info["assets"][i]["asset"].as_str().unwrap() == token {
-            MEM.lock().unwrap().set_balance(
-                account_info["assets"][i]["walletBalance"].as_str().unwrap().parse::<f64>().unwrap()
-            );
+// `status`: the account, positions and open orders of every grid, nothing is changed
+async fn print_status() {
+    for mem in GRIDS.iter() {
+        get_exchange_info(mem).await.unwrap_or_else(|errors| exit_with_errors(&errors));
+        if let Some(account_info) = get_account_info(mem).await {
+            mem.update(move |local_mem| local_mem.apply_account_info(&account_info)).await;
         }
-    }
-    for i in 0..account_info["positions"].len() {
-        if account_info["positions"][i]["symbol"].as_str().unwrap() == pair {
-            match account_info["positions"][i]["positionSide"].as_str().unwrap() {
-                "LONG" => {
-                    MEM.lock().unwrap().set_current_longs(
-                        account_info["positions"][i]["positionAmt"].as_str().unwrap().parse::<f64>().unwrap(),
-                        account_info["positions"][i]["entryPrice"].as_str().unwrap().parse::<f64>().unwrap(),
-                        account_info["positions"][i]["unrealizedProfit"].as_str().unwrap().parse::<f64>().unwrap()
-                    );
-                },
-                "SHORT" => {
-                    MEM.lock().unwrap().set_current_shorts(
-                        account_info["positions"][i]["positionAmt"].as_str().unwrap().parse::<f64>().unwrap() * -1.0,
-                        account_info["positions"][i]["entryPrice"].as_str().unwrap().parse::<f64>().unwrap(),
-                        account_info["positions"][i]["unrealizedProfit"].as_str().unwrap().parse::<f64>().unwrap()
-                    );
-                },
-                _ => ()
-            }
+        println!("\x1b[96m{}\x1b[0m", stats_message(mem).await);
+        let (exchange, pair, price_decimal_count, quantity_decimal_count) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair(), local_mem.get_price_decimal_count() as usize, local_mem.get_quantity_decimal_count() as usize)).await;
+        match exchange.get_open_orders(&pair).await {
+            Ok(open_orders) => {
+                println!("{} open orders: {}", pair, open_orders.len());
+                for open_order in open_orders.iter() {
+                    println!("{0:?} {1:?} {2:.3$} at {4:.5$} ({6})", open_order.side, open_order.position_side, open_order.orig_qty, quantity_decimal_count, open_order.price, price_decimal_count, open_order.client_order_id);
+                }
+            },
+            Err(error) => println!("\x1b[91mFailed to get_open_orders(): {}\x1b[0m", error)
         }
     }
-    if !account_info["canTrade"].as_bool().unwrap_or(false) {
-        panic!("\x1b[91mERROR: Your account is not allowed to trade\x1b[0m");
+}
+
+async fn apply_intial_settings(mem: &MemHandle) -> Result<(), String> {
+    set_leverage(mem).await.map_err(|error| format!("Failed to set_leverage(): {}", error))?;
+    set_position_mode(mem).await.map_err(|error| format!("Failed to set_position_mode(): {}", error))?;
+    set_multiassets_mode(mem).await.map_err(|error| format!("Failed to set_multiassets_mode(): {}", error))?;
+    set_margin_mode(mem).await.map_err(|error| format!("Failed to set_margin_mode(): {}", error))
+}
+
+// Only errors when the account cannot trade, a failed request is logged and left to the next call
+async fn verify_account_info(mem: &MemHandle) -> Result<(), String> {
+    let account_info = match get_account_info(mem).await {
+        Some(account_info) => account_info,
+        None => return Ok(())
+    };
+    let can_trade = account_info.can_trade;
+    mem.update(move |local_mem| local_mem.apply_account_info(&account_info)).await;
+    if !can_trade {
+        return Err(String::from("Your account is not allowed to trade"))
     }
-    print_stats().await;
+    print_stats(mem).await;
+    Ok(())
 }
 
-async fn post_initial_orders() -> (Vec<Order>, Vec<Order>, Order, Order) {
+async fn post_initial_orders(mem: &MemHandle) -> (Vec<Order>, Vec<Order>, Vec<Order>, Vec<Order>) {
+    let market_price = wait_marketprice(mem).await;
     let (
         order_amount,
-        order_quantity,
+        (long_ladder, short_ladder),
         margin,
         open_long_pool,
-        close_long_pool,
         open_short_pool,
-        close_short_pool,
-        (close_long_price, close_short_price),
-        quantity_decimal_half
-    ) = { let local_mem = &MEM.lock().unwrap();
-        (
+        (long_close_levels, short_close_levels)
+    ) = mem.query(|local_mem| (
             local_mem.get_order_amount(),
-            local_mem.get_order_quantity(),
+            local_mem.get_ladder_quantities(),
             local_mem.get_margin(),
             local_mem.max_open_long(),
-            local_mem.max_close_long(),
             local_mem.max_open_short(),
-            local_mem.max_close_short(),
-            local_mem.get_close_prices(),
-            local_mem.get_quantity_decimal_half()
-        )
-    };
-    let market_price = wait_marketprice().await;
-    let (long_increment, short_increment) = { MEM.lock().unwrap().get_increments() };
-    let mut upper_price = market_price + margin;
-    let mut lower_price = market_price - margin;
-    let open_long_quantities = distribute_quantity(order_amount as usize, order_quantity, open_long_pool, quantity_decimal_half);
-    let open_short_quantities = distribute_quantity(order_amount as usize, order_quantity, open_short_pool, quantity_decimal_half);
+            local_mem.get_close_levels()
+        )).await;
+    let ((long_increment, short_increment), inventory_shift, (top_ask, top_bid)) = mem.query(|local_mem| (local_mem.get_increments(), local_mem.get_inventory_shift(), (local_mem.get_top_ask(), local_mem.get_top_bid()))).await;
+    // Both sides start around the centre moved by the inventory skew, the lighter one never past the touch
+    let open_long_prices: Vec<f64> = (0..order_amount).map(|i| long_increment.below((market_price - margin + inventory_shift).min(top_bid), i)).collect();
+    let open_short_prices: Vec<f64> = (0..order_amount).map(|i| short_increment.above((market_price + margin + inventory_shift).max(top_ask), i)).collect();
+    let open_long_quantities = long_ladder.distribute(&open_long_prices, open_long_pool);
+    let open_short_quantities = short_ladder.distribute(&open_short_prices, open_short_pool);
     let mut open_long_orders: Vec<Order> = Vec::with_capacity(order_amount as usize);
     let mut open_short_orders: Vec<Order> = Vec::with_capacity(order_amount as usize);
     let mut orders_to_post: Vec<Order> = vec![];
     for i in 0..order_amount as usize {
-        open_long_orders.push(Order::new(lower_price, open_long_quantities[i], true, true));
+        open_long_orders.push(Order::new(open_long_prices[i], open_long_quantities[i], true, true));
         orders_to_post.push(open_long_orders[i].clone());
-        open_short_orders.push(Order::new(upper_price, open_short_quantities[i], true, false));
+        open_short_orders.push(Order::new(open_short_prices[i], open_short_quantities[i], true, false));
         orders_to_post.push(open_short_orders[i].clone());
-        upper_price += short_increment;
-        lower_price -= long_increment;
     }
-    let close_long_order = Order::new(close_long_price, close_long_pool, false, true);
-    orders_to_post.push(close_long_order.clone());
-    let close_short_order = Order::new(close_short_price, close_short_pool, false, false);
-    orders_to_post.push(close_short_order.clone());
-    post_multiple_orders(orders_to_post).await;
-    (open_long_orders, open_short_orders, close_long_order, close_short_order)
+    let close_long_orders: Vec<Order> = long_close_levels.into_iter().map(|(price, quantity)| Order::new(price, quantity, false, true)).collect();
+    orders_to_post.extend(close_long_orders.iter().cloned());
+    let close_short_orders: Vec<Order> = short_close_levels.into_iter().map(|(price, quantity)| Order::new(price, quantity, false, false)).collect();
+    orders_to_post.extend(close_short_orders.iter().cloned());
+    post_multiple_orders(mem, orders_to_post).await;
+    (open_long_orders, open_short_orders, close_long_orders, close_short_orders)
 }
 
-async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
-    let (mut last_long_increment, mut last_short_increment) = { MEM.lock().unwrap().get_increments() };
+#[allow(clippy::needless_range_loop)]
+async fn trader_loop(mem: &MemHandle, current_orders: (Vec<Order>, Vec<Order>, Vec<Order>, Vec<Order>)) {
+    let (mut last_long_increment, mut last_short_increment) = mem.query(|local_mem| local_mem.get_increments()).await;
     let (
         mut open_long_orders,
         mut open_short_orders,
-        mut close_long_order,
-        mut close_short_order
+        mut close_long_orders,
+        mut close_short_orders
     ) = current_orders;
     let (
-        order_quantity, 
-        order_amount, 
-        quantity_decimal_half
-    ) = { let local_mem = &MEM.lock().unwrap();
-        (
-            local_mem.get_order_quantity(),
+        (mut long_ladder, mut short_ladder),
+        order_amount,
+        reconcile_interval
+    ) = mem.query(|local_mem| (
+            local_mem.get_ladder_quantities(),
             local_mem.get_order_amount(),
-            local_mem.get_quantity_decimal_half()
-        )
-    };
+            local_mem.get_reconcile_interval()
+        )).await;
+    let mut last_open_limits = mem.query(|local_mem| local_mem.get_open_limits()).await;
+    let mut reconciler = Reconciler::new(reconcile_interval);
+    let mut changes = mem.subscribe();
     loop {
-        sleep(Duration::ZERO).await;
-        if MEM.lock().unwrap().is_oveflowing() {
-            cancel_all_orders().await;
+        let version = *changes.borrow_and_update();
+        let (shutting_down, halted, overflowing) = mem.query(|local_mem| (local_mem.is_shutting_down(), local_mem.is_halted(), local_mem.is_oveflowing())).await;
+        if shutting_down {
+            println!("Trader stopped");
+            return
+        }
+        if halted {
+            // The risk manager owns the grid now, nothing is posted until a restart
+            mem.pass_done(version);
+            let _ = changes.changed().await;
+            continue
+        }
+        if overflowing {
+            cancel_all_orders(mem).await;
             sleep(Duration::from_secs(2)).await;
-            cancel_all_orders().await;
+            cancel_all_orders(mem).await;
             sleep(Duration::from_secs(15)).await;
-            { MEM.lock().unwrap().reset_all() };
+            wait_resync(mem).await;
+            mem.update(|local_mem| local_mem.reset_all()).await;
+            // The book stays empty until the account can trade again
+            while let Err(error) = verify_account_info(mem).await {
+                println!("\x1b[91m{}, retrying in {}s\x1b[0m", error, ACCOUNT_RETRY_DELAY.as_secs());
+                sleep(ACCOUNT_RETRY_DELAY).await;
+            }
             println!("Reposting orders");
-            (open_long_orders, open_short_orders, close_long_order, close_short_order) = post_initial_orders().await;
+            (open_long_orders, open_short_orders, close_long_orders, close_short_orders) = post_initial_orders(mem).await;
             println!("Bot ready and listening");
         } else {
+            if reconciler.is_due() {
+                let local_orders = open_long_orders.iter().chain(open_short_orders.iter()).chain(close_long_orders.iter()).chain(close_short_orders.iter()).collect();
+                reconcile_orders(mem, &mut reconciler, local_orders).await;
+            }
+            let open_limits = mem.query(|local_mem| local_mem.get_open_limits()).await;
+            if open_limits.imbalance != last_open_limits.imbalance {
+                println!("\x1b[95mInventory imbalance: {:.0}% -> {:.0}%\x1b[0m", last_open_limits.imbalance * 100.0, open_limits.imbalance * 100.0);
+            }
+            // The funding and inventory skews change the ladder quantities themselves
+            if open_limits.funding_bias != last_open_limits.funding_bias || open_limits.imbalance != last_open_limits.imbalance {
+                (long_ladder, short_ladder) = mem.query(|local_mem| local_mem.get_ladder_quantities()).await;
+            }
+            // A trim moves the close ladders by less than the drift they are reposted on
+            let (reprice_long_closes, reprice_short_closes) = (open_limits.funding_bias.trim_long != last_open_limits.funding_bias.trim_long, open_limits.funding_bias.trim_short != last_open_limits.funding_bias.trim_short);
             let (
                 top_ask,
                 top_bid,
                 mut open_long_shift_down,
                 mut open_short_shift_up,
-                close_long_repost,
-                close_short_repost,
-                current_longs,
-                current_shorts,
-                long_close_price,
-                short_close_price,
+                finished_closes,
+                long_close_levels,
+                short_close_levels,
                 long_increment,
                 short_increment
-            ) = { MEM.lock().unwrap().get_updates() };
+            ) = mem.query(|local_mem| local_mem.get_updates()).await;
+            // The open ladders follow the touch moved by the inventory skew, the heavier side away
+            // from the market and the lighter one never past it
+            let (inventory_shift, (price_drift, quantity_drift)) = mem.query(|local_mem| (local_mem.get_inventory_shift(), local_mem.get_close_drift())).await;
+            let (top_ask, top_bid) = (top_ask + inventory_shift.max(0.0), top_bid + inventory_shift.min(0.0));
             let mut orders_to_cancel: Vec<Order> = vec![];
             let mut orders_to_post: Vec<Order> = vec![];
+            // A new imbalance step moves the centre, both ladders are repriced around it at once
+            // instead of keeping their resting levels until they fill
+            if open_limits.imbalance != last_open_limits.imbalance {
+                println!("Repricing open ladders around the skewed centre");
+                (last_long_increment, last_short_increment) = (long_increment, short_increment);
+                (open_long_shift_down, open_short_shift_up) = (0, 0);
+                let (open_long_pool, open_short_pool) = mem.query(|local_mem| (local_mem.max_open_long(), local_mem.max_open_short())).await;
+                for (cancel, post) in [
+                    reprice_ladder(&mut open_long_orders, true, &last_long_increment, Some(top_bid), &long_ladder, open_long_pool),
+                    reprice_ladder(&mut open_short_orders, false, &last_short_increment, Some(top_ask), &short_ladder, open_short_pool)
+                ] {
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
+                }
+            }
             if open_long_shift_down == 0 && !open_long_orders[0].is_real() {
                 let mut target_price = open_long_orders[0].get_price();
                 loop {
                     if target_price > top_bid {
                         open_long_shift_down += 1;
-                        target_price -= last_long_increment;
+                        target_price = last_long_increment.below(target_price, 1);
                     } else {
                         break;
                     }
@@ -536,7 +994,7 @@ async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
                 loop {
                     if target_price < top_ask {
                         open_short_shift_up += 1;
-                        target_price += last_short_increment;
+                        target_price = last_short_increment.above(target_price, 1);
                     } else {
                         break;
                     }
@@ -547,42 +1005,34 @@ async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
                 if last_long_increment != long_increment {    // Increment change START ------------------------
                     println!("\x1b[95mLong increment change: {} -> {}\x1b[0m", last_long_increment, long_increment);
                     last_long_increment = long_increment;
-                    let open_long_pool = { MEM.lock().unwrap().max_open_long() };
-                    let quantities = distribute_quantity(order_amount as usize, order_quantity, open_long_pool, quantity_decimal_half);
-                    for i in 0..order_amount as usize {
-                        orders_to_cancel.push(open_long_orders[i].clone());
-                        open_long_orders[i] = Order::new(top_bid - (last_long_increment * i as f64), quantities[i], true, true);
-                        orders_to_post.push(open_long_orders[i].clone());
-                    }
+                    let open_long_pool = mem.query(|local_mem| local_mem.max_open_long()).await;
+                    let (cancel, post) = reprice_ladder(&mut open_long_orders, true, &last_long_increment, Some(top_bid), &long_ladder, open_long_pool);
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
                 } else {                                        // Increment change END ------------------------
                     println!("Shifting open longs down {}", open_long_shift_down);
                     if open_long_shift_down >= order_amount {
                         for i in 0..order_amount as usize {
-                            open_long_orders[i] = Order::new(open_long_orders[i].get_price() - open_long_shift_down as f64 * last_long_increment, 0.0, true, true);
+                            open_long_orders[i] = Order::new(last_long_increment.below(open_long_orders[i].get_price(), open_long_shift_down), 0.0, true, true);
                         }
                     } else {
                         for i in 0..open_long_shift_down as usize {
-                            open_long_orders[i] = Order::new(open_long_orders[i].get_price() - order_amount as f64 * last_long_increment, 0.0, true, true);
+                            open_long_orders[i] = Order::new(last_long_increment.below(open_long_orders[i].get_price(), order_amount), 0.0, true, true);
                         }
                         open_long_orders.rotate_left(open_long_shift_down as usize);
                     }
-                    let open_long_pool = { MEM.lock().unwrap().max_open_long() };
-                    let quantities = distribute_quantity(order_amount as usize, order_quantity, open_long_pool, quantity_decimal_half);
-                    for i in 0..order_amount as usize {
-                        if open_long_orders[i].get_quantity() != quantities[i] {
-                            orders_to_cancel.push(open_long_orders[i].clone());
-                            open_long_orders[i] = Order::new(open_long_orders[i].get_price(), quantities[i], true, true);
-                            orders_to_post.push(open_long_orders[i].clone());
-                        }
-                    }
+                    let open_long_pool = mem.query(|local_mem| local_mem.max_open_long()).await;
+                    let (cancel, post) = reprice_ladder(&mut open_long_orders, true, &last_long_increment, None, &long_ladder, open_long_pool);
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
                 }
             } else {
                 let mut open_long_shift_up = 0;
-                let mut target_price = open_long_orders[0].get_price() + last_long_increment;
+                let mut target_price = last_long_increment.above(open_long_orders[0].get_price(), 1);
                 loop {
                     if target_price <= top_bid {
                         open_long_shift_up += 1;
-                        target_price += last_long_increment;
+                        target_price = last_long_increment.above(target_price, 1);
                     } else {
                         break;
                     }
@@ -591,36 +1041,28 @@ async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
                     if last_long_increment != long_increment {    // Increment change START ------------------------
                         println!("\x1b[95mLong increment change: {} -> {}\x1b[0m", last_long_increment, long_increment);
                         last_long_increment = long_increment;
-                        let open_long_pool = { MEM.lock().unwrap().max_open_long() };
-                        let quantities = distribute_quantity(order_amount as usize, order_quantity, open_long_pool, quantity_decimal_half);
-                        for i in 0..order_amount as usize {
-                            orders_to_cancel.push(open_long_orders[i].clone());
-                            open_long_orders[i] = Order::new(top_bid - (last_long_increment * i as f64), quantities[i], true, true);
-                            orders_to_post.push(open_long_orders[i].clone());
-                        }
+                        let open_long_pool = mem.query(|local_mem| local_mem.max_open_long()).await;
+                        let (cancel, post) = reprice_ladder(&mut open_long_orders, true, &last_long_increment, Some(top_bid), &long_ladder, open_long_pool);
+                        orders_to_cancel.extend(cancel);
+                        orders_to_post.extend(post);
                     } else {                                        // Increment change END ------------------------
                         println!("Shifting open longs up {}", open_long_shift_up);
                         if open_long_shift_up >= order_amount {
                             for i in 0..order_amount as usize {
                                 orders_to_cancel.push(open_long_orders[i].clone());
-                                open_long_orders[i] = Order::new(open_long_orders[i].get_price() + open_long_shift_up as f64 * last_long_increment, 0.0, true, true);
+                                open_long_orders[i] = Order::new(last_long_increment.above(open_long_orders[i].get_price(), open_long_shift_up), 0.0, true, true);
                             }
                         } else {
                             open_long_orders.rotate_right(open_long_shift_up as usize);
                             for i in 0..open_long_shift_up as usize {
                                 orders_to_cancel.push(open_long_orders[i].clone());
-                                open_long_orders[i] = Order::new(open_long_orders[i].get_price() + order_amount as f64 * last_long_increment, 0.0, true, true);
-                            }
-                        }
-                        let open_long_pool = { MEM.lock().unwrap().max_open_long() };
-                        let quantities = distribute_quantity(order_amount as usize, order_quantity, open_long_pool, quantity_decimal_half);
-                        for i in 0..order_amount as usize {
-                            if open_long_orders[i].get_quantity() != quantities[i] {
-                                orders_to_cancel.push(open_long_orders[i].clone());
-                                open_long_orders[i] = Order::new(open_long_orders[i].get_price(), quantities[i], true, true);
-                                orders_to_post.push(open_long_orders[i].clone());
+                                open_long_orders[i] = Order::new(last_long_increment.above(open_long_orders[i].get_price(), order_amount), 0.0, true, true);
                             }
                         }
+                        let open_long_pool = mem.query(|local_mem| local_mem.max_open_long()).await;
+                        let (cancel, post) = reprice_ladder(&mut open_long_orders, true, &last_long_increment, None, &long_ladder, open_long_pool);
+                        orders_to_cancel.extend(cancel);
+                        orders_to_post.extend(post);
                     }
                 }
             }
@@ -630,42 +1072,34 @@ async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
                 if last_short_increment != short_increment {    // Increment change START ------------------------
                     println!("\x1b[95mShort increment change: {} -> {}\x1b[0m", last_short_increment, short_increment);
                     last_short_increment = short_increment;
-                    let open_short_pool = { MEM.lock().unwrap().max_open_short() };
-                    let quantities = distribute_quantity(order_amount as usize, order_quantity, open_short_pool, quantity_decimal_half);
-                    for i in 0..order_amount as usize {
-                        orders_to_cancel.push(open_short_orders[i].clone());
-                        open_short_orders[i] = Order::new(top_ask + (last_short_increment * i as f64), quantities[i], true, false);
-                        orders_to_post.push(open_short_orders[i].clone());
-                    }
+                    let open_short_pool = mem.query(|local_mem| local_mem.max_open_short()).await;
+                    let (cancel, post) = reprice_ladder(&mut open_short_orders, false, &last_short_increment, Some(top_ask), &short_ladder, open_short_pool);
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
                 } else {                                        // Increment change END ------------------------
                     println!("Shifting open shorts up {}", open_short_shift_up);
                     if open_short_shift_up >= order_amount {
                         for i in 0..order_amount as usize {
-                            open_short_orders[i] = Order::new(open_short_orders[i].get_price() + open_short_shift_up as f64 * last_short_increment, 0.0, true, false);
+                            open_short_orders[i] = Order::new(last_short_increment.above(open_short_orders[i].get_price(), open_short_shift_up), 0.0, true, false);
                         }
                     } else {
                         for i in 0..open_short_shift_up as usize {
-                            open_short_orders[i] = Order::new(open_short_orders[i].get_price() + order_amount as f64 * last_short_increment, 0.0, true, false);
+                            open_short_orders[i] = Order::new(last_short_increment.above(open_short_orders[i].get_price(), order_amount), 0.0, true, false);
                         }
                         open_short_orders.rotate_left(open_short_shift_up as usize);
                     }
-                    let open_short_pool = { MEM.lock().unwrap().max_open_short() };
-                    let quantities = distribute_quantity(order_amount as usize, order_quantity, open_short_pool, quantity_decimal_half);
-                    for i in 0..order_amount as usize {
-                        if open_short_orders[i].get_quantity() != quantities[i] {
-                            orders_to_cancel.push(open_short_orders[i].clone());
-                            open_short_orders[i] = Order::new(open_short_orders[i].get_price(), quantities[i], true, false);
-                            orders_to_post.push(open_short_orders[i].clone());
-                        }
-                    }
+                    let open_short_pool = mem.query(|local_mem| local_mem.max_open_short()).await;
+                    let (cancel, post) = reprice_ladder(&mut open_short_orders, false, &last_short_increment, None, &short_ladder, open_short_pool);
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
                 }
             } else {
                 let mut open_short_shift_down = 0;
-                let mut target_price = open_short_orders[0].get_price() - last_short_increment;
+                let mut target_price = last_short_increment.below(open_short_orders[0].get_price(), 1);
                 loop {
                     if target_price >= top_ask {
                         open_short_shift_down += 1;
-                        target_price -= last_short_increment;
+                        target_price = last_short_increment.below(target_price, 1);
                     } else {
                         break;
                     }
@@ -674,429 +1108,240 @@ async fn trader_loop(current_orders: (Vec<Order>, Vec<Order>, Order, Order)) {
                     if last_short_increment != short_increment {    // Increment change START ------------------------
                         println!("\x1b[95mShort increment change: {} -> {}\x1b[0m", last_short_increment, short_increment);
                         last_short_increment = short_increment;
-                        let open_short_pool = { MEM.lock().unwrap().max_open_short() };
-                        let quantities = distribute_quantity(order_amount as usize, order_quantity, open_short_pool, quantity_decimal_half);
-                        for i in 0..order_amount as usize {
-                            orders_to_cancel.push(open_short_orders[i].clone());
-                            open_short_orders[i] = Order::new(top_ask + (last_short_increment * i as f64), quantities[i], true, false);
-                            orders_to_post.push(open_short_orders[i].clone());
-                        }
+                        let open_short_pool = mem.query(|local_mem| local_mem.max_open_short()).await;
+                        let (cancel, post) = reprice_ladder(&mut open_short_orders, false, &last_short_increment, Some(top_ask), &short_ladder, open_short_pool);
+                        orders_to_cancel.extend(cancel);
+                        orders_to_post.extend(post);
                     } else {                                        // Increment change END ------------------------
                         println!("Shifting open shorts down {}", open_short_shift_down);
                         if open_short_shift_down >= order_amount {
                             for i in 0..order_amount as usize {
                                 orders_to_cancel.push(open_short_orders[i].clone());
-                                open_short_orders[i] = Order::new(open_short_orders[i].get_price() - open_short_shift_down as f64 * last_short_increment, 0.0, true, false);
+                                open_short_orders[i] = Order::new(last_short_increment.below(open_short_orders[i].get_price(), open_short_shift_down), 0.0, true, false);
                             }
                         } else {
                             open_short_orders.rotate_right(open_short_shift_down as usize);
                             for i in 0..open_short_shift_down as usize {
                                 orders_to_cancel.push(open_short_orders[i].clone());
-                                open_short_orders[i] = Order::new(open_short_orders[i].get_price() - order_amount as f64 * last_short_increment, 0.0, true, false);
-                            }
-                        }
-                        let open_short_pool = { MEM.lock().unwrap().max_open_short() };
-                        let quantities = distribute_quantity(order_amount as usize, order_quantity, open_short_pool, quantity_decimal_half);
-                        for i in 0..order_amount as usize {
-                            if open_short_orders[i].get_quantity() != quantities[i] {
-                                orders_to_cancel.push(open_short_orders[i].clone());
-                                open_short_orders[i] = Order::new(open_short_orders[i].get_price(), quantities[i], true, false);
-                                orders_to_post.push(open_short_orders[i].clone());
+                                open_short_orders[i] = Order::new(last_short_increment.below(open_short_orders[i].get_price(), order_amount), 0.0, true, false);
                             }
                         }
+                        let open_short_pool = mem.query(|local_mem| local_mem.max_open_short()).await;
+                        let (cancel, post) = reprice_ladder(&mut open_short_orders, false, &last_short_increment, None, &short_ladder, open_short_pool);
+                        orders_to_cancel.extend(cancel);
+                        orders_to_post.extend(post);
                     }
                 }
             }
             // Open Shorts   ----------------------------------------------------------------------------------------------
+            // Close-only, a margin cut or the funding skew changes the open pools, the levels already
+            // posted would keep their size until they shift
+            if open_limits != last_open_limits {
+                last_open_limits = open_limits;
+                let (open_long_pool, open_short_pool) = mem.query(|local_mem| (local_mem.max_open_long(), local_mem.max_open_short())).await;
+                for (cancel, post) in [
+                    reprice_ladder(&mut open_long_orders, true, &last_long_increment, None, &long_ladder, open_long_pool),
+                    reprice_ladder(&mut open_short_orders, false, &last_short_increment, None, &short_ladder, open_short_pool)
+                ] {
+                    orders_to_cancel.extend(cancel);
+                    orders_to_post.extend(post);
+                }
+            }
             // Close Longs   ----------------------------------------------------------------------------------------------
-            if close_long_repost || (current_longs - close_long_order.get_quantity()).abs() > 0.005 || (long_close_price - close_long_order.get_price()).abs() > 5.0 {
-                if !close_long_repost {
-                    orders_to_cancel.push(close_long_order);
+            for (i, (price, quantity)) in long_close_levels.into_iter().enumerate() {
+                let finished = finished_closes.contains(&close_long_orders[i].get_id());
+                if finished || reprice_long_closes || close_long_orders[i].is_real() != (quantity > 0.0) || (quantity - close_long_orders[i].get_quantity()).abs() > quantity_drift || (price - close_long_orders[i].get_price()).abs() > price_drift {
+                    if !finished {
+                        orders_to_cancel.push(close_long_orders[i].clone());
+                    }
+                    close_long_orders[i] = Order::new(price, quantity, false, true);
+                    orders_to_post.push(close_long_orders[i].clone());
                 }
-                close_long_order = Order::new(long_close_price, current_longs, false, true);
-                orders_to_post.push(close_long_order.clone());
             }
             // Close Longs   ----------------------------------------------------------------------------------------------
             // Close Shorts  ----------------------------------------------------------------------------------------------
-            if close_short_repost || (current_shorts - close_short_order.get_quantity()).abs() > 0.005 || (short_close_price - close_short_order.get_price()).abs() > 5.0 {
-                if !close_short_repost {
-                    orders_to_cancel.push(close_short_order);
+            for (i, (price, quantity)) in short_close_levels.into_iter().enumerate() {
+                let finished = finished_closes.contains(&close_short_orders[i].get_id());
+                if finished || reprice_short_closes || close_short_orders[i].is_real() != (quantity > 0.0) || (quantity - close_short_orders[i].get_quantity()).abs() > quantity_drift || (price - close_short_orders[i].get_price()).abs() > price_drift {
+                    if !finished {
+                        orders_to_cancel.push(close_short_orders[i].clone());
+                    }
+                    close_short_orders[i] = Order::new(price, quantity, false, false);
+                    orders_to_post.push(close_short_orders[i].clone());
                 }
-                close_short_order = Order::new(short_close_price, current_shorts, false, false);
-                orders_to_post.push(close_short_order.clone());
             }
             // Close Shorts  ----------------------------------------------------------------------------------------------
-            cancel_multiple_orders(orders_to_cancel).await;
-            post_multiple_orders(orders_to_post).await;
-            sleep(Duration::ZERO).await;
+            cancel_multiple_orders(mem, orders_to_cancel).await;
+            post_multiple_orders(mem, orders_to_post).await;
         }
-    }
-}
-
-fn distribute_quantity(amount: usize, target_quantity: f64, mut pool: f64, quantity_decimal_half: f64) -> Vec<f64> {
-    let mut quantities: Vec<f64> = vec![0.0; amount];
-    for i in 0..amount {
-        if pool > target_quantity {
-            quantities[i] = target_quantity;
-            pool -= target_quantity;
-        } else {
-            if pool >= quantity_decimal_half {
-                quantities[i] = pool;
-            }
-            break;
+        mem.pass_done(version);
+        // Sleeps until the state changes or the reconciler is due
+        match reconciler.time_until_due() {
+            Some(delay) => { let _ = timeout(delay, changes.changed()).await; },
+            None => { let _ = changes.changed().await; }
         }
     }
-    quantities
 }
 
-async fn wait_marketprice() -> f64 {
-    loop {
-        sleep(Duration::ZERO).await;
-        let marketprice = { MEM.lock().unwrap().get_marketprice() };
+async fn wait_marketprice(mem: &MemHandle) -> f64 {
+    mem.wait_for(|local_mem| {
+        let marketprice = local_mem.get_marketprice();
         if marketprice > 0.0 {
-            MEM.lock().unwrap().set_last_prices();
-            return marketprice
-        }
-    }
-}
-
-async fn wait_token() -> String {
-    loop {
-        sleep(Duration::ZERO).await;
-        let token = { MEM.lock().unwrap().get_token() };
-        if token != "" {
-            return token
+            local_mem.set_last_prices();
+            Some(marketprice)
+        } else {
+            None
         }
-    }
-}
-
-fn get_timestamp() -> u128 {
-    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() - 1000
-}
-
-async fn cancel_all_orders() {
-    let resp = delete_request("/fapi/v1/allOpenOrders", &format!("symbol={}&", { MEM.lock().unwrap().get_pair() })).await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to cancel_all_orders(). Got the following response from server: {}\x1b[0m", resp));
-    if json_resp["code"].as_i64().expect(&format!("\x1b[91mERROR: Failed to cancel_all_orders(). Got the following response from server: {}\x1b[0m", resp)) != 200 {
-        panic!("\x1b[91mERROR: Failed to cancel_all_orders(). Got the following response from server: {}\x1b[0m", resp);
-    }
+    }).await
 }
 
-async fn set_leverage() {
-    let (pair, leverage) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_pair(), local_mem.get_leverage())
-    };
-    let resp = post_request("/fapi/v1/leverage", &format!("symbol={}&leverage={}&", pair, leverage)).await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to set_leverage(). Got the following response from server: {}\x1b[0m", resp));
-    if json_resp["leverage"].as_u64().expect(&format!("\x1b[91mERROR: Failed to set_leverage(). Got the following response from server: {}\x1b[0m", resp)) != leverage {
-        panic!("\x1b[91mERROR: Failed to set_leverage(). Got the following response from server: {}\x1b[0m", resp);
-    }
+// Waits until the websocket is connected again after a disconnection
+async fn wait_resync(mem: &MemHandle) {
+    mem.wait_for(|local_mem| if local_mem.is_resyncing() { None } else { Some(()) }).await
 }
 
-async fn set_position_mode() {
-    let resp = post_request("/fapi/v1/positionSide/dual", "dualSidePosition=true&").await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to set_position_mode(). Got the following response from server: {}\x1b[0m", resp));
-    let code = json_resp["code"].as_i64().expect(&format!("\x1b[91mERROR: Failed to set_position_mode(). Got the following response from server: {}\x1b[0m", resp));
-    if code != 200 && code != -4059 {
-        panic!("\x1b[91mERROR: Failed to set_position_mode(). Got the following response from server: {}\x1b[0m", resp);
-    }
+async fn wait_token(mem: &MemHandle) -> String {
+    mem.wait_for(|local_mem| Some(local_mem.get_token()).filter(|token| !token.is_empty())).await
 }
 
-async fn set_multiassets_mode() {
-    let resp = post_request("/fapi/v1/multiAssetsMargin", "multiAssetsMargin=false&").await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to set_multiassets_mode(). Got the following response from server: {}\x1b[0m", resp));
-    let code = json_resp["code"].as_i64().expect(&format!("\x1b[91mERROR: Failed to set_multiassets_mode(). Got the following response from server: {}\x1b[0m", resp));
-    if code != 200 && code != -4171 {
-        panic!("\x1b[91mERROR: Failed to set_multiassets_mode(). Got the following response from server: {}\x1b[0m", resp);
+async fn cancel_all_orders(mem: &MemHandle) {
+    let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+    if let Err(error) = exchange.cancel_all_orders(&pair).await {
+        println!("\x1b[91mFailed to cancel_all_orders(): {}\x1b[0m", error);
     }
 }
 
-async fn set_margin_mode() {
-    let resp = post_request("/fapi/v1/marginType", &format!("symbol={}&marginType=CROSSED&", { &MEM.lock().unwrap().get_pair() })).await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to set_margin_mode(). Got the following response from server: {}\x1b[0m", resp));
-    let code = json_resp["code"].as_i64().expect(&format!("\x1b[91mERROR: Failed to set_margin_mode(). Got the following response from server: {}\x1b[0m", resp));
-    if code != 200 && code != -4046 {
-        panic!("\x1b[91mERROR: Failed to set_margin_mode(). Got the following response from server: {}\x1b[0m", resp);
-    }
+async fn set_leverage(mem: &MemHandle) -> Result<(), GateError> {
+    let (exchange, pair, leverage) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair(), local_mem.get_leverage())).await;
+    exchange.set_leverage(&pair, leverage).await
 }
 
-async fn get_account_info() -> json::JsonValue {
-    let resp = get_request("/fapi/v2/account").await;
-    json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to get_account_info(). Got the following response from server: {}\x1b[0m", resp))
+async fn set_position_mode(mem: &MemHandle) -> Result<(), GateError> {
+    let exchange = mem.query(|local_mem| local_mem.get_exchange()).await;
+    exchange.set_position_mode().await
 }
 
-async fn get_listen_key() -> String {
-    let resp = post_request("/fapi/v1/listenKey", "").await;
-    let json_resp = json::parse(&resp).expect(&format!("\x1b[91mERROR: Failed to get_listen_key(). Got the following response from server: {}\x1b[0m", resp));
-    String::from(json_resp["listenKey"].as_str().expect(&format!("\x1b[91mERROR: Failed to get_listen_key(). Got the following response from server: {}\x1b[0m", resp)))
+async fn set_multiassets_mode(mem: &MemHandle) -> Result<(), GateError> {
+    let exchange = mem.query(|local_mem| local_mem.get_exchange()).await;
+    exchange.set_multiassets_mode().await
 }
 
-async fn send_keepalive() -> json::JsonValue {
-    put_request("/fapi/v1/listenKey").await
+async fn set_margin_mode(mem: &MemHandle) -> Result<(), GateError> {
+    let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+    exchange.set_margin_mode(&pair).await
 }
 
-async fn delete_request(endpoint: &str, params: &str) -> String {
-    let (client, header,mut signature, base_url) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header(), local_mem.get_signature(), local_mem.get_url_request())
-    };
-    let payload = format!("{}timestamp={}", params, get_timestamp());
-    signature.update(payload.as_bytes());
-    let url = format!("{}{}?{}&signature={}", base_url, endpoint, payload, hex_encode(signature.finalize().into_bytes()));
-    client.delete(url).headers(header).send().await.unwrap().text().await.unwrap()
+async fn get_account_info(mem: &MemHandle) -> Option<AccountInfo> {
+    let exchange = mem.query(|local_mem| local_mem.get_exchange()).await;
+    match exchange.get_account_info().await {
+        Ok(account_info) => Some(account_info),
+        Err(error) => {
+            println!("\x1b[91mFailed to get_account_info(): {}\x1b[0m", error);
+            None
+        }
+    }
 }
 
-async fn get_request(endpoint: &str) -> String {
-    let (client, header,mut signature, base_url) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header(), local_mem.get_signature(), local_mem.get_url_request())
-    };
-    let payload = format!("timestamp={}", get_timestamp());
-    signature.update(payload.as_bytes());
-    let url = format!("{}{}?{}&signature={}", base_url, endpoint, payload, hex_encode(signature.finalize().into_bytes()));
-    client.get(url).headers(header).send().await.unwrap().text().await.unwrap()
+async fn get_listen_key() -> Result<String, GateError> {
+    let exchange = GRIDS[0].query(|local_mem| local_mem.get_exchange()).await;
+    exchange.get_listen_key().await
 }
 
-async fn post_request(endpoint: &str, params: &str) -> String {
-    let (client, header,mut signature, base_url) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header(), local_mem.get_signature(), local_mem.get_url_request())
-    };
-    let payload = format!("{}timestamp={}", params, get_timestamp());
-    signature.update(payload.as_bytes());
-    let url = format!("{}{}?{}&signature={}", base_url, endpoint, payload, hex_encode(signature.finalize().into_bytes()));
-    client.post(url).headers(header).send().await.unwrap().text().await.unwrap()
+async fn send_keepalive() {
+    let exchange = GRIDS[0].query(|local_mem| local_mem.get_exchange()).await;
+    if let Err(error) = exchange.keepalive_listen_key().await {
+        println!("\x1b[91mFailed to send_keepalive(): {}\x1b[0m", error);
+    }
 }
 
-async fn put_request(endpoint: &str) -> json::JsonValue {
-    let (client, header,mut signature, base_url) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header(), local_mem.get_signature(), local_mem.get_url_request())
+// Sizes an open ladder from its pool, around a new centre or at its current prices, and returns the
+// orders to cancel and to post. Only the levels whose price or quantity changed are replaced.
+fn reprice_ladder(orders: &mut [Order], is_long: bool, increment: &grid::Step, centre: Option<f64>, ladder: &grid::LadderQuantities, pool: f64) -> (Vec<Order>, Vec<Order>) {
+    let prices: Vec<f64> = match centre {
+        Some(centre) => (0..orders.len() as u64).map(|i| if is_long { increment.below(centre, i) } else { increment.above(centre, i) }).collect(),
+        None => orders.iter().map(|order| order.get_price()).collect()
     };
-    let payload = format!("timestamp={}", get_timestamp());
-    signature.update(payload.as_bytes());
-    let url = format!("{}{}?{}&signature={}", base_url, endpoint, payload, hex_encode(signature.finalize().into_bytes()));
-    let resp: &str = &client.put(url).headers(header).send().await.unwrap().text().await.unwrap();
-    json::parse(resp).unwrap()
-}
-
-async fn post_multiple_orders(orders_to_post: Vec<Order>) {
-    if orders_to_post.len() > 0 {
-        let (signature, base_url, pair, price_decimal_count, quantity_decimal_count) = {
-            let local_mem = &MEM.lock().unwrap();
-            (local_mem.get_signature(), local_mem.get_url_request(), local_mem.get_pair(), local_mem.get_price_decimal_count(), local_mem.get_quantity_decimal_count())
-        };
-        let mut vec_of_orders = vec![];
-        let mut vec_of_vec_of_orders = vec![];
-        for i in 0..orders_to_post.len() {
-            if orders_to_post[i].is_real() {
-                vec_of_orders.push(orders_to_post[i].clone());
-                if vec_of_orders.len() >= 5 {
-                    vec_of_vec_of_orders.push(vec_of_orders);
-                    vec_of_orders = vec![];
-                }
-            }
-        }
-        if vec_of_orders.len() > 0 {
-            vec_of_vec_of_orders.push(vec_of_orders);
-        }
-        let timestamp = get_timestamp();
-        let mut urls: Vec<String> = vec![];
-        for i in 0..vec_of_vec_of_orders.len() {
-            if vec_of_vec_of_orders[i].len() == 1 {
-                let payload = format!("{}&timestamp={}", vec_of_vec_of_orders[i][0].to_single_url_string(&pair, price_decimal_count, quantity_decimal_count), timestamp);
-                let mut current_signature = signature.clone();
-                current_signature.update(payload.as_bytes());
-                urls.push(format!("{}/fapi/v1/order?{}&signature={}", base_url, payload, hex_encode(current_signature.finalize().into_bytes())));
-            } else {
-                let mut batch_orders: String = String::from("[");
-                for j in 0..vec_of_vec_of_orders[i].len() {
-                    if j > 0 {
-                        batch_orders.push_str(&format!(",{}", vec_of_vec_of_orders[i][j].to_url_string(&pair, price_decimal_count, quantity_decimal_count)));
-                    } else {
-                        batch_orders.push_str(&vec_of_vec_of_orders[i][j].to_url_string(&pair, price_decimal_count, quantity_decimal_count));
-                    }
-                }
-                batch_orders.push_str("]");
-                let payload = format!("batchOrders={}&timestamp={}", urlencoding::encode(&batch_orders), timestamp);
-                let mut current_signature = signature.clone();
-                current_signature.update(payload.as_bytes());
-                urls.push(format!("{}/fapi/v1/batchOrders?{}&signature={}", base_url, payload, hex_encode(current_signature.finalize().into_bytes())));
-            }
-        }
-        if urls.len() > 0 {
-            parallel_post_requests(urls).await;
+    let quantities = ladder.distribute(&prices, pool);
+    let (mut orders_to_cancel, mut orders_to_post) = (vec![], vec![]);
+    for (order, (price, quantity)) in orders.iter_mut().zip(prices.into_iter().zip(quantities)) {
+        if order.get_price() != price || order.get_quantity() != quantity {
+            orders_to_cancel.push(order.clone());
+            *order = Order::new(price, quantity, true, is_long);
+            orders_to_post.push(order.clone());
         }
     }
+    (orders_to_cancel, orders_to_post)
 }
 
-async fn cancel_multiple_orders(orders_to_cancel: Vec<Order>) {
-    if orders_to_cancel.len() > 0 {
-        let (signature, base_url, pair) = {
-            let local_mem = &MEM.lock().unwrap();
-            (local_mem.get_signature(), local_mem.get_url_request(), local_mem.get_pair())
-        };
-        let mut vec_of_orders = vec![];
-        let mut vec_of_vec_of_orders = vec![];
-        for i in 0..orders_to_cancel.len() {
-            if orders_to_cancel[i].is_real() {
-                vec_of_orders.push(orders_to_cancel[i].clone());
-                if vec_of_orders.len() >= 5 {
-                    vec_of_vec_of_orders.push(vec_of_orders);
-                    vec_of_orders = vec![];
-                }
-            }
-        }
-        if vec_of_orders.len() > 0 {
-            vec_of_vec_of_orders.push(vec_of_orders);
+async fn reconcile_orders(mem: &MemHandle, reconciler: &mut Reconciler, local_orders: Vec<&Order>) {
+    let (exchange, pair, price_decimal_count, quantity_decimal_count) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair(), local_mem.get_price_decimal_count() as usize, local_mem.get_quantity_decimal_count() as usize)).await;
+    let open_orders = match exchange.get_open_orders(&pair).await {
+        Ok(open_orders) => open_orders,
+        Err(error) => {
+            println!("\x1b[91mFailed to get_open_orders(): {}\x1b[0m", error);
+            return
         }
-        let timestamp = get_timestamp();
-        let mut urls: Vec<String> = vec![];
-        for i in 0..vec_of_vec_of_orders.len() {
-            if vec_of_vec_of_orders[i].len() == 1 {
-                let payload = format!("symbol={}&origClientOrderId={}&timestamp={}", pair, vec_of_vec_of_orders[i][0].get_id(), timestamp);
-                let mut current_signature = signature.clone();
-                current_signature.update(payload.as_bytes());
-                urls.push(format!("{}/fapi/v1/order?{}&signature={}", base_url, payload, hex_encode(current_signature.finalize().into_bytes())));
-            } else {
-                let mut order_ids: String = String::from("[");
-                for j in 0..vec_of_vec_of_orders[i].len() {
-                    if j > 0 {
-                        order_ids.push_str(&format!(",\"{}\"", vec_of_vec_of_orders[i][j].get_id()));
-                    } else {
-                        order_ids.push_str(&format!("\"{}\"", vec_of_vec_of_orders[i][j].get_id()));
-                    }
-                }
-                order_ids.push_str("]");
-                let payload = format!("symbol={}&origClientOrderIdList={}&timestamp={}", pair, urlencoding::encode(&order_ids), timestamp);
-                let mut current_signature = signature.clone();
-                current_signature.update(payload.as_bytes());
-                urls.push(format!("{}/fapi/v1/batchOrders?{}&signature={}", base_url, payload, hex_encode(current_signature.finalize().into_bytes())));
-            }
+    };
+    let (orders_to_cancel, mut orders_to_post, orders_to_check) = reconciler.reconcile(local_orders, &open_orders, price_decimal_count, quantity_decimal_count);
+    let mut missed_fill = false;
+    for order in orders_to_check {
+        match exchange.get_order(&pair, order.get_id()).await {
+            Ok(response) => match response.status {
+                OrderStatus::Canceled | OrderStatus::Expired => {
+                    println!("\x1b[93mOrder {} was {:?} on the exchange, reposting it\x1b[0m", order.get_id(), response.status);
+                    orders_to_post.push(order);
+                },
+                OrderStatus::Filled => {
+                    println!("\x1b[93mOrder {} was filled without an update, applying the fill\x1b[0m", order.get_id());
+                    mem.send(StateEvent::User(UserDataEvent::OrderTradeUpdate { order: response.to_fill_update() }));
+                    missed_fill = true;
+                },
+                // Still live, the next run will see it in the open orders
+                _ => {}
+            },
+            Err(error) if error.is_api(ApiCode::OrderNotFound) => {
+                println!("\x1b[93mOrder {} is unknown to the exchange, reposting it\x1b[0m", order.get_id());
+                orders_to_post.push(order);
+            },
+            Err(error) => println!("\x1b[91mFailed to get_order({}): {}\x1b[0m", order.get_id(), error)
         }
-        if urls.len() > 0 {
-            parallel_delete_requests(urls).await;
+    }
+    cancel_multiple_orders(mem, orders_to_cancel).await;
+    post_multiple_orders(mem, orders_to_post).await;
+    // The fees and the PNL of a missed fill only show in the account
+    if missed_fill {
+        if let Err(error) = verify_account_info(mem).await {
+            println!("\x1b[91mFailed to verify_account_info(): {}\x1b[0m", error);
         }
     }
 }
 
-async fn parallel_post_requests(urls: Vec<String>) {
-    let (client, header) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header())
-    };
-    let parallel_requests: usize = urls.len();
-    let bodies = stream::iter(urls).map(|url| {
-        let clonned_client = client.clone();
-        let clonned_header = header.clone();
-        tokio::spawn(async move {
-            clonned_client.post(url).headers(clonned_header).send().await
-        })
-    }).buffer_unordered(parallel_requests);
-
-    bodies.for_each(|resp| async {
-        let my_text = &resp.unwrap().unwrap().text().await.unwrap();
-        let resp_json = json::parse(my_text).ok();
-        let mut failed: bool = false;
-        if resp_json.is_some() {
-            let resp_json_unwrapped = resp_json.unwrap();
-            let single_status = resp_json_unwrapped["status"].as_str();
-            if single_status.is_some() {
-                if single_status.unwrap() != "NEW" {
-                    failed = true;
-                }
-            } else {
-                let code = resp_json_unwrapped["code"].as_i64();
-                if code.is_some() {
-                    if code.unwrap() != -2022 {
-                        println!("\x1b[91mFailed to post close order\x1b[0m");
-                        failed = true;
-                    }
-                } else {
-                    for i in 0..resp_json_unwrapped.len() {
-                        let status = resp_json_unwrapped[i]["status"].as_str();
-                        if status.is_some() {
-                            if status.unwrap() != "NEW" {
-                                failed = true;
-                                break
-                            }
-                        } else {
-                            let code = resp_json_unwrapped[i]["code"].as_i64();
-                            if code.is_some() {
-                                if code.unwrap() != -2022 {
-                                    println!("\x1b[91mFailed to post close order\x1b[0m");
-                                    failed = true;
-                                    break
-                                }
-                            } else {
-                                failed = true;
-                                break
-                            }
-                        }
-                    }
-                }
-            }
+async fn post_multiple_orders(mem: &MemHandle, orders_to_post: Vec<Order>) {
+    if !orders_to_post.is_empty() {
+        let (exchange, pair, price_decimal_count, quantity_decimal_count) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair(), local_mem.get_price_decimal_count(), local_mem.get_quantity_decimal_count())).await;
+        let success = if orders_to_post.len() == 1 {
+            exchange.place_order(&pair, &orders_to_post[0], price_decimal_count, quantity_decimal_count).await
         } else {
-            failed = true;
-        }
-        if failed {
-            println!("\x1b[91mFailed to post order\x1b[0m");
-            println!("\x1b[91m{}\x1b[0m", my_text);
-            order_overflow();
+            exchange.place_orders(&pair, orders_to_post, price_decimal_count, quantity_decimal_count).await
+        };
+        if !success {
+            order_overflow(mem).await;
         }
-    }).await;
+    }
 }
 
-async fn parallel_delete_requests(urls: Vec<String>) {
-    let (client, header) = {
-        let local_mem = &MEM.lock().unwrap();
-        (local_mem.get_client(), local_mem.get_header())
-    };
-    let parallel_requests: usize = urls.len();
-    let bodies = stream::iter(urls).map(|url| {
-        let clonned_client = client.clone();
-        let clonned_header = header.clone();
-        tokio::spawn(async move {
-            clonned_client.delete(url).headers(clonned_header).send().await
-        })
-    }).buffer_unordered(parallel_requests);
-
-    bodies.for_each(|resp| async {
-        let my_text = resp.unwrap().unwrap().text().await.unwrap();
-        let resp_json = json::parse(&my_text).ok();
-        let mut failed: bool = false;
-        if resp_json.is_some() {
-            let resp_json_unwrapped = resp_json.unwrap();
-            let single_status = resp_json_unwrapped["status"].as_str();
-            if single_status.is_some() {
-                if single_status.unwrap() != "CANCELED" {
-                    failed = true;
-                }
-            } else {
-                for i in 0..resp_json_unwrapped.len() {
-                    let status = resp_json_unwrapped[i]["status"].as_str();
-                    if status.is_some() {
-                        if status.unwrap() != "CANCELED" {
-                            failed = true;
-                            break
-                        }
-                    } else {
-                        failed = true;
-                        break
-                    }
-                }
-            }
+async fn cancel_multiple_orders(mem: &MemHandle, orders_to_cancel: Vec<Order>) {
+    if !orders_to_cancel.is_empty() {
+        let (exchange, pair) = mem.query(|local_mem| (local_mem.get_exchange(), local_mem.get_pair())).await;
+        if orders_to_cancel.len() == 1 {
+            exchange.cancel_order(&pair, &orders_to_cancel[0]).await;
         } else {
-            failed = true;
-        }
-        if failed {
-            println!("\x1b[91mFailed to cancel order\x1b[0m");
+            exchange.cancel_orders(&pair, orders_to_cancel).await;
         }
-    }).await;
+    }
 }
 
-fn order_overflow() {
+async fn order_overflow(mem: &MemHandle) {
     println!("\x1b[91mOrder overflow\x1b[0m");
-    { MEM.lock().unwrap().start_overflow() };
-}
\ No newline at end of file
+    mem.update(|local_mem| local_mem.start_overflow()).await;
+}
@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::models::LeverageBracket;
+
+// The optional "liquidation" key of a grid, margin ratios are maintenance margin over margin
+// balance and the exchange liquidates at 1
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LiquidationSettings {
+    // The open ladders shrink past this ratio, down to nothing at close_only_ratio
+    pub reduce_ratio: f64,
+    // The grid goes close-only past this ratio
+    pub close_only_ratio: f64,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>
+}
+
+impl Default for LiquidationSettings {
+    fn default() -> Self {
+        LiquidationSettings {
+            reduce_ratio: 0.5,
+            close_only_ratio: 0.8,
+            unknown: BTreeMap::new()
+        }
+    }
+}
+
+// Both sides of the hedged position of a pair
+#[derive(Clone, Copy)]
+pub struct HedgedPosition {
+    pub longs: f64,
+    pub long_entry_price: f64,
+    pub shorts: f64,
+    pub short_entry_price: f64
+}
+
+impl HedgedPosition {
+    fn unrealized_profit(&self, price: f64) -> f64 {
+        (price - self.long_entry_price) * self.longs + (self.short_entry_price - price) * self.shorts
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MarginEstimate {
+    pub margin_ratio: f64,
+    // None when the position cannot be liquidated by a move of this pair alone
+    pub liquidation_price: Option<f64>
+}
+
+// The account is only fetched every minute, in between the pair's positions are re-marked to the
+// book while the rest of the account is assumed unchanged since the last snapshot
+pub struct MarginMonitor {
+    settings: LiquidationSettings,
+    brackets: Vec<LeverageBracket>,
+    // Margin balance and maintenance margin of everything but the pair, None before the first snapshot
+    others: Option<(f64, f64)>
+}
+
+impl MarginMonitor {
+    pub fn new(settings: LiquidationSettings) -> Self {
+        MarginMonitor { settings, brackets: vec![], others: None }
+    }
+
+    pub fn set_brackets(&mut self, brackets: Vec<LeverageBracket>) {
+        self.brackets = brackets;
+    }
+
+    // Maintenance ratio and amount of the tier of this notional, nothing without the brackets
+    fn bracket(&self, notional: f64) -> (f64, f64) {
+        self.brackets.iter().find(|bracket| notional < bracket.notional_cap).or(self.brackets.last())
+            .map(|bracket| (bracket.maint_margin_ratio, bracket.cum)).unwrap_or((0.0, 0.0))
+    }
+
+    fn maint_margin(&self, position: &HedgedPosition, price: f64) -> f64 {
+        [position.longs, position.shorts].iter().map(|quantity| {
+            let (maint_margin_ratio, cum) = self.bracket(quantity * price);
+            (quantity * price * maint_margin_ratio - cum).max(0.0)
+        }).sum()
+    }
+
+    // unrealized_profit is the one the exchange computed for the pair in the same payload
+    pub fn apply_account(&mut self, total_margin_balance: f64, total_maint_margin: f64, wallet_balance: f64, unrealized_profit: f64, position: &HedgedPosition, price: f64) {
+        if total_margin_balance <= 0.0 || price <= 0.0 {
+            return
+        }
+        self.others = Some((
+            total_margin_balance - wallet_balance - unrealized_profit,
+            (total_maint_margin - self.maint_margin(position, price)).max(0.0)
+        ));
+    }
+
+    pub fn estimate(&self, wallet_balance: f64, position: &HedgedPosition, price: f64) -> Option<MarginEstimate> {
+        let (other_balance, other_maint_margin) = self.others?;
+        let margin_balance = wallet_balance + other_balance + position.unrealized_profit(price);
+        let maint_margin = other_maint_margin + self.maint_margin(position, price);
+        let margin_ratio = if margin_balance > 0.0 { maint_margin / margin_balance } else { 1.0 };
+        // Price where the margin balance falls to the maintenance margin, the tiers held fixed
+        let (long_ratio, long_cum) = self.bracket(position.longs * price);
+        let (short_ratio, short_cum) = self.bracket(position.shorts * price);
+        let numerator = wallet_balance + other_balance - other_maint_margin + long_cum + short_cum - position.longs * position.long_entry_price + position.shorts * position.short_entry_price;
+        let denominator = position.longs * long_ratio + position.shorts * short_ratio - position.longs + position.shorts;
+        let liquidation_price = Some(numerator / denominator).filter(|price| !self.brackets.is_empty() && denominator.abs() > 1e-12 && *price > 0.0);
+        Some(MarginEstimate { margin_ratio, liquidation_price })
+    }
+
+    // Share of the open pools kept at this margin ratio, in steps of 10% so the ladders are not
+    // reposted on every small move
+    pub fn open_scale(&self, margin_ratio: f64) -> f64 {
+        let (reduce_ratio, close_only_ratio) = (self.settings.reduce_ratio, self.settings.close_only_ratio);
+        if margin_ratio <= reduce_ratio {
+            1.0
+        } else if margin_ratio >= close_only_ratio {
+            0.0
+        } else {
+            ((1.0 - (margin_ratio - reduce_ratio) / (close_only_ratio - reduce_ratio)) * 10.0).floor() / 10.0
+        }
+    }
+
+    pub fn is_close_only(&self, margin_ratio: f64) -> bool {
+        margin_ratio >= self.settings.close_only_ratio
+    }
+
+    pub fn get_close_only_ratio(&self) -> f64 {
+        self.settings.close_only_ratio
+    }
+}
@@ -1,11 +1,21 @@
-use hmac::{Hmac, Mac, digest::{core_api::{CoreWrapper, CtVariableCoreWrapper}, typenum::{UInt, UTerm, B1, B0}}, HmacCore};
-use sha2::{Sha256, Sha256VarCore};
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT, CONTENT_TYPE};
-use std::{time::{Instant, Duration}};
+use std::{sync::Arc, time::{Instant, Duration, SystemTime, UNIX_EPOCH}};
+use crate::binance::BinanceExchange;
+use crate::config::{Config, GridConfig, ShutdownMode};
+use crate::dry_run::DryRunExchange;
+use crate::exchange::Exchange;
+use crate::funding::{FundingBias, FundingMonitor};
+use crate::grid::{round_significant, AtrEstimator, CloseLevel, LadderQuantities, LotFilters, QuantityMode, QuantityProfile, Spacing, Step};
+use crate::inventory::InventorySettings;
+use crate::journal::Journal;
+use crate::margin::{HedgedPosition, MarginEstimate, MarginMonitor};
+use crate::paper::PaperExchange;
+use crate::risk::{RiskAction, RiskMonitor};
+use crate::secrets::Secrets;
+use crate::models::{AccountInfo, AccountUpdate, Income, LeverageBracket, OrderStatus, OrderUpdate, PositionSide, Side, UserDataEvent};
+use crate::state_actor::{MemHandle, StateEvent};
+use crate::stats::{HistoryStats, StatsTotals};
 
 pub struct MemoryManager {
-    url_request: String,
     url_websocket: String,
     discord_token: String,
     discord_channel: String,
@@ -18,15 +28,21 @@ pub struct MemoryManager {
     quantity_decimal_count: i64,
     leverage: u64,
     close_only: bool,
+    spacing: Spacing,
     margin: f64,
     increment: f64,
     close_diff: f64,
+    close_levels: Vec<CloseLevel>,
+    atr: AtrEstimator,
+    atr_multiplier: f64,
     order_amount: u64,
+    quantity_mode: QuantityMode,
     order_quantity: f64,
+    long_quantity_profile: QuantityProfile,
+    short_quantity_profile: QuantityProfile,
+    lot_filters: LotFilters,
     max_position: f64,
-    client: reqwest::Client,
-    header: HeaderMap,
-    signature: CoreWrapper<HmacCore<CoreWrapper<CtVariableCoreWrapper<Sha256VarCore, UInt<UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>, B0>>>>>,
+    exchange: Arc<dyn Exchange>,
     top_ask: f64,
     top_bid: f64,
     current_longs: f64,
@@ -37,12 +53,10 @@ pub struct MemoryManager {
     short_un_pnl: f64,
     open_longs_filled: u64,
     open_longs_expired: u64,
-    close_longs_filled: u64,
-    close_longs_expired: u64,
     open_shorts_filled: u64,
     open_shorts_expired: u64,
-    close_shorts_filled: u64,
-    close_shorts_expired: u64,
+    // Client ids of the close orders gone since the last pass, filled or expired
+    finished_closes: Vec<u32>,
     volume: f64,
     balance: f64,
     realized_profit: f64,
@@ -51,70 +65,129 @@ pub struct MemoryManager {
     started_time: Instant,
     order_overflow: bool,
     need_restart: bool,
+    resyncing: bool,
+    reconcile_interval: u64,
+    shutdown_mode: ShutdownMode,
+    shutting_down: bool,
+    risk: RiskMonitor,
+    liquidation: MarginMonitor,
+    // Share of the open pools the margin ratio still allows
+    open_scale: f64,
+    // Set by the risk manager once it cancelled the grid, the trader posts nothing anymore
+    halted: bool,
+    funding: FundingMonitor,
+    // Recomputed on every funding update, the trader reshapes the ladders when it changes
+    funding_bias: FundingBias,
+    // Funding fees of the session, received when positive
+    funding_paid: f64,
+    inventory_skew: InventorySettings,
+    // Net position over max_net in steps of 10%, updated with the positions
+    imbalance: f64,
+    journal: Journal,
+    history: HistoryStats,
     vip_level: u64,
     soft_position: f64,
     acceleration: f64,
-    half_range: f64,
     last_long_open: f64,
-    last_short_open: f64
+    last_short_open: f64,
+    // Applied a second after a full open fill, see apply_pending_opens
+    pending_long_open: Option<(tokio::time::Instant, f64)>,
+    pending_short_open: Option<(tokio::time::Instant, f64)>
 }
 
-impl MemoryManager {
-    pub fn new() -> Self {
-        let config_str: String = std::fs::read_to_string("ressources/config.json").unwrap();
-        let config_json: json::JsonValue = json::parse(&config_str).unwrap();
-
-        let encrypted_key: String = new_magic_crypt!("encrypted_key", 256).decrypt_base64_to_string(String::from(config_json["encrypted_key"].as_str().unwrap())).unwrap();
-        let encrypted_secret: String = new_magic_crypt!("encrypted_secret", 256).decrypt_base64_to_string(String::from(config_json["encrypted_secret"].as_str().unwrap())).unwrap();
-        let api_key: String = new_magic_crypt!(&encrypted_secret, 256).decrypt_base64_to_string(encrypted_key).unwrap();
-        let api_secret: String = new_magic_crypt!(&api_key, 256).decrypt_base64_to_string(encrypted_secret).unwrap();
+// Price and quantity of every close level of a side, the nearest first
+pub type CloseLevels = Vec<(f64, f64)>;
 
-        let encrypted_token: String = new_magic_crypt!("encrypted_token", 256).decrypt_base64_to_string(String::from(config_json["encrypted_token"].as_str().unwrap())).unwrap();
-        let encrypted_channel: String = new_magic_crypt!("encrypted_channel", 256).decrypt_base64_to_string(String::from(config_json["encrypted_channel"].as_str().unwrap())).unwrap();
-        let discord_token: String = new_magic_crypt!(&encrypted_channel, 256).decrypt_base64_to_string(encrypted_token).unwrap();
-        let discord_channel: String = new_magic_crypt!(&discord_token, 256).decrypt_base64_to_string(encrypted_channel).unwrap();
+// Everything that limits the open ladders, the trader redistributes them when it changes
+#[derive(Clone, Copy, PartialEq)]
+pub struct OpenLimits {
+    pub close_only: bool,
+    pub open_scale: f64,
+    pub funding_bias: FundingBias,
+    pub imbalance: f64
+}
 
-        let mut url_request = String::from("https://fapi.binance.com");
-        let mut url_websocket = String::from("wss://fstream.binance.com");
+// Where the orders of the grids end up
+pub enum Venue {
+    Live,
+    // Reads from the exchange, writes are only printed and the order requests logged
+    DryRun(Arc<DryRunExchange>),
+    // Nothing reaches the exchange, a local engine fills the orders
+    Paper(Arc<PaperExchange>)
+}
 
-        if config_json["testnet"].as_bool().unwrap() {
-            url_request = String::from("https://testnet.binancefuture.com");
-            url_websocket = String::from("wss://stream.binancefuture.com");
+// One MemoryManager per grid, all sharing the same REST client
+pub fn load_grids(config: &Config, secrets: &Secrets, venue: &Venue) -> Vec<MemHandle> {
+    let mut discord_token = secrets.discord_token.clone();
+
+    let (url_request, url_websocket) = endpoints(config);
+    if config.mock_server.is_some() {
+        discord_token = String::from("");
+    }
+
+    let exchange: Arc<dyn Exchange> = match venue {
+        Venue::Live => Arc::new(BinanceExchange::new(url_request, &secrets.api_key, &secrets.api_secret, config.retry, config.rate_limit)),
+        Venue::DryRun(dry_run) => dry_run.clone(),
+        Venue::Paper(paper) => paper.clone()
+    };
+    config.grids.iter().map(|grid| {
+        let mut grid = grid.clone();
+        // Paper fills never mix with the history of the real account
+        if let Venue::Paper(_) = venue {
+            grid.stats_file = paper_path(&grid.stats_file);
+            grid.journal = paper_path(&grid.journal);
         }
+        MemHandle::spawn(MemoryManager::new(&grid, exchange.clone(), &url_websocket, &discord_token, &secrets.discord_channel))
+    }).collect()
+}
 
-        let order_quantity = config_json["order_quantity"].as_f64().unwrap();
-        let max_position = config_json["max_position"].as_f64().unwrap();
-        let increment = config_json["increment"].as_f64().unwrap();
-        let half_range = (0.9 * increment * max_position) / (2.0 * order_quantity);
+// REST and websocket base urls of mainnet, the testnet or the mock server
+pub fn endpoints(config: &Config) -> (String, String) {
+    match &config.mock_server {
+        Some(mock_settings) => (mock_settings.get_url_request(), mock_settings.get_url_websocket()),
+        None if config.testnet => (String::from("https://testnet.binancefuture.com"), String::from("wss://stream.binancefuture.com")),
+        None => (String::from("https://fapi.binance.com"), String::from("wss://fstream.binance.com"))
+    }
+}
 
-        let mut header = HeaderMap::new();
-        header.insert(USER_AGENT, HeaderValue::from_static("binance-rs"));
-        header.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
-        header.insert(HeaderName::from_static("x-mbx-apikey"), HeaderValue::from_str(&api_key).unwrap());
+// ressources/stats.json -> ressources/paper_stats.json
+fn paper_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(index) => format!("{}paper_{}", &path[..=index], &path[index + 1..]),
+        None => format!("paper_{}", path)
+    }
+}
 
+impl MemoryManager {
+    fn new(grid: &GridConfig, exchange: Arc<dyn Exchange>, url_websocket: &str, discord_token: &str, discord_channel: &str) -> Self {
         MemoryManager {
-            url_request,
-            url_websocket,
-            discord_token,
-            discord_channel,
-            pair: String::from(config_json["pair"].as_str().unwrap()),
+            exchange,
+            url_websocket: String::from(url_websocket),
+            discord_token: String::from(discord_token),
+            discord_channel: String::from(discord_channel),
+            pair: grid.pair.clone(),
             token: String::from(""),
             price_decimal: 0.0,
             price_decimal_count: 0,
             quantity_decimal: 0.0,
             quantity_decimal_half: 0.0,
             quantity_decimal_count: 0,
-            leverage: config_json["leverage"].as_u64().unwrap(),
-            close_only: config_json["close_only"].as_bool().unwrap(),
-            margin: config_json["margin"].as_f64().unwrap(),
-            close_diff: config_json["close_diff"].as_f64().unwrap(),
-            increment,
-            order_amount: config_json["order_amount"].as_u64().unwrap(),
-            order_quantity,
-            max_position,
-            client: reqwest::Client::builder().pool_idle_timeout(None).build().unwrap(),
-            header,
-            signature: Hmac::<Sha256>::new_from_slice(api_secret.as_bytes()).unwrap(),
+            leverage: grid.leverage,
+            close_only: grid.close_only,
+            spacing: grid.spacing,
+            margin: grid.margin,
+            close_diff: grid.close_diff,
+            close_levels: grid.close_levels.clone(),
+            increment: grid.increment,
+            atr: AtrEstimator::new(grid.atr_period, grid.atr_interval),
+            atr_multiplier: grid.atr_multiplier,
+            quantity_mode: grid.quantity_mode,
+            long_quantity_profile: grid.long_quantity_profile.clone(),
+            short_quantity_profile: grid.short_quantity_profile.clone(),
+            lot_filters: LotFilters::default(),
+            order_amount: grid.order_amount,
+            order_quantity: grid.order_quantity,
+            max_position: grid.max_position,
             top_ask: 0.0,
             top_bid: 0.0,
             current_longs: 0.0,
@@ -125,12 +198,9 @@ impl MemoryManager {
             short_un_pnl: 0.0,
             open_longs_filled: 0,
             open_longs_expired: 0,
-            close_longs_filled: 0,
-            close_longs_expired: 0,
             open_shorts_filled: 0,
             open_shorts_expired: 0,
-            close_shorts_filled: 0,
-            close_shorts_expired: 0,
+            finished_closes: vec![],
             volume: 0.0,
             balance: 0.0,
             realized_profit: 0.0,
@@ -139,45 +209,54 @@ impl MemoryManager {
             started_time: Instant::now(),
             order_overflow: false,
             need_restart: false,
+            resyncing: false,
+            reconcile_interval: grid.reconcile_interval,
+            shutdown_mode: grid.shutdown,
+            shutting_down: false,
+            risk: RiskMonitor::new(grid.risk.clone()),
+            liquidation: MarginMonitor::new(grid.liquidation.clone()),
+            open_scale: 1.0,
+            halted: false,
+            funding: FundingMonitor::new(grid.funding.clone(), unix_millis()),
+            funding_bias: FundingBias::neutral(),
+            funding_paid: 0.0,
+            inventory_skew: grid.inventory_skew.clone(),
+            imbalance: 0.0,
+            history: HistoryStats::restore(&grid.stats_file, grid.stats_window),
+            journal: Journal::open(&grid.journal),
             vip_level: 0,
-            soft_position: config_json["soft_position"].as_f64().unwrap(),
-            acceleration: config_json["acceleration"].as_f64().unwrap(),
-            half_range,
+            soft_position: grid.soft_position,
+            acceleration: grid.acceleration,
             last_long_open: 99999.0,
-            last_short_open: 0.0
+            last_short_open: 0.0,
+            pending_long_open: None,
+            pending_short_open: None
         }
     }
 
     
-    pub fn set_exchange_info(&mut self, token: String, price_decimal: f64, price_decimal_count: i64, quantity_decimal: f64, quantity_decimal_count: i64, min_quantity: f64, max_quantity: f64, max_order_amount: u64) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_exchange_info(&mut self, token: String, price_decimal: f64, price_decimal_count: i64, quantity_decimal: f64, quantity_decimal_count: i64, min_quantity: f64, max_quantity: f64, min_notional: f64) {
         self.token = token;
         self.price_decimal = price_decimal;
         self.price_decimal_count = price_decimal_count;
         self.quantity_decimal = quantity_decimal;
         self.quantity_decimal_half = quantity_decimal / 2.0;
         self.quantity_decimal_count = quantity_decimal_count;
-        if self.order_quantity < min_quantity || self.order_quantity > max_quantity {
-            panic!("\x1b[91mERROR: Order quantity {} does not match min {} and max {}\x1b[0m", self.order_quantity, min_quantity, max_quantity);
-        }
-        if self.order_amount as f64 > (max_order_amount as f64 / 4.0) {
-            panic!("\x1b[91mERROR: Order amount {} is too high for max order amount {}\x1b[0m", self.order_amount, max_order_amount);
-        }
-    }
-
-    pub fn get_client(&self) -> reqwest::Client {
-        self.client.clone()
-    }
-
-    pub fn get_header(&self) -> HeaderMap {
-        self.header.clone()
+        self.lot_filters = LotFilters { step_size: quantity_decimal, min_quantity, max_quantity, min_notional };
     }
 
-    pub fn get_signature(&self) -> CoreWrapper<HmacCore<CoreWrapper<CtVariableCoreWrapper<Sha256VarCore, UInt<UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>, B0>>>>> {
-        self.signature.clone()
+    pub fn get_exchange(&self) -> Arc<dyn Exchange> {
+        self.exchange.clone()
     }
 
-    pub fn get_url_request(&self) -> String {
-        self.url_request.clone()
+    // Used by the backtest: orders go to the simulated exchange and nothing is sent to discord
+    pub fn set_backtest_exchange(&mut self, exchange: Arc<dyn Exchange>) {
+        self.exchange = exchange;
+        self.journal = Journal::disabled();
+        self.history = HistoryStats::restore("", 30);
+        self.discord_token = String::from("");
+        self.discord_channel = String::from("");
     }
     
     pub fn get_url_websocket(&self) -> String {
@@ -193,11 +272,67 @@ impl MemoryManager {
     }
 
     pub fn get_order_amount(&self) -> u64 {
-        self.order_amount.clone()
+        self.order_amount
     }
 
     pub fn get_margin(&self) -> f64 {
-        self.margin.clone()
+        self.to_quote(self.margin)
+    }
+
+    fn get_close_diff(&self) -> f64 {
+        self.to_quote(self.close_diff)
+    }
+
+    // margin and close_diff in quote currency at the current price
+    fn to_quote(&self, value: f64) -> f64 {
+        match self.spacing {
+            Spacing::Fixed | Spacing::Atr => value,
+            Spacing::Percentage | Spacing::Geometric => value / 100.0 * self.get_marketprice()
+        }
+    }
+
+    // Quote currency between two levels before acceleration
+    fn get_base_increment(&self) -> f64 {
+        match self.spacing {
+            Spacing::Fixed => self.increment,
+            Spacing::Percentage | Spacing::Geometric => self.increment / 100.0 * self.get_marketprice(),
+            Spacing::Atr => self.increment.max(self.atr_multiplier * self.atr.value().unwrap_or(0.0))
+        }
+    }
+
+    // Base asset of one level at the current price, the acceleration range is counted in levels
+    fn get_base_order_quantity(&self) -> f64 {
+        match self.quantity_mode {
+            QuantityMode::Base | QuantityMode::Pyramid => self.order_quantity,
+            QuantityMode::Quote => self.order_quantity / self.get_marketprice()
+        }
+    }
+
+    // Open long ladder then open short ladder
+    pub fn get_ladder_quantities(&self) -> (LadderQuantities, LadderQuantities) {
+        let (long_inventory_scale, short_inventory_scale) = self.inventory_skew.quantity_scales(self.get_imbalance());
+        (
+            LadderQuantities::new(self.quantity_mode, self.order_quantity, self.long_quantity_profile.clone(), self.lot_filters).scaled(self.funding_bias.long_scale * long_inventory_scale),
+            LadderQuantities::new(self.quantity_mode, self.order_quantity, self.short_quantity_profile.clone(), self.lot_filters).scaled(self.funding_bias.short_scale * short_inventory_scale)
+        )
+    }
+
+    fn get_imbalance(&self) -> f64 {
+        self.imbalance
+    }
+
+    fn update_imbalance(&mut self) {
+        self.imbalance = self.inventory_skew.imbalance(self.current_longs, self.current_shorts, self.max_position, self.imbalance);
+    }
+
+    // Move of the ladder centre in quote currency, down when long-heavy, rounded to the tick size
+    pub fn get_inventory_shift(&self) -> f64 {
+        let shift = -self.get_imbalance() * self.to_quote(self.inventory_skew.shift);
+        if self.price_decimal > 0.0 {
+            (shift / self.price_decimal).round() * self.price_decimal
+        } else {
+            shift
+        }
     }
 
     // pub fn get_base_increments(&self) -> (f64, f64) {
@@ -205,32 +340,27 @@ impl MemoryManager {
     // }
 
     pub fn get_leverage(&self) -> u64 {
-        self.leverage.clone()
+        self.leverage
     }
 
-    pub fn get_order_quantity(&self) -> f64 {
-        self.order_quantity.clone()
+    pub fn get_reconcile_interval(&self) -> u64 {
+        self.reconcile_interval
     }
 
-    pub fn get_max_position(&self) -> f64 {
-        self.max_position.clone()
-    }
-    
     pub fn get_price_decimal_count(&self) -> i64 {
-        self.price_decimal_count.clone()
-    }
-
-    pub fn get_quantity_decimal_half(&self) -> f64 {
-        self.quantity_decimal_half.clone()
+        self.price_decimal_count
     }
 
     pub fn get_quantity_decimal_count(&self) -> i64 {
-        self.quantity_decimal_count.clone()
+        self.quantity_decimal_count
     }
 
-    pub fn set_marketprice(&mut self, top_ask: f64, top_bid: f64) {
+    pub fn set_marketprice(&mut self, top_ask: f64, top_bid: f64, time: u64) {
         self.top_ask = top_ask;
         self.top_bid = top_bid;
+        if self.spacing == Spacing::Atr {
+            self.atr.update(self.get_marketprice(), if time > 0 { time } else { unix_millis() });
+        }
     }
 
     pub fn get_marketprice(&self) -> f64 {
@@ -275,7 +405,7 @@ impl MemoryManager {
         self.last_short_open = mp;
     }
 
-    pub fn get_increments(&self) -> (f64, f64) {
+    pub fn get_increments(&self) -> (Step, Step) {
         (self.get_long_increment(), self.get_short_increment())
     }
 
@@ -322,6 +452,7 @@ impl MemoryManager {
         self.volume += volume;
         self.commission += commission;
         self.realized_profit += realized_profit;
+        self.history.add(volume, commission, realized_profit);
     }
 
     pub fn get_open_long_expired(&mut self) -> u64 {
@@ -335,20 +466,10 @@ impl MemoryManager {
     }
 
     pub fn new_close_long_filled(&mut self, volume: f64, commission: f64, realized_profit: f64) {
-        self.close_longs_filled += 1;
         self.volume += volume;
         self.commission += commission;
         self.realized_profit += realized_profit;
-    }
-
-    pub fn get_close_long_expired(&mut self) -> u64 {
-        let temp = self.close_longs_expired;
-        self.close_longs_expired = 0;
-        temp
-    }
-
-    pub fn new_close_long_expired(&mut self) {
-        self.close_longs_expired += 1;
+        self.history.add(volume, commission, realized_profit);
     }
     
     pub fn get_open_short_filled(&mut self) -> u64 {
@@ -362,6 +483,7 @@ impl MemoryManager {
         self.volume += volume;
         self.commission += commission;
         self.realized_profit += realized_profit;
+        self.history.add(volume, commission, realized_profit);
     }
 
     pub fn get_open_short_expired(&mut self) -> u64 {
@@ -375,81 +497,67 @@ impl MemoryManager {
     }
 
     pub fn new_close_short_filled(&mut self, volume: f64, commission: f64, realized_profit: f64) {
-        self.close_shorts_filled += 1;
         self.volume += volume;
         self.commission += commission;
         self.realized_profit += realized_profit;
+        self.history.add(volume, commission, realized_profit);
     }
 
-    pub fn get_close_short_filled(&mut self) -> u64 {
-        let temp = self.close_shorts_filled;
-        self.close_shorts_filled = 0;
-        temp
-    }
-
-    pub fn get_close_long_filled(&mut self) -> u64 {
-        let temp = self.close_longs_filled;
-        self.close_longs_filled = 0;
-        temp
-    }
-
-    pub fn get_close_short_expired(&mut self) -> u64 {
-        let temp = self.close_shorts_expired;
-        self.close_shorts_expired = 0;
-        temp
-    }
-
-    pub fn new_close_short_expired(&mut self) {
-        self.close_shorts_expired += 1;
+    // Orders not placed by the bot have no numeric client id and are left alone
+    fn new_close_finished(&mut self, client_order_id: &str) {
+        if let Ok(order_id) = client_order_id.parse::<u32>() {
+            self.finished_closes.push(order_id);
+        }
     }
 
     pub fn max_open_long(&self) -> f64 {
-        if self.close_only {
+        if self.close_only || self.funding_bias.trim_long {
             0.0
         } else {
-            self.max_position - self.current_longs
+            (self.max_position - self.current_longs) * self.open_scale
         }
     }
 
-    pub fn max_close_long(&self) -> f64 {
-        self.current_longs
-    }
-
     pub fn max_open_short(&self) -> f64 {
-        if self.close_only {
+        if self.close_only || self.funding_bias.trim_short {
             0.0
         } else {
-            self.max_position - self.current_shorts
+            (self.max_position - self.current_shorts) * self.open_scale
         }
     }
 
-    pub fn max_close_short(&self) -> f64 {
-        self.current_shorts
-    }
-
-    pub fn get_updates(&mut self) -> (f64, f64, u64, u64, bool, bool, f64, f64, f64, f64, f64, f64) {
+    pub fn get_updates(&mut self) -> (f64, f64, u64, u64, Vec<u32>, CloseLevels, CloseLevels, Step, Step) {
+        let (long_close_levels, short_close_levels) = self.get_close_levels();
         (
             self.get_top_ask(),
             self.get_top_bid(),
             self.get_open_long_filled() + self.get_open_long_expired(),
             self.get_open_short_filled() + self.get_open_short_expired(),
-            self.get_close_long_expired() > 0 || self.get_close_long_filled() > 0,
-            self.get_close_short_expired() > 0 || self.get_close_short_filled() > 0,
-            self.current_longs,
-            self.current_shorts,
-            self.top_ask.max(self.long_entry_price + self.close_diff),
-            self.top_bid.min(self.short_entry_price - self.close_diff),
+            std::mem::take(&mut self.finished_closes),
+            long_close_levels,
+            short_close_levels,
             self.get_long_increment(),
             self.get_short_increment()
         )
     }
 
-    pub fn get_long_increment(&self) -> f64 {
-        let inc = self.get_long_increment_imp();
-        if inc < 2.0 {
-            (inc * 4.0).round() / 4.0
-        } else {
-            (inc * 2.0).round() / 2.0
+    pub fn get_long_increment(&self) -> Step {
+        self.get_step(self.get_long_increment_imp())
+    }
+
+    // Fixed spacing keeps its historical rounding, the scaled ones keep two significant digits
+    fn get_step(&self, inc: f64) -> Step {
+        match self.spacing {
+            Spacing::Fixed => Step::Absolute(if inc < 2.0 {
+                (inc * 4.0).round() / 4.0
+            } else {
+                (inc * 2.0).round() / 2.0
+            }),
+            Spacing::Percentage | Spacing::Atr => Step::Absolute(((round_significant(inc, 2) / self.price_decimal).round() * self.price_decimal).max(self.price_decimal)),
+            Spacing::Geometric => match self.get_marketprice() {
+                price if price > 0.0 => Step::Ratio(round_significant(inc / price, 2)),
+                _ => Step::Ratio(self.increment / 100.0)
+            }
         }
     }
 
@@ -458,7 +566,7 @@ impl MemoryManager {
         // println!("self.get_long_amplitude() = {}", self.get_long_amplitude());
         // println!("self.get_long_range_supposed() = {}", self.get_long_range_supposed());
         // println!("self.get_long_range_current() = {}", self.get_long_range_current());
-        self.increment + (self.acceleration * self.get_long_r_delta().sqrt() * self.get_long_amplitude())
+        self.get_base_increment() + (self.acceleration * self.get_long_r_delta().sqrt() * self.get_long_amplitude())
     }
 
     pub fn get_long_amplitude(&self) -> f64 {
@@ -473,27 +581,27 @@ impl MemoryManager {
         let range_supposed = self.get_long_range_supposed();
         let range_current = self.get_long_range_current();
         if range_supposed > range_current {
-            (range_supposed - range_current) / self.increment + 1.0
+            (range_supposed - range_current) / self.get_base_increment() + 1.0
         } else {
             0.0
         }
     }
 
+    // Price range a full position should have been opened over, 90% of the ladder on each side
+    fn get_half_range(&self) -> f64 {
+        (0.9 * self.get_base_increment() * self.max_position) / (2.0 * self.get_base_order_quantity())
+    }
+
     pub fn get_long_range_supposed(&self) -> f64 {
-        self.half_range * self.current_longs / self.max_position
+        self.get_half_range() * self.current_longs / self.max_position
     }
 
     pub fn get_long_range_current(&self) -> f64 {
         0.0_f64.max(self.long_entry_price - self.last_long_open)
     }
 
-    pub fn get_short_increment(&self) -> f64 {
-        let inc = self.get_short_increment_imp();
-        if inc < 2.0 {
-            (inc * 4.0).round() / 4.0
-        } else {
-            (inc * 2.0).round() / 2.0
-        }
+    pub fn get_short_increment(&self) -> Step {
+        self.get_step(self.get_short_increment_imp())
     }
 
     pub fn get_short_increment_imp(&self) -> f64 {
@@ -501,7 +609,7 @@ impl MemoryManager {
         // println!("self.get_short_amplitude() = {}", self.get_short_amplitude());
         // println!("self.get_short_range_supposed() = {}", self.get_short_range_supposed());
         // println!("self.get_short_range_current() = {}", self.get_short_range_current());
-        self.increment + ((self.acceleration * self.get_short_r_delta().sqrt() * self.get_short_amplitude() * 10.0).round() / 10.0)
+        self.get_base_increment() + ((self.acceleration * self.get_short_r_delta().sqrt() * self.get_short_amplitude() * 10.0).round() / 10.0)
     }
 
     pub fn get_short_amplitude(&self) -> f64 {
@@ -516,49 +624,85 @@ impl MemoryManager {
         let range_supposed = self.get_short_range_supposed();
         let range_current = self.get_short_range_current();
         if range_supposed > range_current {
-            (range_supposed - range_current) / self.increment + 1.0
+            (range_supposed - range_current) / self.get_base_increment() + 1.0
         } else {
             0.0
         }
     }
 
     pub fn get_short_range_supposed(&self) -> f64 {
-        self.half_range * self.current_shorts / self.max_position
+        self.get_half_range() * self.current_shorts / self.max_position
     }
 
     pub fn get_short_range_current(&self) -> f64 {
         0.0_f64.max(self.last_short_open - self.short_entry_price)
     }
 
-    pub fn get_close_prices(&self) -> (f64, f64) {
+    // Rolling window then all-time: covered duration and totals
+    pub fn get_history(&self) -> (Duration, StatsTotals, Duration, StatsTotals) {
+        let (window_duration, window_totals) = self.history.get_window();
+        let (all_time_duration, all_time_totals) = self.history.get_all_time();
+        (window_duration, window_totals, all_time_duration, all_time_totals)
+    }
+
+    pub fn save_history(&self) {
+        self.history.save();
+    }
+
+    pub fn get_positions(&self) -> (f64, f64) {
+        (self.current_longs, self.current_shorts)
+    }
+
+    // Price and quantity of every close level, longs then shorts, the nearest level first. A side
+    // trimmed before a funding settlement closes every level at its entry price.
+    pub fn get_close_levels(&self) -> (CloseLevels, CloseLevels) {
+        let close_diff = self.get_close_diff();
+        let long_prices: Vec<f64> = self.close_levels.iter().map(|level| match self.funding_bias.trim_long {
+            true => self.top_ask.max(self.long_entry_price),
+            false => self.top_ask.max(self.long_entry_price + close_diff + self.to_quote(level.offset))
+        }).collect();
+        let short_prices: Vec<f64> = self.close_levels.iter().map(|level| match self.funding_bias.trim_short {
+            true => self.top_bid.min(self.short_entry_price),
+            false => self.top_bid.min(self.short_entry_price - close_diff - self.to_quote(level.offset))
+        }).collect();
+        let long_quantities = self.lot_filters.split_close(self.current_longs, &self.close_levels, &long_prices);
+        let short_quantities = self.lot_filters.split_close(self.current_shorts, &self.close_levels, &short_prices);
         (
-            self.top_ask.max(self.long_entry_price + self.close_diff),
-            self.top_bid.min(self.short_entry_price - self.close_diff)
+            long_prices.into_iter().zip(long_quantities).collect(),
+            short_prices.into_iter().zip(short_quantities).collect()
         )
     }
 
+    // How far a posted close level may drift before it is reposted: a tenth of close_diff in price
+    // but at least a tick, and any step in quantity. Half a tick or step is taken off so the
+    // rounded values compare cleanly.
+    pub fn get_close_drift(&self) -> (f64, f64) {
+        ((self.get_close_diff() / 10.0).max(self.price_decimal) - self.price_decimal / 2.0, self.quantity_decimal_half)
+    }
+
+    #[allow(clippy::type_complexity)]
     pub fn get_stats(&self) -> (Duration, f64, u64, f64, f64, f64, f64, f64, i64, i64, f64, f64, f64, f64, f64, f64, f64, u64, f64, f64) {
         (
             self.started_time.elapsed(),
             self.volume,
             self.order_overflow_count,
             self.balance,
-            self.commission * -1.0,
+            -self.commission,
             self.realized_profit,
             self.current_longs,
             self.current_shorts,
             self.price_decimal_count,
             self.quantity_decimal_count,
             self.get_marketprice(),
-            self.top_ask.max(self.long_entry_price + self.close_diff),
-            self.top_bid.min(self.short_entry_price - self.close_diff),
+            self.top_ask.max(self.long_entry_price + self.get_close_diff()),
+            self.top_bid.min(self.short_entry_price - self.get_close_diff()),
             self.long_entry_price,
             self.short_entry_price,
             self.long_un_pnl,
             self.short_un_pnl,
             self.vip_level,
-            self.get_long_increment(),
-            self.get_short_increment()
+            self.get_long_increment().size_at(self.get_marketprice()),
+            self.get_short_increment().size_at(self.get_marketprice())
         )
     }
 
@@ -578,12 +722,9 @@ impl MemoryManager {
         self.current_shorts = 0.0;
         self.open_longs_filled = 0;
         self.open_longs_expired = 0;
-        self.close_longs_filled = 0;
-        self.close_longs_expired = 0;
         self.open_shorts_filled = 0;
         self.open_shorts_expired = 0;
-        self.close_shorts_filled = 0;
-        self.close_shorts_expired = 0;
+        self.finished_closes.clear();
         self.order_overflow = false;
     }
 
@@ -592,6 +733,30 @@ impl MemoryManager {
         self.order_overflow = true;
     }
 
+    // The trader loop returns on its next pass
+    pub fn start_shutdown(&mut self) -> ShutdownMode {
+        self.shutting_down = true;
+        self.shutdown_mode
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
+    // The trader cancels everything and waits for end_resync() before reposting
+    pub fn start_resync(&mut self) {
+        self.resyncing = true;
+        self.order_overflow = true;
+    }
+
+    pub fn end_resync(&mut self) {
+        self.resyncing = false;
+    }
+
+    pub fn is_resyncing(&self) -> bool {
+        self.resyncing
+    }
+
     pub fn is_restart_needed(&self) -> bool {
         self.need_restart
     }
@@ -607,4 +772,271 @@ impl MemoryManager {
     pub fn activate_close_only(&mut self) {
         self.close_only = true;
     }
-}
\ No newline at end of file
+
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Equity is the wallet balance plus the positions marked to the market price, which moves
+    // between two account updates. Nothing is checked before the account and the book are known.
+    pub fn check_risk(&mut self) -> Option<(RiskAction, String)> {
+        let market_price = self.get_marketprice();
+        if self.balance <= 0.0 || market_price <= 0.0 {
+            return None
+        }
+        let long_un_pnl = if self.current_longs > 0.0 { (market_price - self.long_entry_price) * self.current_longs } else { 0.0 };
+        let short_un_pnl = if self.current_shorts > 0.0 { (self.short_entry_price - market_price) * self.current_shorts } else { 0.0 };
+        let net_notional = (self.current_longs - self.current_shorts) * market_price;
+        self.risk.check(self.balance + long_un_pnl + short_un_pnl, net_notional)
+    }
+
+    // Returns true when the grid has to react to the event
+    pub fn apply_event(&mut self, event: StateEvent) -> bool {
+        match event {
+            StateEvent::Tick { top_ask, top_bid, time } => {
+                let changed = top_ask != self.top_ask || top_bid != self.top_bid;
+                self.set_marketprice(top_ask, top_bid, time);
+                changed
+            },
+            StateEvent::Funding { rate, next_funding_time } => self.apply_funding(rate, next_funding_time),
+            StateEvent::User(UserDataEvent::OrderTradeUpdate { order }) => self.apply_order_update(&order),
+            StateEvent::User(UserDataEvent::AccountUpdate { account }) => self.apply_account_update(&account),
+            StateEvent::User(UserDataEvent::Unknown) => false
+        }
+    }
+
+    fn apply_order_update(&mut self, order: &OrderUpdate) -> bool {
+        if order.symbol != self.pair {
+            return false
+        }
+        self.journal.record(order);
+        let pair = self.pair.clone();
+        let (quantity_decimal_count, price_decimal_count) = (self.quantity_decimal_count as usize, self.price_decimal_count as usize);
+        let is_long = order.position_side == PositionSide::Long;
+        let is_open = (order.side == Side::Buy) == is_long;
+        match order.status {
+            OrderStatus::Expired => {
+                match (is_open, is_long) {
+                    (true, true) => {
+                        println!("\x1b[95m{} open long order expired\x1b[0m", pair);
+                        self.new_open_long_expired();
+                    },
+                    (false, true) => {
+                        println!("\x1b[95m{} close long order expired\x1b[0m", pair);
+                        self.new_close_finished(&order.client_order_id);
+                    },
+                    (true, false) => {
+                        println!("\x1b[95m{} open short order expired\x1b[0m", pair);
+                        self.new_open_short_expired();
+                    },
+                    (false, false) => {
+                        println!("\x1b[95m{} close short order expired\x1b[0m", pair);
+                        self.new_close_finished(&order.client_order_id);
+                    }
+                }
+                true
+            },
+            OrderStatus::Filled | OrderStatus::PartiallyFilled => {
+                let fully_filled = order.status == OrderStatus::Filled;
+                let filled = if fully_filled { "filled" } else { "PARTIALLY filled" };
+                let (quantity, price, comission, pnl) = (order.last_quantity, order.last_price, order.commission, order.realized_profit);
+                let side = if is_long { "LONG" } else { "SHORT" };
+                if is_open {
+                    println!("\x1b[92m{7} order {0} OPEN {2:.3$} {1} at {4:.5$} with {6:.4} comission\x1b[0m", filled, side, quantity, quantity_decimal_count, price, price_decimal_count, -comission, pair);
+                } else {
+                    println!("\x1b[92m{8} order {0} CLOSE {2:.3$} {1} at {4:.5$} with {6:.5$} PNL and {7:.4} comission\x1b[0m", filled, side, quantity, quantity_decimal_count, price, price_decimal_count, pnl, -comission, pair);
+                }
+                let open_delay = tokio::time::Instant::now() + Duration::from_secs(1);
+                match (is_open, is_long) {
+                    (true, true) => {
+                        self.new_open_long_filled(quantity * price, comission, pnl);
+                        if fully_filled {
+                            self.pending_long_open = Some((open_delay, price));
+                        }
+                    },
+                    (false, true) => {
+                        self.new_close_long_filled(quantity * price, comission, pnl);
+                        if fully_filled {
+                            self.new_close_finished(&order.client_order_id);
+                        }
+                    },
+                    (true, false) => {
+                        self.new_open_short_filled(quantity * price, comission, pnl);
+                        if fully_filled {
+                            self.pending_short_open = Some((open_delay, price));
+                        }
+                    },
+                    (false, false) => {
+                        self.new_close_short_filled(quantity * price, comission, pnl);
+                        if fully_filled {
+                            self.new_close_finished(&order.client_order_id);
+                        }
+                    }
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn apply_account_update(&mut self, account: &AccountUpdate) -> bool {
+        let pair = self.pair.clone();
+        let mut changed = false;
+        if let Some(balance) = account.balances.iter().find(|balance| balance.asset == self.token) {
+            self.set_balance(balance.wallet_balance);
+        }
+        for position in account.positions.iter().filter(|position| position.symbol == pair) {
+            match position.position_side {
+                PositionSide::Long => self.set_current_longs(position.position_amount, position.entry_price, position.unrealized_profit),
+                PositionSide::Short => self.set_current_shorts(-position.position_amount, position.entry_price, position.unrealized_profit),
+                PositionSide::Both => continue
+            }
+            changed = true;
+        }
+        self.update_imbalance();
+        changed
+    }
+
+    pub fn apply_account_info(&mut self, account_info: &AccountInfo) {
+        let pair = self.pair.clone();
+        self.set_vip_level(account_info.fee_tier);
+        if let Some(asset) = account_info.assets.iter().find(|asset| asset.asset == self.token) {
+            self.set_balance(asset.wallet_balance);
+        }
+        for position in account_info.positions.iter().filter(|position| position.symbol == pair) {
+            match position.position_side {
+                PositionSide::Long => self.set_current_longs(position.position_amt, position.entry_price, position.unrealized_profit),
+                PositionSide::Short => self.set_current_shorts(-position.position_amt, position.entry_price, position.unrealized_profit),
+                PositionSide::Both => ()
+            }
+        }
+        self.update_imbalance();
+        let position = self.get_hedged_position();
+        self.liquidation.apply_account(account_info.total_margin_balance, account_info.total_maint_margin, self.balance, self.long_un_pnl + self.short_un_pnl, &position, self.get_marketprice());
+    }
+
+    pub fn set_leverage_brackets(&mut self, brackets: Vec<LeverageBracket>) {
+        self.liquidation.set_brackets(brackets);
+    }
+
+    fn get_hedged_position(&self) -> HedgedPosition {
+        HedgedPosition {
+            longs: self.current_longs,
+            long_entry_price: self.long_entry_price,
+            shorts: self.current_shorts,
+            short_entry_price: self.short_entry_price
+        }
+    }
+
+    pub fn get_margin_estimate(&self) -> Option<MarginEstimate> {
+        self.liquidation.estimate(self.balance, &self.get_hedged_position(), self.get_marketprice())
+    }
+
+    // Re-estimates the margin ratio. Returns the new scale of the open pools when it changed, and
+    // the reason to go close-only once the ratio is past the threshold.
+    pub fn check_margin(&self) -> (Option<f64>, Option<String>) {
+        let estimate = match self.get_margin_estimate() {
+            Some(estimate) => estimate,
+            None => return (None, None)
+        };
+        let open_scale = self.liquidation.open_scale(estimate.margin_ratio);
+        let open_scale = if open_scale != self.open_scale {
+            println!("\x1b[93m{} margin ratio {:.1}%, open ladders at {:.0}% of their size\x1b[0m", self.pair, estimate.margin_ratio * 100.0, open_scale * 100.0);
+            Some(open_scale)
+        } else {
+            None
+        };
+        if self.liquidation.is_close_only(estimate.margin_ratio) && !self.close_only {
+            (open_scale, Some(format!("margin ratio {:.1}% above {:.1}%", estimate.margin_ratio * 100.0, self.liquidation.get_close_only_ratio() * 100.0)))
+        } else {
+            (open_scale, None)
+        }
+    }
+
+    pub fn set_open_scale(&mut self, open_scale: f64) {
+        self.open_scale = open_scale;
+    }
+
+    pub fn get_open_limits(&self) -> OpenLimits {
+        OpenLimits {
+            close_only: self.close_only,
+            open_scale: self.open_scale,
+            funding_bias: self.funding_bias,
+            imbalance: self.get_imbalance()
+        }
+    }
+
+    // Only wakes the trader when the bias changed, the stream updates every few seconds
+    pub fn apply_funding(&mut self, rate: f64, next_funding_time: u64) -> bool {
+        self.funding.apply(rate, next_funding_time);
+        let funding_bias = self.funding.bias(unix_millis());
+        if funding_bias == self.funding_bias {
+            return false
+        }
+        let paying = if rate > 0.0 { "longs" } else { "shorts" };
+        if funding_bias.trim_long || funding_bias.trim_short {
+            println!("\x1b[93m{} funding rate {:.4}%, trimming the {} before the settlement\x1b[0m", self.pair, rate * 100.0, paying);
+        } else if funding_bias == FundingBias::neutral() {
+            println!("\x1b[93m{} funding rate {:.4}%, ladders back to their size\x1b[0m", self.pair, rate * 100.0);
+        } else {
+            println!("\x1b[93m{} funding rate {:.4}%, skewing the {} ladder\x1b[0m", self.pair, rate * 100.0, paying);
+        }
+        self.funding_bias = funding_bias;
+        true
+    }
+
+    // Predicted rate, time of the coming settlement and fees of the session
+    pub fn get_funding(&self) -> (Option<(f64, u64)>, f64) {
+        (self.funding.get_prediction(), self.funding_paid)
+    }
+
+    pub fn is_funding_stale(&self) -> bool {
+        self.funding.is_stale(unix_millis())
+    }
+
+    pub fn funding_income_due(&mut self) -> Option<u64> {
+        self.funding.income_due(unix_millis())
+    }
+
+    pub fn apply_funding_income(&mut self, incomes: Vec<Income>) {
+        let incomes = incomes.into_iter().filter(|income| income.symbol == self.pair && income.income_type == "FUNDING_FEE").map(|income| (income.time, income.income)).collect();
+        for income in self.funding.apply_income(incomes) {
+            println!("\x1b[94m{} funding fee {:.4} {}\x1b[0m", self.pair, income, self.token);
+            self.funding_paid += income;
+            self.history.add_funding(income);
+        }
+    }
+
+    pub fn next_pending_open(&self) -> Option<tokio::time::Instant> {
+        [self.pending_long_open, self.pending_short_open].iter().flatten().map(|(deadline, _)| *deadline).min()
+    }
+
+    // The last open prices only move a second after the fill, once the grid has shifted
+    pub fn apply_pending_opens(&mut self) -> bool {
+        let now = tokio::time::Instant::now();
+        let mut changed = false;
+        if let Some((deadline, price)) = self.pending_long_open {
+            if deadline <= now {
+                self.set_last_long_open(price);
+                self.pending_long_open = None;
+                changed = true;
+            }
+        }
+        if let Some((deadline, price)) = self.pending_short_open {
+            if deadline <= now {
+                self.set_last_short_open(price);
+                self.pending_short_open = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
@@ -0,0 +1,408 @@
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, Duration};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+use crate::error::GateError;
+use crate::exchange::Exchange;
+use crate::order_manager::Order;
+use crate::models::{ApiError, LeverageBrackets, LeverageResponse, ListenKey, OrderResult, OrderStatus};
+use crate::simulator::{decimal_count, SimulatedExchange, SimulatorConfig, SimulatorSettings};
+
+// The optional "mock_server" section of the config, the engine keys are shared with the backtest
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct MockSettings {
+    rest_port: u16,
+    ws_port: u16,
+    start_price: f64,
+    pub tick_interval: u64,
+    volatility: f64,
+    #[serde(flatten)]
+    pub simulator: SimulatorConfig
+}
+
+impl Default for MockSettings {
+    fn default() -> Self {
+        MockSettings {
+            rest_port: 18080,
+            ws_port: 18081,
+            start_price: 20000.0,
+            tick_interval: 250,
+            volatility: 3.0,
+            simulator: SimulatorConfig::default()
+        }
+    }
+}
+
+impl MockSettings {
+    pub fn get_url_request(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rest_port)
+    }
+
+    pub fn get_url_websocket(&self) -> String {
+        format!("ws://127.0.0.1:{}", self.ws_port)
+    }
+}
+
+// Local stand-in for the Binance USDⓈ-M futures REST API and combined websocket stream.
+// Prices follow a random walk and orders are matched by the same engine as the backtest,
+// every pair has its own engine and wallet.
+struct MockServer {
+    engines: Vec<(String, SimulatedExchange)>,
+    listen_key: String,
+    // Stream name and message, each connection only forwards the streams it subscribed to
+    sender: broadcast::Sender<(String, String)>
+}
+
+impl MockServer {
+    fn engine(&self, symbol: &str) -> Option<&SimulatedExchange> {
+        self.engines.iter().find(|(pair, _)| pair == symbol).map(|(_, engine)| engine)
+    }
+}
+
+pub async fn start(pairs: Vec<String>, settings: MockSettings) {
+    let settings = Arc::new(settings);
+    let (sender, _) = broadcast::channel(4096);
+    let server = Arc::new(MockServer {
+        engines: pairs.iter().map(|pair| (pair.clone(), SimulatedExchange::new(pair.clone(), SimulatorSettings::new(pair, &settings.simulator)))).collect(),
+        listen_key: format!("mock{}", rand::thread_rng().gen_range(0..u32::MAX)),
+        sender
+    });
+    let rest_listener = TcpListener::bind(("127.0.0.1", settings.rest_port)).await.unwrap_or_else(|_| panic!("\x1b[91mERROR: Mock server failed to bind port {}\x1b[0m", settings.rest_port));
+    let ws_listener = TcpListener::bind(("127.0.0.1", settings.ws_port)).await.unwrap_or_else(|_| panic!("\x1b[91mERROR: Mock server failed to bind port {}\x1b[0m", settings.ws_port));
+    println!("\x1b[94mMock server listening on {} and {}\x1b[0m", settings.get_url_request(), settings.get_url_websocket());
+    tokio::spawn(task_rest(server.clone(), rest_listener));
+    tokio::spawn(task_ws(server.clone(), ws_listener));
+    for index in 0..server.engines.len() {
+        tokio::spawn(task_market(server.clone(), index, settings.clone()));
+    }
+}
+
+async fn task_market(server: Arc<MockServer>, index: usize, settings: Arc<MockSettings>) {
+    let (pair, engine) = &server.engines[index];
+    let tick_size = engine.get_tick_size();
+    let mut mid_price = (settings.start_price / tick_size).round() * tick_size;
+    let book_ticker = format!("{}@bookTicker", pair.to_lowercase());
+    let agg_trade = format!("{}@aggTrade", pair.to_lowercase());
+    let mark_price = format!("{}@markPrice", pair.to_lowercase());
+    let price_decimal_count = decimal_count(tick_size) as usize;
+    loop {
+        sleep(Duration::from_millis(settings.tick_interval)).await;
+        let step = rand::thread_rng().gen_range(-settings.volatility..=settings.volatility).round();
+        mid_price = (mid_price + step * tick_size).max(tick_size);
+        let top_bid = mid_price;
+        let top_ask = mid_price + tick_size;
+        engine.apply_tick(top_bid, top_ask);
+        let ticker = json!({
+            "stream": book_ticker,
+            "data": {
+                "e": "bookTicker",
+                "s": pair,
+                "b": format!("{0:.1$}", top_bid, price_decimal_count),
+                "B": "1.000",
+                "a": format!("{0:.1$}", top_ask, price_decimal_count),
+                "A": "1.000"
+            }
+        });
+        let _ = server.sender.send((book_ticker.clone(), ticker.to_string()));
+        // A taker sold into the bid when the price went down, bought the ask otherwise
+        let trade = json!({
+            "stream": agg_trade,
+            "data": {
+                "e": "aggTrade",
+                "s": pair,
+                "p": format!("{0:.1$}", if step < 0.0 { top_bid } else { top_ask }, price_decimal_count),
+                "q": format!("{:.3}", rand::thread_rng().gen_range(0.001..2.0)),
+                "m": step < 0.0
+            }
+        });
+        let _ = server.sender.send((agg_trade.clone(), trade.to_string()));
+        engine.settle_funding();
+        let premium_index = engine.get_premium_index(pair).await.unwrap();
+        let mark = json!({
+            "stream": mark_price,
+            "data": {
+                "e": "markPriceUpdate",
+                "s": pair,
+                "p": format!("{0:.1$}", premium_index.mark_price, price_decimal_count + 1),
+                "r": format!("{:.8}", premium_index.funding_rate),
+                "T": premium_index.next_funding_time
+            }
+        });
+        let _ = server.sender.send((mark_price.clone(), mark.to_string()));
+        for event in engine.take_events() {
+            let _ = server.sender.send((server.listen_key.clone(), json!({ "stream": server.listen_key, "data": event }).to_string()));
+        }
+    }
+}
+
+async fn task_ws(server: Arc<MockServer>, listener: TcpListener) {
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_ws_connection(server.clone(), stream));
+        }
+    }
+}
+
+async fn serve_ws_connection(server: Arc<MockServer>, stream: TcpStream) {
+    let mut streams: Vec<String> = vec![];
+    // Only the streams named in the url are forwarded, the error type is the one tungstenite expects
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        let query = request.uri().query().unwrap_or("");
+        streams = url::form_urlencoded::parse(query.as_bytes()).filter(|(key, _)| key == "streams").flat_map(|(_, value)| value.split('/').map(String::from).collect::<Vec<String>>()).collect();
+        Ok(response)
+    };
+    let mut socket = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(socket) => socket,
+        Err(_) => return
+    };
+    let mut receiver = server.sender.subscribe();
+    let mut ping_interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        tokio::select! {
+            msg = receiver.recv() => {
+                match msg {
+                    Ok((stream, _)) if !streams.contains(&stream) => (),
+                    Ok((_, msg)) => {
+                        if socket.send(Message::Text(msg)).await.is_err() {
+                            return
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => (),
+                    Err(broadcast::error::RecvError::Closed) => return
+                }
+            },
+            _ = ping_interval.tick() => {
+                if socket.send(Message::Ping(vec![])).await.is_err() {
+                    return
+                }
+            },
+            incoming = socket.next() => {
+                match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    _ => ()
+                }
+            }
+        }
+    }
+}
+
+async fn task_rest(server: Arc<MockServer>, listener: TcpListener) {
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_rest_connection(server.clone(), stream));
+        }
+    }
+}
+
+// Minimal HTTP/1.1 with keep-alive, parameters are read from the query string and the form body
+async fn serve_rest_connection(server: Arc<MockServer>, stream: TcpStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            return
+        }
+        let mut content_length: usize = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return
+        }
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("");
+        let target = parts.next().unwrap_or("");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        params.extend(url::form_urlencoded::parse(&body).into_owned());
+
+        let (status, resp) = handle_request(&server, method, path, &params).await;
+        let resp_str = resp.to_string();
+        let head = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: keep-alive\r\n\r\n", status, if status == 200 { "OK" } else { "Bad Request" }, resp_str.len());
+        if writer.write_all(head.as_bytes()).await.is_err() || writer.write_all(resp_str.as_bytes()).await.is_err() {
+            return
+        }
+    }
+}
+
+async fn handle_request(server: &MockServer, method: &str, path: &str, params: &HashMap<String, String>) -> (u16, Value) {
+    let param = |key: &str| params.get(key).cloned().unwrap_or_default();
+    match (method, path) {
+        ("GET", "/fapi/v1/exchangeInfo") => {
+            let mut exchange_info = server.engines[0].1.get_exchange_info().await.unwrap();
+            for (_, engine) in server.engines.iter().skip(1) {
+                exchange_info.symbols.extend(engine.get_exchange_info().await.unwrap().symbols);
+            }
+            (200, json!(exchange_info))
+        },
+        ("GET", "/fapi/v2/account") => {
+            // The wallets of all the pairs are summed per asset
+            let mut account_info = server.engines[0].1.get_account_info().await.unwrap();
+            for (_, engine) in server.engines.iter().skip(1) {
+                let engine_info = engine.get_account_info().await.unwrap();
+                account_info.total_maint_margin += engine_info.total_maint_margin;
+                account_info.total_margin_balance += engine_info.total_margin_balance;
+                for asset in engine_info.assets {
+                    match account_info.assets.iter_mut().find(|account_asset| account_asset.asset == asset.asset) {
+                        Some(account_asset) => account_asset.wallet_balance += asset.wallet_balance,
+                        None => account_info.assets.push(asset)
+                    }
+                }
+                account_info.positions.extend(engine_info.positions);
+            }
+            (200, json!(account_info))
+        },
+        ("POST", "/fapi/v1/listenKey") | ("PUT", "/fapi/v1/listenKey") => (200, json!(ListenKey { listen_key: server.listen_key.clone() })),
+        ("POST", "/fapi/v1/leverage") => (200, json!(LeverageResponse {
+            leverage: param("leverage").parse::<u64>().unwrap_or(20),
+            symbol: param("symbol")
+        })),
+        ("POST", "/fapi/v1/positionSide/dual") | ("POST", "/fapi/v1/marginType") | ("POST", "/fapi/v1/multiAssetsMargin") => (200, api_error(200, "success")),
+        ("POST", "/fapi/v1/batchOrders") => {
+            // Every order of a batch carries its own symbol
+            let batch_json: Vec<HashMap<String, String>> = serde_json::from_str(&param("batchOrders")).unwrap_or_default();
+            let mut results: Vec<OrderResult> = vec![];
+            for order_params in batch_json.iter() {
+                let symbol = order_params.get("symbol").cloned().unwrap_or_default();
+                results.push(match server.engine(&symbol) {
+                    Some(engine) => place_from_params(engine, &symbol, &|key: &str| order_params.get(key).cloned()).await,
+                    None => order_error(-1121, "Invalid symbol.")
+                });
+            }
+            (200, json!(results))
+        },
+        _ => {
+            let symbol = param("symbol");
+            match server.engine(&symbol) {
+                Some(engine) => handle_symbol_request(engine, &symbol, method, path, params).await,
+                None => (400, api_error(-1121, "Invalid symbol."))
+            }
+        }
+    }
+}
+
+async fn handle_symbol_request(engine: &SimulatedExchange, symbol: &str, method: &str, path: &str, params: &HashMap<String, String>) -> (u16, Value) {
+    let param = |key: &str| params.get(key).cloned().unwrap_or_default();
+    match (method, path) {
+        ("DELETE", "/fapi/v1/allOpenOrders") => {
+            engine.cancel_all_orders(symbol).await.unwrap();
+            (200, api_error(200, "The operation of cancel all open order is done."))
+        },
+        ("GET", "/fapi/v1/openOrders") => (200, json!(engine.get_open_orders(symbol).await.unwrap())),
+        ("GET", "/fapi/v1/order") => match engine.get_order(symbol, param("origClientOrderId").parse::<u32>().unwrap_or(0)).await {
+            Ok(response) => (200, json!(response)),
+            Err(GateError::Api(code, msg)) => (400, api_error(code.code(), &msg)),
+            Err(error) => (500, api_error(-1000, &error.to_string()))
+        },
+        ("GET", "/fapi/v1/leverageBracket") => (200, json!(LeverageBrackets {
+            symbol: String::from(symbol),
+            brackets: engine.get_leverage_brackets(symbol).await.unwrap()
+        })),
+        ("GET", "/fapi/v1/premiumIndex") => (200, json!(engine.get_premium_index(symbol).await.unwrap())),
+        ("GET", "/fapi/v1/income") => (200, json!(engine.get_funding_income(symbol, param("startTime").parse::<u64>().unwrap_or(0)).await.unwrap())),
+        ("POST", "/fapi/v1/order") => single_response(place_from_params(engine, symbol, &|key: &str| params.get(key).cloned()).await),
+        ("DELETE", "/fapi/v1/order") => single_response(cancel_from_id(engine, &param("origClientOrderId"))),
+        ("DELETE", "/fapi/v1/batchOrders") => {
+            let ids: Vec<String> = serde_json::from_str(&param("origClientOrderIdList")).unwrap_or_default();
+            let results: Vec<OrderResult> = ids.iter().map(|id| cancel_from_id(engine, id)).collect();
+            (200, json!(results))
+        },
+        _ => (404, api_error(-1000, "Unknown endpoint"))
+    }
+}
+
+// Hedge mode orders only: the side and position side tell if the order opens or closes a position
+async fn place_from_params(engine: &SimulatedExchange, symbol: &str, get: &(dyn Fn(&str) -> Option<String> + Sync)) -> OrderResult {
+    if get("type").as_deref() == Some("MARKET") {
+        return close_from_params(engine, symbol, get).await
+    }
+    let price = get("price").and_then(|price| price.parse::<f64>().ok());
+    let quantity = get("quantity").and_then(|quantity| quantity.parse::<f64>().ok());
+    let (price, quantity) = match (price, quantity) {
+        (Some(price), Some(quantity)) if quantity > 0.0 => (price, quantity),
+        _ => return order_error(-1102, "Mandatory parameter 'price' or 'quantity' was not sent, was empty/null, or malformed.")
+    };
+    let is_long = get("positionSide").as_deref() == Some("LONG");
+    let is_buy_side = get("side").as_deref() == Some("BUY");
+    let is_open = is_long == is_buy_side;
+    let order_id = get("newClientOrderId").and_then(|id| id.parse::<u32>().ok()).unwrap_or_else(|| rand::thread_rng().gen_range(0..u32::MAX));
+    let order = Order::with_id(price, quantity, is_open, is_long, order_id);
+    if !is_open {
+        let (long_quantity, short_quantity) = engine.get_positions();
+        let position = if is_long { long_quantity } else { short_quantity };
+        if quantity > position + engine.get_step_size() / 2.0 {
+            return order_error(-2022, "ReduceOnly Order is rejected.")
+        }
+    }
+    // Like Binance, a post-only order that would take is rejected instead of expiring
+    if get("timeInForce").as_deref() == Some("GTX") && engine.would_take(&order) {
+        return order_error(-5022, "Due to the order could not be executed as maker, the Post Only order will be rejected.")
+    }
+    engine.place_order(symbol, &order, 0, 0).await;
+    order_response(engine, &order, OrderStatus::New)
+}
+
+// Only closing market orders are supported, as sent on shutdown
+async fn close_from_params(engine: &SimulatedExchange, symbol: &str, get: &(dyn Fn(&str) -> Option<String> + Sync)) -> OrderResult {
+    let is_long = get("positionSide").as_deref() == Some("LONG");
+    let is_buy_side = get("side").as_deref() == Some("BUY");
+    let quantity = match get("quantity").and_then(|quantity| quantity.parse::<f64>().ok()) {
+        Some(quantity) if is_long != is_buy_side => quantity,
+        _ => return order_error(-1116, "Invalid orderType.")
+    };
+    match engine.close_position(symbol, is_long, quantity, 0).await {
+        Ok(()) => order_response(engine, &Order::new(0.0, quantity, false, is_long), OrderStatus::Filled),
+        Err(GateError::Api(code, msg)) => order_error(code.code(), &msg),
+        Err(error) => order_error(-1000, &error.to_string())
+    }
+}
+
+fn cancel_from_id(engine: &SimulatedExchange, id: &str) -> OrderResult {
+    match id.parse::<u32>().ok().and_then(|order_id| engine.cancel_by_id(order_id)) {
+        Some(order) => order_response(engine, &order, OrderStatus::Canceled),
+        None => order_error(-2011, "Unknown order sent.")
+    }
+}
+
+fn order_response(engine: &SimulatedExchange, order: &Order, status: OrderStatus) -> OrderResult {
+    OrderResult::Order(engine.order_response(order, status))
+}
+
+fn single_response(result: OrderResult) -> (u16, Value) {
+    match result {
+        OrderResult::Order(_) => (200, json!(result)),
+        OrderResult::Error(_) => (400, json!(result))
+    }
+}
+
+fn order_error(code: i64, msg: &str) -> OrderResult {
+    OrderResult::Error(ApiError { code, msg: String::from(msg) })
+}
+
+fn api_error(code: i64, msg: &str) -> Value {
+    json!(ApiError { code, msg: String::from(msg) })
+}
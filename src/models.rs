@@ -0,0 +1,391 @@
+use serde::{Deserialize, Serialize};
+
+// Binance sends most numbers as strings, these are read from either a string or a number
+// and written back as strings so the mock server payloads look like the real ones.
+mod string_f64 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(f64)
+    }
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(value) => value.parse::<f64>().map_err(serde::de::Error::custom),
+            StringOrNumber::Number(value) => Ok(value)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Side {
+    Buy,
+    Sell
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PositionSide {
+    Long,
+    Short,
+    Both
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    Rejected,
+    Expired,
+    #[serde(other)]
+    Unknown
+}
+
+// Combined stream wrapper: {"stream": "<name>", "data": {...}}
+#[derive(Deserialize)]
+pub struct StreamMessage {
+    pub stream: String,
+    pub data: serde_json::Value
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BookTicker {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b", with = "string_f64")]
+    pub best_bid: f64,
+    #[serde(rename = "B", with = "string_f64", default)]
+    pub best_bid_quantity: f64,
+    #[serde(rename = "a", with = "string_f64")]
+    pub best_ask: f64,
+    #[serde(rename = "A", with = "string_f64", default)]
+    pub best_ask_quantity: f64,
+    #[serde(rename = "E", default)]
+    pub event_time: u64
+}
+
+// Trades aggregated by taker order, only used by paper trading
+#[derive(Serialize, Deserialize)]
+pub struct AggTrade {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "p", with = "string_f64")]
+    pub price: f64,
+    #[serde(rename = "q", with = "string_f64")]
+    pub quantity: f64,
+    #[serde(rename = "m")]
+    pub buyer_is_maker: bool
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "e")]
+pub enum UserDataEvent {
+    #[serde(rename = "ORDER_TRADE_UPDATE")]
+    OrderTradeUpdate {
+        #[serde(rename = "o")]
+        order: OrderUpdate
+    },
+    #[serde(rename = "ACCOUNT_UPDATE")]
+    AccountUpdate {
+        #[serde(rename = "a")]
+        account: AccountUpdate
+    },
+    #[serde(other)]
+    Unknown
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrderUpdate {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "c")]
+    pub client_order_id: String,
+    #[serde(rename = "S")]
+    pub side: Side,
+    #[serde(rename = "ps")]
+    pub position_side: PositionSide,
+    #[serde(rename = "X")]
+    pub status: OrderStatus,
+    #[serde(rename = "p", with = "string_f64")]
+    pub price: f64,
+    #[serde(rename = "q", with = "string_f64")]
+    pub quantity: f64,
+    #[serde(rename = "L", with = "string_f64")]
+    pub last_price: f64,
+    #[serde(rename = "l", with = "string_f64")]
+    pub last_quantity: f64,
+    // Not pushed when there is no commission
+    #[serde(rename = "n", with = "string_f64", default)]
+    pub commission: f64,
+    #[serde(rename = "rp", with = "string_f64", default)]
+    pub realized_profit: f64,
+    // Transaction time in ms, 0 when the source has none and the local clock is used
+    #[serde(rename = "T", default)]
+    pub trade_time: u64
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AccountUpdate {
+    #[serde(rename = "B", default)]
+    pub balances: Vec<BalanceUpdate>,
+    #[serde(rename = "P", default)]
+    pub positions: Vec<PositionUpdate>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BalanceUpdate {
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(rename = "wb", with = "string_f64")]
+    pub wallet_balance: f64
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PositionUpdate {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "ps")]
+    pub position_side: PositionSide,
+    #[serde(rename = "pa", with = "string_f64")]
+    pub position_amount: f64,
+    #[serde(rename = "ep", with = "string_f64")]
+    pub entry_price: f64,
+    #[serde(rename = "up", with = "string_f64")]
+    pub unrealized_profit: f64
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExchangeInfo {
+    pub symbols: Vec<SymbolInfo>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub status: String,
+    pub margin_asset: String,
+    pub price_precision: i64,
+    pub quantity_precision: i64,
+    pub filters: Vec<SymbolFilter>,
+    pub order_types: Vec<String>,
+    pub time_in_force: Vec<String>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "filterType")]
+pub enum SymbolFilter {
+    #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+    PriceFilter {
+        #[serde(with = "string_f64")]
+        tick_size: f64
+    },
+    #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+    LotSize {
+        #[serde(with = "string_f64")]
+        step_size: f64,
+        #[serde(with = "string_f64")]
+        min_qty: f64,
+        #[serde(with = "string_f64")]
+        max_qty: f64
+    },
+    #[serde(rename = "MAX_NUM_ORDERS")]
+    MaxNumOrders {
+        limit: u64
+    },
+    #[serde(rename = "MIN_NOTIONAL")]
+    MinNotional {
+        #[serde(with = "string_f64")]
+        notional: f64
+    },
+    #[serde(other)]
+    Other
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    pub fee_tier: u64,
+    #[serde(default)]
+    pub can_trade: bool,
+    // Cross margin totals of the whole account, the margin ratio is their quotient
+    #[serde(default, with = "string_f64")]
+    pub total_maint_margin: f64,
+    #[serde(default, with = "string_f64")]
+    pub total_margin_balance: f64,
+    pub assets: Vec<AccountAsset>,
+    pub positions: Vec<AccountPosition>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountAsset {
+    pub asset: String,
+    #[serde(with = "string_f64")]
+    pub wallet_balance: f64
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountPosition {
+    pub symbol: String,
+    pub position_side: PositionSide,
+    #[serde(with = "string_f64")]
+    pub position_amt: f64,
+    #[serde(with = "string_f64")]
+    pub entry_price: f64,
+    #[serde(with = "string_f64")]
+    pub unrealized_profit: f64
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResponse {
+    pub symbol: String,
+    pub status: OrderStatus,
+    pub client_order_id: String,
+    #[serde(with = "string_f64")]
+    pub price: f64,
+    #[serde(with = "string_f64")]
+    pub orig_qty: f64,
+    #[serde(with = "string_f64", default)]
+    pub executed_qty: f64,
+    // 0 until the order traded
+    #[serde(with = "string_f64", default)]
+    pub avg_price: f64,
+    #[serde(default)]
+    pub update_time: u64,
+    pub side: Side,
+    pub position_side: PositionSide
+}
+
+impl OrderResponse {
+    // The ORDER_TRADE_UPDATE a lost fill would have pushed, the commission and the PNL are left
+    // to the next account refresh
+    pub fn to_fill_update(&self) -> OrderUpdate {
+        OrderUpdate {
+            symbol: self.symbol.clone(),
+            client_order_id: self.client_order_id.clone(),
+            side: self.side,
+            position_side: self.position_side,
+            status: self.status,
+            price: self.price,
+            quantity: self.orig_qty,
+            last_price: if self.avg_price > 0.0 { self.avg_price } else { self.price },
+            last_quantity: self.executed_qty,
+            commission: 0.0,
+            realized_profit: 0.0,
+            trade_time: self.update_time
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: i64,
+    pub msg: String
+}
+
+// One entry of a single or batch order response
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OrderResult {
+    Order(OrderResponse),
+    Error(ApiError)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OrderResults {
+    Batch(Vec<OrderResult>),
+    Single(OrderResult)
+}
+
+impl OrderResults {
+    pub fn into_vec(self) -> Vec<OrderResult> {
+        match self {
+            OrderResults::Batch(results) => results,
+            OrderResults::Single(result) => vec![result]
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenKey {
+    pub listen_key: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeverageResponse {
+    pub leverage: u64,
+    pub symbol: String
+}
+
+// /fapi/v1/leverageBracket with a symbol
+#[derive(Serialize, Deserialize)]
+pub struct LeverageBrackets {
+    pub symbol: String,
+    pub brackets: Vec<LeverageBracket>
+}
+
+// Maintenance margin of a position whose notional is between floor and cap: notional × ratio - cum
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeverageBracket {
+    pub bracket: u64,
+    pub initial_leverage: u64,
+    pub notional_cap: f64,
+    pub notional_floor: f64,
+    pub maint_margin_ratio: f64,
+    pub cum: f64
+}
+
+// /fapi/v1/premiumIndex with a symbol, lastFundingRate is the rate of the coming settlement
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumIndex {
+    pub symbol: String,
+    #[serde(with = "string_f64")]
+    pub mark_price: f64,
+    #[serde(rename = "lastFundingRate", with = "string_f64")]
+    pub funding_rate: f64,
+    pub next_funding_time: u64
+}
+
+// The markPrice stream carries the same fields as the premium index
+#[derive(Serialize, Deserialize)]
+pub struct MarkPriceUpdate {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "p", with = "string_f64")]
+    pub mark_price: f64,
+    #[serde(rename = "r", with = "string_f64")]
+    pub funding_rate: f64,
+    #[serde(rename = "T")]
+    pub next_funding_time: u64
+}
+
+// One entry of /fapi/v1/income, income is positive when received
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Income {
+    pub symbol: String,
+    pub income_type: String,
+    #[serde(with = "string_f64")]
+    pub income: f64,
+    pub asset: String,
+    pub time: u64,
+    pub tran_id: u64
+}
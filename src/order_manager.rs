@@ -1,4 +1,5 @@
 use rand::Rng;
+use crate::models::{OrderResponse, PositionSide, Side};
 
 #[derive(Clone)]
 #[derive(Debug)]
@@ -21,6 +22,24 @@ impl Order {
         }
     }
 
+    pub fn with_id(price: f64, quantity: f64, is_open: bool, is_long: bool, order_id: u32) -> Self {
+        Order {
+            price,
+            quantity,
+            is_open,
+            is_long,
+            order_id
+        }
+    }
+
+    // None if the order was not placed by the bot, its client id is not one of ours
+    pub fn from_response(response: &OrderResponse) -> Option<Self> {
+        let order_id = response.client_order_id.parse::<u32>().ok()?;
+        let is_long = response.position_side == PositionSide::Long;
+        let is_open = (response.side == Side::Buy) == is_long;
+        Some(Order::with_id(response.price, response.orig_qty, is_open, is_long, order_id))
+    }
+
     pub fn get_price(&self) -> f64 {
         self.price
     }
@@ -33,6 +52,26 @@ impl Order {
         self.quantity = quantity;
     }
 
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    pub fn is_long(&self) -> bool {
+        self.is_long
+    }
+
+    pub fn is_buy(&self) -> bool {
+        self.is_open == self.is_long
+    }
+
+    pub fn get_side(&self) -> Side {
+        if self.is_buy() { Side::Buy } else { Side::Sell }
+    }
+
+    pub fn get_position_side(&self) -> PositionSide {
+        if self.is_long { PositionSide::Long } else { PositionSide::Short }
+    }
+
     pub fn is_real(&self) -> bool {
         self.quantity > 0.0
     }
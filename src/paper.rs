@@ -0,0 +1,278 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use crate::error::GateError;
+use crate::exchange::Exchange;
+use crate::models::{AccountAsset, AccountInfo, AggTrade, BookTicker, ExchangeInfo, Income, LeverageBracket, OrderResponse, PremiumIndex, StreamMessage, SymbolFilter, UserDataEvent};
+use crate::order_manager::Order;
+use crate::simulator::{SimulatedExchange, SimulatorConfig, SimulatorSettings};
+use crate::state_actor::{MemHandle, StateEvent};
+use crate::{MAX_RECONNECT_DELAY, PING_TIMEOUT};
+
+pub const URL_REQUEST: &str = "https://fapi.binance.com";
+pub const URL_WEBSOCKET: &str = "wss://fstream.binance.com";
+
+// Paper trading: the grids follow the real mainnet book but their orders are matched by a local
+// engine per pair, which keeps its own wallet and queue model. Nothing is sent to the exchange
+// and no API key is needed, the fills come back as the events the user data stream would push.
+pub struct PaperExchange {
+    exchange_info: ExchangeInfo,
+    engines: HashMap<String, SimulatedExchange>,
+    sender: mpsc::UnboundedSender<UserDataEvent>
+}
+
+impl PaperExchange {
+    // The trading rules of the real symbols are public, the engines use their tick and step sizes
+    pub async fn new(url_request: &str, pairs: &[String], config: &SimulatorConfig) -> Result<(Self, mpsc::UnboundedReceiver<UserDataEvent>), String> {
+        let url = format!("{}/fapi/v1/exchangeInfo", url_request);
+        let text = reqwest::get(&url).await.map_err(|error| format!("Failed to get {}: {}", url, error))?.text().await.map_err(|error| format!("Failed to get {}: {}", url, error))?;
+        let exchange_info: ExchangeInfo = serde_json::from_str(&text).map_err(|error| format!("Invalid exchange info from {}: {}", url, error))?;
+        let mut engines: HashMap<String, SimulatedExchange> = HashMap::new();
+        for pair in pairs.iter() {
+            let symbol_info = exchange_info.symbols.iter().find(|symbol_info| symbol_info.symbol == *pair).ok_or(format!("The pair {} does not exists", pair))?;
+            let (mut tick_size, mut step_size) = (0.0, 0.0);
+            for filter in symbol_info.filters.iter() {
+                match filter {
+                    SymbolFilter::PriceFilter { tick_size: size } => tick_size = *size,
+                    SymbolFilter::LotSize { step_size: size, .. } => step_size = *size,
+                    _ => {}
+                }
+            }
+            if tick_size <= 0.0 || step_size <= 0.0 {
+                return Err(format!("The pair {} has no PRICE_FILTER or LOT_SIZE filter", pair))
+            }
+            let settings = SimulatorSettings::new(pair, config).with_filters(tick_size, step_size);
+            engines.insert(pair.clone(), SimulatedExchange::new(pair.clone(), settings).with_queue_model());
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Ok((PaperExchange { exchange_info, engines, sender }, receiver))
+    }
+
+    fn engine(&self, pair: &str) -> Result<&SimulatedExchange, GateError> {
+        self.engines.get(pair).ok_or_else(|| GateError::Parse(format!("no paper engine for {}", pair)))
+    }
+
+    // Hands the events queued by the engines to the market task
+    fn flush(&self) {
+        for engine in self.engines.values() {
+            for event in engine.take_events() {
+                let _ = self.sender.send(event);
+            }
+        }
+    }
+
+    pub fn apply_book(&self, ticker: &BookTicker) {
+        if let Some(engine) = self.engines.get(&ticker.symbol) {
+            engine.apply_book(ticker.best_bid, ticker.best_bid_quantity, ticker.best_ask, ticker.best_ask_quantity);
+            engine.settle_funding();
+        }
+        self.flush();
+    }
+
+    pub fn apply_trade(&self, trade: &AggTrade) {
+        if let Some(engine) = self.engines.get(&trade.symbol) {
+            engine.apply_trade(trade.price, trade.quantity, trade.buyer_is_maker);
+        }
+        self.flush();
+    }
+}
+
+#[async_trait]
+impl Exchange for PaperExchange {
+    async fn get_exchange_info(&self) -> Result<ExchangeInfo, GateError> {
+        Ok(ExchangeInfo {
+            symbols: self.exchange_info.symbols.iter().filter(|symbol_info| self.engines.contains_key(&symbol_info.symbol)).cloned().collect()
+        })
+    }
+
+    // The wallets of the engines sharing a margin asset are added up, like one account would
+    async fn get_account_info(&self) -> Result<AccountInfo, GateError> {
+        let mut account_info = AccountInfo { fee_tier: 0, can_trade: true, total_maint_margin: 0.0, total_margin_balance: 0.0, assets: vec![], positions: vec![] };
+        for engine in self.engines.values() {
+            let engine_info = engine.get_account_info().await?;
+            account_info.total_maint_margin += engine_info.total_maint_margin;
+            account_info.total_margin_balance += engine_info.total_margin_balance;
+            for asset in engine_info.assets {
+                match account_info.assets.iter_mut().find(|existing| existing.asset == asset.asset) {
+                    Some(existing) => existing.wallet_balance += asset.wallet_balance,
+                    None => account_info.assets.push(AccountAsset { asset: asset.asset, wallet_balance: asset.wallet_balance })
+                }
+            }
+            account_info.positions.extend(engine_info.positions);
+        }
+        Ok(account_info)
+    }
+
+    // The real brackets need an API key, the engines use their simulated tier
+    async fn get_leverage_brackets(&self, pair: &str) -> Result<Vec<LeverageBracket>, GateError> {
+        self.engine(pair)?.get_leverage_brackets(pair).await
+    }
+
+    // The engines settle their own configured rate, not the real one
+    async fn get_premium_index(&self, pair: &str) -> Result<PremiumIndex, GateError> {
+        self.engine(pair)?.get_premium_index(pair).await
+    }
+
+    async fn get_funding_income(&self, pair: &str, start_time: u64) -> Result<Vec<Income>, GateError> {
+        self.engine(pair)?.get_funding_income(pair, start_time).await
+    }
+
+    async fn get_listen_key(&self) -> Result<String, GateError> {
+        Ok(String::from("paper"))
+    }
+
+    async fn keepalive_listen_key(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_leverage(&self, _pair: &str, _leverage: u64) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_position_mode(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_multiassets_mode(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_margin_mode(&self, _pair: &str) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn cancel_all_orders(&self, pair: &str) -> Result<(), GateError> {
+        self.engine(pair)?.cancel_all_orders(pair).await
+    }
+
+    async fn get_open_orders(&self, pair: &str) -> Result<Vec<OrderResponse>, GateError> {
+        self.engine(pair)?.get_open_orders(pair).await
+    }
+
+    async fn get_order(&self, pair: &str, order_id: u32) -> Result<OrderResponse, GateError> {
+        self.engine(pair)?.get_order(pair, order_id).await
+    }
+
+    async fn close_position(&self, pair: &str, is_long: bool, quantity: f64, quantity_decimal_count: i64) -> Result<(), GateError> {
+        let result = self.engine(pair)?.close_position(pair, is_long, quantity, quantity_decimal_count).await;
+        self.flush();
+        result
+    }
+
+    async fn place_order(&self, pair: &str, order: &Order, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        self.place_orders(pair, vec![order.clone()], price_decimal_count, quantity_decimal_count).await
+    }
+
+    async fn cancel_order(&self, pair: &str, order: &Order) -> bool {
+        self.cancel_orders(pair, vec![order.clone()]).await
+    }
+
+    async fn place_orders(&self, pair: &str, orders: Vec<Order>, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        let success = match self.engine(pair) {
+            Ok(engine) => engine.place_orders(pair, orders, price_decimal_count, quantity_decimal_count).await,
+            Err(_) => false
+        };
+        self.flush();
+        success
+    }
+
+    async fn cancel_orders(&self, pair: &str, orders: Vec<Order>) -> bool {
+        let success = match self.engine(pair) {
+            Ok(engine) => engine.cancel_orders(pair, orders).await,
+            Err(_) => false
+        };
+        self.flush();
+        success
+    }
+}
+
+// The bookTicker and aggTrade streams of every pair feed the engines. The grids get the ticks
+// and the synthetic user data events, which are delivered before the next market message.
+pub async fn task_market(paper: Arc<PaperExchange>, mut events: mpsc::UnboundedReceiver<UserDataEvent>, base_url: String, grids: Vec<MemHandle>) {
+    let mut pairs: Vec<String> = vec![];
+    for mem in grids.iter() {
+        pairs.push(mem.query(|local_mem| local_mem.get_pair()).await);
+    }
+    let streams = pairs.iter().map(|pair| format!("{0}@bookTicker/{0}@aggTrade", pair.to_lowercase())).collect::<Vec<String>>().join("/");
+    let mut socket = connect(&base_url, &streams).await;
+    let mut last_ping = Instant::now();
+    loop {
+        tokio::select! {
+            biased;
+            event = events.recv() => match event {
+                Some(event) => for mem in grids.iter() {
+                    mem.send(StateEvent::User(event.clone()));
+                },
+                None => return
+            },
+            msg = timeout_at(last_ping + PING_TIMEOUT, socket.next()) => {
+                let msg = match msg {
+                    Ok(Some(Ok(msg))) if !msg.is_close() => Ok(msg),
+                    Ok(Some(Ok(_))) | Ok(None) => Err(String::from("closed by the server")),
+                    Ok(Some(Err(error))) => Err(error.to_string()),
+                    Err(_) => Err(format!("no ping for {}s", PING_TIMEOUT.as_secs()))
+                };
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(reason) => {
+                        // The engines only see the market through this socket, nothing needs a resync
+                        println!("\x1b[91mPaper market stream lost: {}\x1b[0m", reason);
+                        socket = connect(&base_url, &streams).await;
+                        last_ping = Instant::now();
+                        continue
+                    }
+                };
+                if msg.is_ping() {
+                    last_ping = Instant::now();
+                } else if msg.is_text() {
+                    let stream_message: StreamMessage = match serde_json::from_str(msg.to_text().unwrap()) {
+                        Ok(stream_message) => stream_message,
+                        Err(error) => {
+                            println!("\x1b[91mSkipping malformed websocket message ({}): {}\x1b[0m", error, msg);
+                            continue
+                        }
+                    };
+                    if stream_message.stream.ends_with("@bookTicker") {
+                        match BookTicker::deserialize(&stream_message.data) {
+                            Ok(ticker) => if let Some(index) = pairs.iter().position(|pair| *pair == ticker.symbol) {
+                                paper.apply_book(&ticker);
+                                grids[index].send(StateEvent::Tick { top_ask: ticker.best_ask, top_bid: ticker.best_bid, time: ticker.event_time });
+                            },
+                            Err(error) => println!("\x1b[91mSkipping malformed bookTicker ({}): {}\x1b[0m", error, stream_message.data)
+                        }
+                    } else {
+                        match AggTrade::deserialize(&stream_message.data) {
+                            Ok(trade) => paper.apply_trade(&trade),
+                            Err(error) => println!("\x1b[91mSkipping malformed aggTrade ({}): {}\x1b[0m", error, stream_message.data)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Public market streams only, there is no listen key to fetch
+async fn connect(base_url: &str, streams: &str) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let url = url::Url::parse(&format!("{}/stream?streams={}", base_url, streams)).unwrap();
+        match tokio_tungstenite::connect_async(url).await {
+            Ok((socket, _)) => {
+                println!("Connected to the paper market stream");
+                return socket
+            },
+            Err(error) => {
+                println!("\x1b[91mFailed to connect the paper market stream: {}, retrying in {}s\x1b[0m", error, delay.as_secs());
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Duration};
+
+// The optional "rate_limit" section of the config: budgets per window, and the share
+// of each budget only high priority requests can use
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    pub weight_1m: u64,
+    pub orders_10s: u64,
+    pub orders_1m: u64,
+    pub reserve: f64
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        RateLimitSettings {
+            weight_1m: 2400,
+            orders_10s: 300,
+            orders_1m: 1200,
+            reserve: 0.2
+        }
+    }
+}
+
+// Close orders may use the reserve, open levels and other requests wait for the next window
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    High
+}
+
+// What a request takes from the budgets
+#[derive(Clone, Copy)]
+pub struct Cost {
+    pub weight: u64,
+    pub orders: u64,
+    pub priority: Priority
+}
+
+impl Cost {
+    pub fn new(weight: u64, orders: u64, priority: Priority) -> Self {
+        Cost { weight, orders, priority }
+    }
+}
+
+// Usage over a fixed window aligned on the clock, like the Binance counters
+struct Counter {
+    window_ms: u64,
+    window: u64,
+    used: u64
+}
+
+impl Counter {
+    fn new(window_ms: u64) -> Self {
+        Counter { window_ms, window: 0, used: 0 }
+    }
+
+    fn roll(&mut self, now: u64) {
+        if now / self.window_ms != self.window {
+            self.window = now / self.window_ms;
+            self.used = 0;
+        }
+    }
+
+    fn fits(&self, cost: u64, limit: u64) -> bool {
+        cost == 0 || self.used == 0 || self.used + cost <= limit
+    }
+
+    fn until_next_window(&self, now: u64) -> u64 {
+        (self.window + 1) * self.window_ms - now
+    }
+
+    // The header counts the requests of every process sharing the IP or the account
+    fn sync(&mut self, now: u64, used: u64) {
+        self.roll(now);
+        self.used = self.used.max(used);
+    }
+}
+
+struct LimiterState {
+    weight_1m: Counter,
+    orders_10s: Counter,
+    orders_1m: Counter,
+    banned_until: u64
+}
+
+// Shared by every request of the REST client, whatever the grid
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    state: Mutex<LimiterState>
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        RateLimiter {
+            settings,
+            state: Mutex::new(LimiterState {
+                weight_1m: Counter::new(60000),
+                orders_10s: Counter::new(10000),
+                orders_1m: Counter::new(60000),
+                banned_until: 0
+            })
+        }
+    }
+
+    // Waits until the request fits in every budget, then books it
+    pub async fn acquire(&self, cost: Cost) {
+        loop {
+            let wait = self.try_acquire(cost);
+            if wait == 0 {
+                return
+            }
+            println!("\x1b[93mRate limit budget used up, waiting {}ms\x1b[0m", wait);
+            sleep(Duration::from_millis(wait)).await;
+        }
+    }
+
+    // Returns 0 once booked, otherwise the milliseconds to wait
+    fn try_acquire(&self, cost: Cost) -> u64 {
+        let now = now_ms();
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if state.banned_until > now {
+            return state.banned_until - now
+        }
+        let share = if cost.priority == Priority::High { 1.0 } else { 1.0 - self.settings.reserve };
+        let limit = |limit: u64| (limit as f64 * share) as u64;
+        let mut wait = 0;
+        for (counter, cost, limit) in [
+            (&mut state.weight_1m, cost.weight, limit(self.settings.weight_1m)),
+            (&mut state.orders_10s, cost.orders, limit(self.settings.orders_10s)),
+            (&mut state.orders_1m, cost.orders, limit(self.settings.orders_1m))
+        ] {
+            counter.roll(now);
+            if !counter.fits(cost, limit) {
+                wait = wait.max(counter.until_next_window(now));
+            }
+        }
+        if wait == 0 {
+            state.weight_1m.used += cost.weight;
+            state.orders_10s.used += cost.orders;
+            state.orders_1m.used += cost.orders;
+        }
+        wait
+    }
+
+    // Syncs the budgets with the usage headers, and stops every request on a 429 or 418
+    pub fn update(&self, status: StatusCode, headers: &HeaderMap) {
+        let now = now_ms();
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<u64>().ok());
+        let state = &mut self.state.lock().unwrap();
+        if let Some(used) = header("x-mbx-used-weight-1m") {
+            state.weight_1m.sync(now, used);
+        }
+        if let Some(used) = header("x-mbx-order-count-10s") {
+            state.orders_10s.sync(now, used);
+        }
+        if let Some(used) = header("x-mbx-order-count-1m") {
+            state.orders_1m.sync(now, used);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::IM_A_TEAPOT {
+            let retry_after = header("retry-after").unwrap_or(60);
+            state.banned_until = state.banned_until.max(now + retry_after * 1000);
+            println!("\x1b[91mRate limited with HTTP {}, pausing requests for {}s\x1b[0m", status.as_u16(), retry_after);
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_resets_on_a_new_window() {
+        let mut counter = Counter::new(10000);
+        counter.roll(25000);
+        counter.used = 8;
+        assert!(counter.fits(2, 10));
+        assert!(!counter.fits(3, 10));
+        assert_eq!(counter.until_next_window(25000), 5000);
+        counter.roll(29999);
+        assert_eq!(counter.used, 8);
+        counter.roll(30000);
+        assert_eq!(counter.used, 0);
+    }
+
+    #[test]
+    fn counter_always_lets_a_first_request_through() {
+        let counter = Counter::new(10000);
+        assert!(counter.fits(50, 10));
+        assert!(counter.fits(0, 0));
+    }
+
+    #[test]
+    fn counter_sync_keeps_the_highest_usage() {
+        let mut counter = Counter::new(60000);
+        counter.roll(1000);
+        counter.used = 30;
+        counter.sync(2000, 20);
+        assert_eq!(counter.used, 30);
+        counter.sync(2000, 45);
+        assert_eq!(counter.used, 45);
+    }
+
+    #[test]
+    fn try_acquire_keeps_the_reserve_for_high_priority() {
+        let limiter = RateLimiter::new(RateLimitSettings { weight_1m: 2400, orders_10s: 10, orders_1m: 1200, reserve: 0.2 });
+        for _ in 0..8 {
+            assert_eq!(limiter.try_acquire(Cost::new(1, 1, Priority::Normal)), 0);
+        }
+        assert!(limiter.try_acquire(Cost::new(1, 1, Priority::Normal)) > 0);
+        assert_eq!(limiter.try_acquire(Cost::new(1, 2, Priority::High)), 0);
+        assert!(limiter.try_acquire(Cost::new(1, 1, Priority::High)) > 0);
+    }
+
+    #[test]
+    fn try_acquire_waits_out_a_ban() {
+        let limiter = RateLimiter::new(RateLimitSettings::default());
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "30".parse().unwrap());
+        limiter.update(StatusCode::TOO_MANY_REQUESTS, &headers);
+        let wait = limiter.try_acquire(Cost::new(1, 0, Priority::High));
+        assert!(wait > 29000 && wait <= 30000, "waiting {}ms", wait);
+    }
+}
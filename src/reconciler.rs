@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use tokio::time::{Duration, Instant};
+use crate::models::{OrderResponse, OrderStatus};
+use crate::order_manager::Order;
+
+// Compares the grid the trader thinks it has with the open orders on the exchange.
+// An order missing from the exchange is only looked up if it is still missing on the next run,
+// so a fill whose event has not been handled yet is not taken for a lost order.
+pub struct Reconciler {
+    interval: Duration,
+    last_run: Instant,
+    missing: HashSet<u32>
+}
+
+impl Reconciler {
+    // An interval of 0 disables the reconciler
+    pub fn new(interval: u64) -> Self {
+        Reconciler {
+            interval: Duration::from_secs(interval),
+            last_run: Instant::now(),
+            missing: HashSet::new()
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        !self.interval.is_zero() && self.last_run.elapsed() >= self.interval
+    }
+
+    // None when disabled
+    pub fn time_until_due(&self) -> Option<Duration> {
+        if self.interval.is_zero() {
+            None
+        } else {
+            Some(self.interval.saturating_sub(self.last_run.elapsed()))
+        }
+    }
+
+    // Returns the orders to cancel and the orders to post to bring the exchange back to the local grid,
+    // and the missing orders to look up before reposting them
+    pub fn reconcile(&mut self, local_orders: Vec<&Order>, open_orders: &[OrderResponse], price_decimal_count: usize, quantity_decimal_count: usize) -> (Vec<Order>, Vec<Order>, Vec<Order>) {
+        self.last_run = Instant::now();
+        let local_orders: HashMap<u32, &Order> = local_orders.into_iter().filter(|order| order.is_real()).map(|order| (order.get_id(), order)).collect();
+        let mut orders_to_cancel: Vec<Order> = vec![];
+        let mut orders_to_post: Vec<Order> = vec![];
+        let mut orders_to_check: Vec<Order> = vec![];
+        let mut seen: HashSet<u32> = HashSet::new();
+        for open_order in open_orders.iter() {
+            let remote_order = match Order::from_response(open_order) {
+                Some(remote_order) => remote_order,
+                None => {
+                    println!("\x1b[93mIgnoring open order {} not placed by the bot\x1b[0m", open_order.client_order_id);
+                    continue
+                }
+            };
+            seen.insert(remote_order.get_id());
+            match local_orders.get(&remote_order.get_id()) {
+                None => {
+                    println!("\x1b[93mOrphaned order {0} {1:.2$} at {3:.4$}, canceling it\x1b[0m", remote_order.get_id(), remote_order.get_quantity(), quantity_decimal_count, remote_order.get_price(), price_decimal_count);
+                    orders_to_cancel.push(remote_order);
+                },
+                // Its quantity left no longer matches the grid until the fill event is handled
+                Some(_) if open_order.status == OrderStatus::PartiallyFilled => {},
+                Some(local_order) => {
+                    let same_price = format!("{:.1$}", local_order.get_price(), price_decimal_count) == format!("{:.1$}", remote_order.get_price(), price_decimal_count);
+                    let same_quantity = format!("{:.1$}", local_order.get_quantity(), quantity_decimal_count) == format!("{:.1$}", remote_order.get_quantity(), quantity_decimal_count);
+                    let same_side = local_order.is_open() == remote_order.is_open() && local_order.is_long() == remote_order.is_long();
+                    if !same_price || !same_quantity || !same_side {
+                        println!("\x1b[93mMismatched order {0}: {1:.2$} at {3:.4$} on the exchange, {5:.2$} at {6:.4$} locally, reposting it\x1b[0m", remote_order.get_id(), remote_order.get_quantity(), quantity_decimal_count, remote_order.get_price(), price_decimal_count, local_order.get_quantity(), local_order.get_price());
+                        orders_to_cancel.push(remote_order);
+                        orders_to_post.push((*local_order).clone());
+                    }
+                }
+            }
+        }
+        let mut missing: HashSet<u32> = HashSet::new();
+        for (order_id, local_order) in local_orders.iter() {
+            if seen.contains(order_id) {
+                continue
+            }
+            if self.missing.contains(order_id) {
+                println!("\x1b[93mMissing order {0} {1:.2$} at {3:.4$}, looking it up\x1b[0m", order_id, local_order.get_quantity(), quantity_decimal_count, local_order.get_price(), price_decimal_count);
+                orders_to_check.push((*local_order).clone());
+            } else {
+                missing.insert(*order_id);
+            }
+        }
+        self.missing = missing;
+        (orders_to_cancel, orders_to_post, orders_to_check)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(order: &Order, status: OrderStatus) -> OrderResponse {
+        OrderResponse {
+            symbol: String::from("BTCUSDT"),
+            status,
+            client_order_id: order.get_id().to_string(),
+            price: order.get_price(),
+            orig_qty: order.get_quantity(),
+            executed_qty: 0.0,
+            avg_price: 0.0,
+            update_time: 0,
+            side: order.get_side(),
+            position_side: order.get_position_side()
+        }
+    }
+
+    fn ids(orders: &[Order]) -> Vec<u32> {
+        orders.iter().map(|order| order.get_id()).collect()
+    }
+
+    #[test]
+    fn matching_grid_changes_nothing() {
+        let mut reconciler = Reconciler::new(60);
+        let local = Order::new(100.0, 0.002, true, true);
+        let (cancel, post, check) = reconciler.reconcile(vec![&local], &[remote(&local, OrderStatus::New)], 1, 3);
+        assert!(cancel.is_empty() && post.is_empty() && check.is_empty());
+    }
+
+    #[test]
+    fn orphan_is_cancelled_and_foreign_orders_are_left_alone() {
+        let mut reconciler = Reconciler::new(60);
+        let orphan = Order::new(100.0, 0.002, true, true);
+        let mut foreign = remote(&orphan, OrderStatus::New);
+        foreign.client_order_id = String::from("web_manual");
+        let (cancel, post, check) = reconciler.reconcile(vec![], &[remote(&orphan, OrderStatus::New), foreign], 1, 3);
+        assert_eq!(ids(&cancel), vec![orphan.get_id()]);
+        assert!(post.is_empty() && check.is_empty());
+    }
+
+    #[test]
+    fn mismatch_is_cancelled_and_reposted() {
+        let mut reconciler = Reconciler::new(60);
+        let local = Order::new(100.0, 0.002, true, true);
+        let moved = Order::with_id(99.0, 0.002, true, true, local.get_id());
+        let (cancel, post, _) = reconciler.reconcile(vec![&local], &[remote(&moved, OrderStatus::New)], 1, 3);
+        assert_eq!(ids(&cancel), vec![local.get_id()]);
+        assert_eq!(ids(&post), vec![local.get_id()]);
+        assert_eq!(post[0].get_price(), 100.0);
+    }
+
+    #[test]
+    fn partially_filled_order_is_not_a_mismatch() {
+        let mut reconciler = Reconciler::new(60);
+        let local = Order::new(100.0, 0.002, true, true);
+        let mut partial = remote(&local, OrderStatus::PartiallyFilled);
+        partial.executed_qty = 0.001;
+        let (cancel, post, check) = reconciler.reconcile(vec![&local], &[partial], 1, 3);
+        assert!(cancel.is_empty() && post.is_empty() && check.is_empty());
+    }
+
+    #[test]
+    fn missing_order_is_looked_up_on_the_second_run() {
+        let mut reconciler = Reconciler::new(60);
+        let local = Order::new(100.0, 0.002, false, false);
+        let placeholder = Order::new(90.0, 0.0, true, true);
+        let (_, post, check) = reconciler.reconcile(vec![&local, &placeholder], &[], 1, 3);
+        assert!(post.is_empty() && check.is_empty());
+        let (_, post, check) = reconciler.reconcile(vec![&local, &placeholder], &[], 1, 3);
+        assert!(post.is_empty());
+        assert_eq!(ids(&check), vec![local.get_id()]);
+    }
+
+    #[test]
+    fn order_seen_again_is_no_longer_missing() {
+        let mut reconciler = Reconciler::new(60);
+        let local = Order::new(100.0, 0.002, true, false);
+        reconciler.reconcile(vec![&local], &[], 1, 3);
+        reconciler.reconcile(vec![&local], &[remote(&local, OrderStatus::New)], 1, 3);
+        let (_, _, check) = reconciler.reconcile(vec![&local], &[], 1, 3);
+        assert!(check.is_empty());
+    }
+
+    #[test]
+    fn zero_interval_disables_it() {
+        let reconciler = Reconciler::new(0);
+        assert!(!reconciler.is_due());
+        assert!(reconciler.time_until_due().is_none());
+    }
+}
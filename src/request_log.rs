@@ -0,0 +1,111 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use crate::models::{PositionSide, Side};
+use crate::order_manager::Order;
+
+pub const DEFAULT_REQUEST_LOG_PATH: &str = "ressources/dry_run_requests.jsonl";
+
+// One signed request the dry run did not send. Client order ids are random, so orders are named
+// by their rank of first appearance instead: two runs on the same market data produce the same
+// lines once timestamp, signature and payload are dropped.
+#[derive(Serialize)]
+struct RequestRecord<'a> {
+    sequence: u64,
+    // Every place or cancel call of the trader gets its own number, shared by its batches
+    call: u64,
+    batch: usize,
+    method: &'a str,
+    endpoint: &'a str,
+    orders: Vec<OrderRecord>,
+    payload: &'a str,
+    timestamp: u128,
+    signature: &'a str
+}
+
+#[derive(Serialize)]
+struct OrderRecord {
+    order: u64,
+    side: Side,
+    position_side: PositionSide,
+    // Only for new orders, a cancel names the order alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quantity: Option<String>
+}
+
+// A request of a call, the payload is the signed query string without its timestamp
+pub struct LoggedRequest<'a> {
+    pub endpoint: &'a str,
+    pub orders: &'a [Order],
+    pub payload: &'a str,
+    pub timestamp: u128,
+    pub signature: &'a str
+}
+
+struct RequestLogState {
+    file: File,
+    sequence: u64,
+    call: u64,
+    aliases: HashMap<u32, u64>
+}
+
+// JSONL file of every order request the dry run would have sent, truncated on start
+pub struct RequestLog {
+    path: String,
+    state: Mutex<RequestLogState>
+}
+
+impl RequestLog {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| format!("Cannot create request log {}: {}", path, error))?;
+        Ok(RequestLog {
+            path: String::from(path),
+            state: Mutex::new(RequestLogState { file, sequence: 0, call: 0, aliases: HashMap::new() })
+        })
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    // The requests of one call are written together so concurrent grids never interleave them.
+    // The decimal counts are the ones of new orders, None for cancels.
+    pub fn record(&self, method: &str, requests: &[LoggedRequest], decimal_counts: Option<(i64, i64)>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.call += 1;
+        let mut lines = String::new();
+        for (batch, request) in requests.iter().enumerate() {
+            state.sequence += 1;
+            let orders = request.orders.iter().map(|order| {
+                let next_alias = state.aliases.len() as u64 + 1;
+                OrderRecord {
+                    order: *state.aliases.entry(order.get_id()).or_insert(next_alias),
+                    side: order.get_side(),
+                    position_side: order.get_position_side(),
+                    price: decimal_counts.map(|(price_decimal_count, _)| format!("{:.1$}", order.get_price(), price_decimal_count as usize)),
+                    quantity: decimal_counts.map(|(_, quantity_decimal_count)| format!("{:.1$}", order.get_quantity(), quantity_decimal_count as usize))
+                }
+            }).collect();
+            let record = RequestRecord {
+                sequence: state.sequence,
+                call: state.call,
+                batch,
+                method,
+                endpoint: request.endpoint,
+                orders,
+                payload: request.payload,
+                timestamp: request.timestamp,
+                signature: request.signature
+            };
+            lines.push_str(&format!("{}\n", serde_json::to_string(&record).unwrap()));
+        }
+        if let Err(error) = state.file.write_all(lines.as_bytes()) {
+            println!("\x1b[91mFailed to write request log {}: {}\x1b[0m", self.path, error);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use tokio::time::Duration;
+use crate::error::{ErrorClass, GateError};
+
+// Exponential backoff: initial_delay, 2 * initial_delay, ... capped at max_delay (milliseconds)
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_delay: u64,
+    max_delay: u64
+}
+
+// One policy as written in the config, the missing keys take the defaults of its error class
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicyConfig {
+    max_retries: Option<u32>,
+    initial_delay: Option<u64>,
+    max_delay: Option<u64>
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    network: RetryPolicyConfig,
+    rate_limit: RetryPolicyConfig,
+    timestamp: RetryPolicyConfig
+}
+
+impl RetryPolicy {
+    fn from_config(config: &RetryPolicyConfig, max_retries: u32, initial_delay: u64, max_delay: u64) -> Self {
+        RetryPolicy {
+            max_retries: config.max_retries.unwrap_or(max_retries),
+            initial_delay: config.initial_delay.unwrap_or(initial_delay),
+            max_delay: config.max_delay.unwrap_or(max_delay)
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None
+        }
+        let delay = self.initial_delay.saturating_mul(2u64.saturating_pow(attempt)).min(self.max_delay);
+        Some(Duration::from_millis(delay))
+    }
+}
+
+// The optional "retry" section of the config
+#[derive(Clone, Copy, Deserialize)]
+#[serde(from = "RetryConfig")]
+pub struct RetrySettings {
+    network: RetryPolicy,
+    rate_limit: RetryPolicy,
+    timestamp: RetryPolicy
+}
+
+impl From<RetryConfig> for RetrySettings {
+    fn from(config: RetryConfig) -> Self {
+        RetrySettings {
+            network: RetryPolicy::from_config(&config.network, 5, 500, 10000),
+            rate_limit: RetryPolicy::from_config(&config.rate_limit, 5, 5000, 60000),
+            timestamp: RetryPolicy::from_config(&config.timestamp, 3, 100, 1000)
+        }
+    }
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings::from(RetryConfig::default())
+    }
+}
+
+impl RetrySettings {
+    // None when the error must not be retried or the attempts are exhausted
+    pub fn delay(&self, error: &GateError, attempt: u32) -> Option<Duration> {
+        match error.class() {
+            ErrorClass::Network => self.network.delay(attempt),
+            ErrorClass::RateLimit => self.rate_limit.delay(attempt),
+            ErrorClass::Timestamp => self.timestamp.delay(attempt),
+            ErrorClass::Fatal => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiCode;
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::from_config(&RetryPolicyConfig::default(), 5, 500, 3000);
+        let delays: Vec<Option<Duration>> = (0..6).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(delays, vec![
+            Some(Duration::from_millis(500)),
+            Some(Duration::from_millis(1000)),
+            Some(Duration::from_millis(2000)),
+            Some(Duration::from_millis(3000)),
+            Some(Duration::from_millis(3000)),
+            None
+        ]);
+    }
+
+    #[test]
+    fn config_overrides_the_defaults() {
+        let config = RetryPolicyConfig { max_retries: Some(1), initial_delay: Some(10), max_delay: None };
+        let policy = RetryPolicy::from_config(&config, 5, 500, 3000);
+        assert_eq!(policy.delay(0), Some(Duration::from_millis(10)));
+        assert_eq!(policy.delay(1), None);
+    }
+
+    #[test]
+    fn settings_pick_the_policy_of_the_error() {
+        let settings = RetrySettings::default();
+        assert_eq!(settings.delay(&GateError::Network(String::from("reset")), 0), Some(Duration::from_millis(500)));
+        assert_eq!(settings.delay(&GateError::Api(ApiCode::RateLimit, String::new()), 0), Some(Duration::from_millis(5000)));
+        assert_eq!(settings.delay(&GateError::Api(ApiCode::Timestamp, String::new()), 3), None);
+        assert_eq!(settings.delay(&GateError::Api(ApiCode::GtxRejected, String::new()), 0), None);
+    }
+}
@@ -0,0 +1,191 @@
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// What the risk manager does on a breach, in the order it escalates by default
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskAction {
+    // Report the breach on the console and discord
+    Notify,
+    // Stop opening, the close ladders keep working
+    CloseOnly,
+    // Cancel every order of the grid and post nothing until a restart
+    CancelAll,
+    // Cancel everything and close the positions at market
+    Flatten
+}
+
+impl fmt::Display for RiskAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskAction::Notify => write!(f, "notify"),
+            RiskAction::CloseOnly => write!(f, "close_only"),
+            RiskAction::CancelAll => write!(f, "cancel_all"),
+            RiskAction::Flatten => write!(f, "flatten")
+        }
+    }
+}
+
+// The optional "risk" key of a grid, every limit is in quote currency and off when missing
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RiskSettings {
+    // Equity below its highest point of the session
+    pub max_drawdown: Option<f64>,
+    // Equity below what it was when the local day started
+    pub max_daily_loss: Option<f64>,
+    // Longs minus shorts, either way, at the market price
+    pub max_net_notional: Option<f64>,
+    // Actions taken one after the other while a limit stays breached
+    pub escalation: Vec<RiskAction>,
+    // Seconds between two steps of the escalation
+    pub escalation_interval: u64,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>
+}
+
+impl Default for RiskSettings {
+    fn default() -> Self {
+        RiskSettings {
+            max_drawdown: None,
+            max_daily_loss: None,
+            max_net_notional: None,
+            escalation: vec![RiskAction::Notify, RiskAction::CloseOnly, RiskAction::CancelAll, RiskAction::Flatten],
+            escalation_interval: 60,
+            unknown: BTreeMap::new()
+        }
+    }
+}
+
+impl RiskSettings {
+    fn is_enabled(&self) -> bool {
+        self.max_drawdown.is_some() || self.max_daily_loss.is_some() || self.max_net_notional.is_some()
+    }
+}
+
+// Tracks the equity of a grid against its limits. The escalation never goes back down, the
+// actions taken stay in force until the bot is restarted.
+pub struct RiskMonitor {
+    settings: RiskSettings,
+    peak_equity: f64,
+    day: Option<NaiveDate>,
+    day_start_equity: f64,
+    steps_taken: usize,
+    last_step: Option<Instant>
+}
+
+impl RiskMonitor {
+    pub fn new(settings: RiskSettings) -> Self {
+        RiskMonitor {
+            settings,
+            peak_equity: f64::MIN,
+            day: None,
+            day_start_equity: 0.0,
+            steps_taken: 0,
+            last_step: None
+        }
+    }
+
+    // The next action of the escalation and the limits breached, None while within the limits,
+    // before the interval since the last step is over, or once every step was taken
+    pub fn check(&mut self, equity: f64, net_notional: f64) -> Option<(RiskAction, String)> {
+        if !self.settings.is_enabled() {
+            return None
+        }
+        let today = Local::now().date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.day_start_equity = equity;
+        }
+        self.peak_equity = self.peak_equity.max(equity);
+        let mut breaches: Vec<String> = vec![];
+        if let Some(max_drawdown) = self.settings.max_drawdown {
+            let drawdown = self.peak_equity - equity;
+            if drawdown > max_drawdown {
+                breaches.push(format!("drawdown {:.2} above {}", drawdown, max_drawdown));
+            }
+        }
+        if let Some(max_daily_loss) = self.settings.max_daily_loss {
+            let daily_loss = self.day_start_equity - equity;
+            if daily_loss > max_daily_loss {
+                breaches.push(format!("daily loss {:.2} above {}", daily_loss, max_daily_loss));
+            }
+        }
+        if let Some(max_net_notional) = self.settings.max_net_notional {
+            if net_notional.abs() > max_net_notional {
+                breaches.push(format!("net notional {:.2} above {}", net_notional, max_net_notional));
+            }
+        }
+        if breaches.is_empty() || self.steps_taken >= self.settings.escalation.len() {
+            return None
+        }
+        if self.last_step.is_some_and(|last_step| last_step.elapsed() < Duration::from_secs(self.settings.escalation_interval)) {
+            return None
+        }
+        let action = self.settings.escalation[self.steps_taken];
+        self.steps_taken += 1;
+        self.last_step = Some(Instant::now());
+        Some((action, breaches.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(escalation_interval: u64) -> RiskSettings {
+        RiskSettings {
+            max_drawdown: Some(100.0),
+            escalation: vec![RiskAction::Notify, RiskAction::CloseOnly],
+            escalation_interval,
+            ..RiskSettings::default()
+        }
+    }
+
+    #[test]
+    fn disabled_without_limits() {
+        let mut monitor = RiskMonitor::new(RiskSettings::default());
+        assert!(monitor.check(1000.0, 0.0).is_none());
+        assert!(monitor.check(0.0, 1e9).is_none());
+    }
+
+    #[test]
+    fn escalates_one_step_per_breach_until_exhausted() {
+        let mut monitor = RiskMonitor::new(settings(0));
+        assert!(monitor.check(1000.0, 0.0).is_none());
+        let (action, reason) = monitor.check(850.0, 0.0).unwrap();
+        assert!(action == RiskAction::Notify);
+        assert_eq!(reason, "drawdown 150.00 above 100");
+        assert!(monitor.check(850.0, 0.0).is_some_and(|(action, _)| action == RiskAction::CloseOnly));
+        assert!(monitor.check(850.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn waits_the_interval_between_steps() {
+        let mut monitor = RiskMonitor::new(settings(60));
+        monitor.check(1000.0, 0.0);
+        assert!(monitor.check(850.0, 0.0).is_some());
+        assert!(monitor.check(850.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn drawdown_is_measured_from_the_peak() {
+        let mut monitor = RiskMonitor::new(settings(0));
+        monitor.check(1000.0, 0.0);
+        monitor.check(1200.0, 0.0);
+        assert!(monitor.check(1150.0, 0.0).is_none());
+        assert!(monitor.check(1050.0, 0.0).is_some());
+    }
+
+    #[test]
+    fn daily_loss_and_net_notional() {
+        let mut monitor = RiskMonitor::new(RiskSettings { max_daily_loss: Some(100.0), max_net_notional: Some(500.0), escalation_interval: 0, ..RiskSettings::default() });
+        assert!(monitor.check(1000.0, 0.0).is_none());
+        let (_, reason) = monitor.check(890.0, -600.0).unwrap();
+        assert_eq!(reason, "daily loss 110.00 above 100, net notional -600.00 above 500");
+    }
+}
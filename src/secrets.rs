@@ -0,0 +1,269 @@
+use aes::Aes256;
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use crate::config::Config;
+
+pub const DEFAULT_KEYSTORE_PATH: &str = "ressources/keystore.json";
+const PASSPHRASE_ENV: &str = "GRID_KEYSTORE_PASSPHRASE";
+const NEW_PASSPHRASE_ENV: &str = "GRID_KEYSTORE_NEW_PASSPHRASE";
+const KEYSTORE_VERSION: u64 = 2;
+// Used to seal, and the least a keystore is accepted with
+const KDF_ITERATIONS: u32 = 200_000;
+// Above this a keystore would take minutes to unlock
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+// The optional "secrets" section of the config, at most one of the two is set
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsSettings {
+    // Plain JSON file only readable by its owner
+    pub file: Option<String>,
+    // Passphrase-encrypted keystore created with `keystore create`
+    pub keystore: Option<String>
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Secrets {
+    pub api_key: String,
+    pub api_secret: String,
+    #[serde(default)]
+    pub discord_token: String,
+    #[serde(default)]
+    pub discord_channel: String
+}
+
+// PBKDF2-HMAC-SHA256 derives the AES-256-CBC key and the HMAC-SHA256 key from the passphrase,
+// the MAC covers the iv and the ciphertext so a wrong passphrase or a modified file is refused
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u64,
+    iterations: u32,
+    salt: String,
+    iv: String,
+    ciphertext: String,
+    mac: String
+}
+
+impl Secrets {
+    // The environment first, then the secrets file or the keystore of the config, then the legacy
+    // encrypted_* keys. The mock server and the backtest never sign real requests.
+    pub fn load(config: &Config) -> Result<Self, String> {
+        if let (Ok(api_key), Ok(api_secret)) = (std::env::var("BINANCE_API_KEY"), std::env::var("BINANCE_API_SECRET")) {
+            return Ok(Secrets {
+                api_key,
+                api_secret,
+                discord_token: std::env::var("DISCORD_TOKEN").unwrap_or_default(),
+                discord_channel: std::env::var("DISCORD_CHANNEL").unwrap_or_default()
+            })
+        }
+        if let Some(path) = &config.secrets.file {
+            return Secrets::read_file(path)
+        }
+        if let Some(path) = &config.secrets.keystore {
+            let passphrase = match std::env::var(PASSPHRASE_ENV) {
+                Ok(passphrase) => passphrase,
+                Err(_) => prompt(&format!("Passphrase of {}: ", path), true)
+            };
+            return unlock(path, &passphrase)
+        }
+        if let Some(secrets) = Secrets::from_legacy(config) {
+            println!("\x1b[93mWARNING: The encrypted_* keys of the config are only obfuscated, move them to a keystore with `keystore create`\x1b[0m");
+            return Ok(secrets)
+        }
+        if config.mock_server.is_some() {
+            return Ok(Secrets::default())
+        }
+        Err(String::from("No API credentials: set BINANCE_API_KEY and BINANCE_API_SECRET, secrets.file or secrets.keystore"))
+    }
+
+    fn read_file(path: &str) -> Result<Self, String> {
+        let metadata = std::fs::metadata(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(format!("{} is accessible by other users (mode {:o}), run chmod 600 {}", path, mode, path))
+        }
+        let secrets_str = std::fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+        serde_json::from_str(&secrets_str).map_err(|error| format!("Invalid {}: {}", path, error))
+    }
+
+    fn from_legacy(config: &Config) -> Option<Self> {
+        let encrypted_key: String = new_magic_crypt!("encrypted_key", 256).decrypt_base64_to_string(config.encrypted_key.as_ref()?).ok()?;
+        let encrypted_secret: String = new_magic_crypt!("encrypted_secret", 256).decrypt_base64_to_string(config.encrypted_secret.as_ref()?).ok()?;
+        let api_key: String = new_magic_crypt!(&encrypted_secret, 256).decrypt_base64_to_string(encrypted_key).ok()?;
+        let api_secret: String = new_magic_crypt!(&api_key, 256).decrypt_base64_to_string(encrypted_secret).ok()?;
+
+        let (discord_token, discord_channel) = match (&config.encrypted_token, &config.encrypted_channel) {
+            (Some(token), Some(channel)) => {
+                let encrypted_token: String = new_magic_crypt!("encrypted_token", 256).decrypt_base64_to_string(token).ok()?;
+                let encrypted_channel: String = new_magic_crypt!("encrypted_channel", 256).decrypt_base64_to_string(channel).ok()?;
+                let discord_token: String = new_magic_crypt!(&encrypted_channel, 256).decrypt_base64_to_string(encrypted_token).ok()?;
+                let discord_channel: String = new_magic_crypt!(&discord_token, 256).decrypt_base64_to_string(encrypted_channel).ok()?;
+                (discord_token, discord_channel)
+            },
+            _ => (String::new(), String::new())
+        };
+        Some(Secrets { api_key, api_secret, discord_token, discord_channel })
+    }
+}
+
+// `keystore create [--keystore <path>]` and `keystore rotate [--keystore <path>]`
+pub fn run_keystore_command(args: &[String]) -> Result<(), String> {
+    let path = match args.iter().position(|arg| arg == "--keystore") {
+        Some(index) => args.get(index + 1).ok_or("--keystore expects the path of the keystore")?.clone(),
+        None => String::from(DEFAULT_KEYSTORE_PATH)
+    };
+    let command = args.iter().skip_while(|arg| *arg != "keystore").nth(1);
+    match command.map(|command| command.as_str()) {
+        Some("create") => {
+            if std::path::Path::new(&path).exists() {
+                return Err(format!("{} already exists, use `keystore rotate` to change it", path))
+            }
+            let secrets = Secrets {
+                api_key: env_or_prompt("BINANCE_API_KEY", "API key: ", false, None)?,
+                api_secret: env_or_prompt("BINANCE_API_SECRET", "API secret: ", true, None)?,
+                discord_token: env_or_prompt("DISCORD_TOKEN", "Discord token (empty disables Discord): ", true, Some(""))?,
+                discord_channel: env_or_prompt("DISCORD_CHANNEL", "Discord channel (empty disables Discord): ", false, Some(""))?
+            };
+            let passphrase = new_passphrase(PASSPHRASE_ENV)?;
+            seal(&path, &secrets, &passphrase)?;
+            println!("\x1b[92mKeystore written to {}, set \"secrets\": {{ \"keystore\": \"{}\" }} in the config\x1b[0m", path, path);
+            Ok(())
+        },
+        Some("rotate") => {
+            let passphrase = match std::env::var(PASSPHRASE_ENV) {
+                Ok(passphrase) => passphrase,
+                Err(_) => prompt(&format!("Current passphrase of {}: ", path), true)
+            };
+            let current = unlock(&path, &passphrase)?;
+            let secrets = Secrets {
+                api_key: env_or_prompt("BINANCE_API_KEY", "New API key (empty keeps the current one): ", false, Some(&current.api_key))?,
+                api_secret: env_or_prompt("BINANCE_API_SECRET", "New API secret (empty keeps the current one): ", true, Some(&current.api_secret))?,
+                discord_token: env_or_prompt("DISCORD_TOKEN", "New Discord token (empty keeps the current one): ", true, Some(&current.discord_token))?,
+                discord_channel: env_or_prompt("DISCORD_CHANNEL", "New Discord channel (empty keeps the current one): ", false, Some(&current.discord_channel))?
+            };
+            let new_passphrase = match std::env::var(NEW_PASSPHRASE_ENV) {
+                Ok(new_passphrase) => new_passphrase,
+                Err(_) => {
+                    let new_passphrase = prompt("New passphrase (empty keeps the current one): ", true);
+                    if new_passphrase.is_empty() {
+                        passphrase
+                    } else if prompt("Repeat the new passphrase: ", true) == new_passphrase {
+                        new_passphrase
+                    } else {
+                        return Err(String::from("The passphrases do not match"))
+                    }
+                }
+            };
+            seal(&path, &secrets, &new_passphrase)?;
+            println!("\x1b[92mKeystore {} rotated\x1b[0m", path);
+            Ok(())
+        },
+        _ => Err(String::from("Usage: keystore <create|rotate> [--keystore <path>]"))
+    }
+}
+
+fn env_or_prompt(env: &str, label: &str, hidden: bool, default: Option<&str>) -> Result<String, String> {
+    if let Ok(value) = std::env::var(env) {
+        return Ok(value)
+    }
+    match (prompt(label, hidden), default) {
+        (value, Some(default)) if value.is_empty() => Ok(String::from(default)),
+        (value, None) if value.is_empty() => Err(format!("{} is required", label.trim_end_matches(": "))),
+        (value, _) => Ok(value)
+    }
+}
+
+fn new_passphrase(env: &str) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(env) {
+        return Ok(passphrase)
+    }
+    let passphrase = prompt("New passphrase: ", true);
+    if passphrase.is_empty() {
+        return Err(String::from("The passphrase must not be empty"))
+    }
+    if prompt("Repeat the new passphrase: ", true) != passphrase {
+        return Err(String::from("The passphrases do not match"))
+    }
+    Ok(passphrase)
+}
+
+// Reads one line from the terminal, without echo for secrets
+fn prompt(label: &str, hidden: bool) -> String {
+    print!("{}", label);
+    let _ = std::io::stdout().flush();
+    let stty = |arg: &str| std::process::Command::new("stty").arg(arg).stdin(std::process::Stdio::inherit()).stderr(std::process::Stdio::null()).status();
+    if hidden {
+        let _ = stty("-echo");
+    }
+    let mut line = String::new();
+    let _ = std::io::stdin().read_line(&mut line);
+    if hidden {
+        let _ = stty("echo");
+        println!();
+    }
+    String::from(line.trim_end_matches(['\r', '\n']))
+}
+
+fn derive_keys(passphrase: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; 32]) {
+    let mut keys = [0u8; 64];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut keys);
+    let (encryption_key, mac_key) = keys.split_at(32);
+    (encryption_key.try_into().unwrap(), mac_key.try_into().unwrap())
+}
+
+fn keystore_mac(mac_key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(mac_key).unwrap();
+    mac.update(iv);
+    mac.update(ciphertext);
+    mac
+}
+
+fn seal(path: &str, secrets: &Secrets, passphrase: &str) -> Result<(), String> {
+    let mut salt = [0u8; 16];
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill(&mut salt);
+    rand::thread_rng().fill(&mut iv);
+    let (encryption_key, mac_key) = derive_keys(passphrase, &salt, KDF_ITERATIONS);
+    let ciphertext = cbc::Encryptor::<Aes256>::new(&encryption_key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(&serde_json::to_vec(secrets).unwrap());
+    let keystore = Keystore {
+        version: KEYSTORE_VERSION,
+        iterations: KDF_ITERATIONS,
+        salt: hex::encode(salt),
+        iv: hex::encode(iv),
+        mac: hex::encode(keystore_mac(&mac_key, &iv, &ciphertext).finalize().into_bytes()),
+        ciphertext: hex::encode(ciphertext)
+    };
+    // Written next to the keystore then renamed, so a failed rotation keeps the old one
+    let tmp_path = format!("{}.tmp", path);
+    let result = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp_path)
+        .and_then(|mut file| file.write_all(serde_json::to_string_pretty(&keystore).unwrap().as_bytes()))
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    result.map_err(|error| format!("Cannot write {}: {}", path, error))
+}
+
+fn unlock(path: &str, passphrase: &str) -> Result<Secrets, String> {
+    let keystore_str = std::fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    let keystore: Keystore = serde_json::from_str(&keystore_str).map_err(|error| format!("Invalid {}: {}", path, error))?;
+    if keystore.version != KEYSTORE_VERSION {
+        return Err(format!("{} has version {}, expected {}, create it again with `keystore create`", path, keystore.version, KEYSTORE_VERSION))
+    }
+    if !(KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&keystore.iterations) {
+        return Err(format!("{} has {} PBKDF2 iterations, expected between {} and {}", path, keystore.iterations, KDF_ITERATIONS, MAX_KDF_ITERATIONS))
+    }
+    let decode = |value: &str| hex::decode(value).map_err(|error| format!("Invalid {}: {}", path, error));
+    let (salt, iv, ciphertext, mac) = (decode(&keystore.salt)?, decode(&keystore.iv)?, decode(&keystore.ciphertext)?, decode(&keystore.mac)?);
+    let (encryption_key, mac_key) = derive_keys(passphrase, &salt, keystore.iterations);
+    if keystore_mac(&mac_key, &iv, &ciphertext).verify_slice(&mac).is_err() {
+        return Err(format!("Wrong passphrase for {}, or the file was modified", path))
+    }
+    let iv: [u8; 16] = iv.try_into().map_err(|_| format!("Invalid {}: the iv is not 16 bytes", path))?;
+    let plaintext = cbc::Decryptor::<Aes256>::new(&encryption_key.into(), &iv.into()).decrypt_padded_vec_mut::<Pkcs7>(&ciphertext).map_err(|error| format!("Cannot decrypt {}: {}", path, error))?;
+    serde_json::from_slice(&plaintext).map_err(|error| format!("Invalid {}: {}", path, error))
+}
@@ -0,0 +1,611 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{ApiCode, GateError};
+use crate::exchange::Exchange;
+use crate::models::{AccountAsset, AccountInfo, AccountPosition, AccountUpdate, BalanceUpdate, ExchangeInfo, Income, LeverageBracket, OrderResponse, OrderStatus, OrderUpdate, PositionSide, PositionUpdate, PremiumIndex, SymbolFilter, SymbolInfo, UserDataEvent};
+use crate::order_manager::Order;
+
+// The optional "backtest" section of the config, or the engine keys of "mock_server"
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct SimulatorConfig {
+    tick_size: f64,
+    step_size: f64,
+    maker_fee: f64,
+    taker_fee: f64,
+    initial_balance: f64,
+    margin_asset: Option<String>,
+    // Rate paid by the longs to the shorts at every settlement, negative the other way around
+    funding_rate: f64,
+    // Seconds between two settlements, aligned on the unix epoch like the 8h of Binance
+    funding_interval: u64,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            tick_size: 0.1,
+            step_size: 0.001,
+            maker_fee: 0.0002,
+            taker_fee: 0.0004,
+            initial_balance: 1000.0,
+            margin_asset: None,
+            funding_rate: 0.0001,
+            funding_interval: 28800,
+            unknown: BTreeMap::new()
+        }
+    }
+}
+
+pub struct SimulatorSettings {
+    tick_size: f64,
+    step_size: f64,
+    maker_fee: f64,
+    taker_fee: f64,
+    initial_balance: f64,
+    margin_asset: String,
+    funding_rate: f64,
+    funding_interval: u64
+}
+
+impl SimulatorSettings {
+    // Paper trading takes the filters of the real symbol instead of the configured ones
+    pub fn with_filters(mut self, tick_size: f64, step_size: f64) -> Self {
+        self.tick_size = tick_size;
+        self.step_size = step_size;
+        self
+    }
+
+    // The margin asset defaults to the quote asset of the pair
+    pub fn new(pair: &str, config: &SimulatorConfig) -> Self {
+        SimulatorSettings {
+            tick_size: config.tick_size,
+            step_size: config.step_size,
+            maker_fee: config.maker_fee,
+            taker_fee: config.taker_fee,
+            initial_balance: config.initial_balance,
+            margin_asset: config.margin_asset.clone().unwrap_or_else(|| String::from(if pair.ends_with("BUSD") { "BUSD" } else { "USDT" })),
+            funding_rate: config.funding_rate,
+            funding_interval: config.funding_interval.max(1)
+        }
+    }
+}
+
+struct SimulatorState {
+    top_ask: f64,
+    top_bid: f64,
+    top_ask_quantity: f64,
+    top_bid_quantity: f64,
+    resting_orders: Vec<Order>,
+    // Quantity resting before each order at its price, only with the queue model
+    queue_ahead: HashMap<u32, f64>,
+    long_quantity: f64,
+    long_entry_price: f64,
+    short_quantity: f64,
+    short_entry_price: f64,
+    balance: f64,
+    realized_profit: f64,
+    commission: f64,
+    volume: f64,
+    max_long: f64,
+    max_short: f64,
+    fills: u64,
+    expired: u64,
+    funding: f64,
+    // Milliseconds, the settlements are only counted from the start of the engine
+    next_funding_time: u64,
+    incomes: Vec<Income>,
+    // Last state of every order that left the book, for the order queries
+    finished_orders: HashMap<u32, OrderResponse>,
+    events: Vec<UserDataEvent>
+}
+
+// First tier of BTCUSDT
+const SIMULATED_MAINT_MARGIN_RATIO: f64 = 0.004;
+
+// Simulated venue shared by the backtest and the mock server: GTX orders rest until the book
+// trades through them, every fill and expiry is queued as the same ORDER_TRADE_UPDATE /
+// ACCOUNT_UPDATE payload the websocket would push.
+pub struct SimulatedExchange {
+    pair: String,
+    settings: SimulatorSettings,
+    queue_model: bool,
+    state: Mutex<SimulatorState>
+}
+
+impl SimulatedExchange {
+    pub fn new(pair: String, settings: SimulatorSettings) -> Self {
+        let balance = settings.initial_balance;
+        SimulatedExchange {
+            pair,
+            queue_model: false,
+            state: Mutex::new(SimulatorState {
+                top_ask: 0.0,
+                top_bid: 0.0,
+                top_ask_quantity: 0.0,
+                top_bid_quantity: 0.0,
+                resting_orders: vec![],
+                queue_ahead: HashMap::new(),
+                long_quantity: 0.0,
+                long_entry_price: 0.0,
+                short_quantity: 0.0,
+                short_entry_price: 0.0,
+                balance,
+                realized_profit: 0.0,
+                commission: 0.0,
+                volume: 0.0,
+                max_long: 0.0,
+                max_short: 0.0,
+                fills: 0,
+                expired: 0,
+                funding: 0.0,
+                next_funding_time: next_funding_time(unix_millis(), settings.funding_interval),
+                incomes: vec![],
+                finished_orders: HashMap::new(),
+                events: vec![]
+            }),
+            settings
+        }
+    }
+
+    // Used by paper trading: an order is only filled once the trades at its price have consumed
+    // the quantity queued before it, or once the market trades through its price
+    pub fn with_queue_model(mut self) -> Self {
+        self.queue_model = true;
+        self
+    }
+
+    // Moves the book and fills every resting order the new best bid/ask traded through
+    pub fn apply_tick(&self, top_bid: f64, top_ask: f64) {
+        let state = &mut self.state.lock().unwrap();
+        state.top_bid = top_bid;
+        state.top_ask = top_ask;
+        self.fill_resting(state, |order| {
+            if order.is_buy() {
+                top_bid < order.get_price()
+            } else {
+                top_ask > order.get_price()
+            }
+        });
+    }
+
+    // Same as apply_tick, the quantity at the top also caps the queue of the orders at the best price,
+    // the ones ahead can only leave it
+    pub fn apply_book(&self, top_bid: f64, top_bid_quantity: f64, top_ask: f64, top_ask_quantity: f64) {
+        self.apply_tick(top_bid, top_ask);
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.top_bid_quantity = top_bid_quantity;
+        state.top_ask_quantity = top_ask_quantity;
+        let half_tick = self.settings.tick_size / 2.0;
+        for order in state.resting_orders.iter() {
+            let (top_price, top_quantity) = if order.is_buy() { (top_bid, top_bid_quantity) } else { (top_ask, top_ask_quantity) };
+            if (order.get_price() - top_price).abs() < half_tick {
+                if let Some(ahead) = state.queue_ahead.get_mut(&order.get_id()) {
+                    *ahead = ahead.min(top_quantity);
+                }
+            }
+        }
+    }
+
+    // A trade where the buyer is the maker hit the bids, otherwise it lifted the asks. Orders the
+    // trade price went through are filled, at the trade price the queue ahead shrinks by its quantity.
+    pub fn apply_trade(&self, price: f64, quantity: f64, buyer_is_maker: bool) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let half_tick = self.settings.tick_size / 2.0;
+        let mut reached: Vec<u32> = vec![];
+        for order in state.resting_orders.iter().filter(|order| order.is_buy() == buyer_is_maker) {
+            if (order.get_price() - price).abs() < half_tick {
+                let ahead = state.queue_ahead.entry(order.get_id()).or_insert(0.0);
+                *ahead -= quantity;
+                if *ahead < 0.0 {
+                    reached.push(order.get_id());
+                }
+            } else if (order.is_buy() && price < order.get_price()) || (!order.is_buy() && price > order.get_price()) {
+                reached.push(order.get_id());
+            }
+        }
+        self.fill_resting(state, |order| reached.contains(&order.get_id()));
+    }
+
+    // Pays or collects the funding of both positions at the mark price once the settlement time
+    // has passed, like the exchange the wallet moves and an ACCOUNT_UPDATE is pushed
+    pub fn settle_funding(&self) {
+        let now = unix_millis();
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if now < state.next_funding_time || state.top_bid <= 0.0 {
+            return
+        }
+        let mark_price = (state.top_ask + state.top_bid) / 2.0;
+        let payment = (state.short_quantity - state.long_quantity) * mark_price * self.settings.funding_rate;
+        if payment != 0.0 {
+            state.balance += payment;
+            state.funding += payment;
+            let tran_id = state.incomes.len() as u64 + 1;
+            state.incomes.push(Income {
+                symbol: self.pair.clone(),
+                income_type: String::from("FUNDING_FEE"),
+                income: payment,
+                asset: self.settings.margin_asset.clone(),
+                time: state.next_funding_time,
+                tran_id
+            });
+            let event = self.account_event(state);
+            state.events.push(event);
+        }
+        state.next_funding_time = next_funding_time(now, self.settings.funding_interval);
+    }
+
+    fn fill_resting(&self, state: &mut SimulatorState, is_filled: impl Fn(&Order) -> bool) {
+        let (filled, resting): (Vec<Order>, Vec<Order>) = std::mem::take(&mut state.resting_orders).into_iter().partition(is_filled);
+        state.resting_orders = resting;
+        for order in filled {
+            state.queue_ahead.remove(&order.get_id());
+            self.fill_order(state, &order, self.settings.maker_fee);
+        }
+    }
+
+    // Inside the spread nothing is ahead, at the best price everything displayed is, further away
+    // the queue is unknown until the price becomes the best one
+    fn initial_queue(&self, state: &SimulatorState, order: &Order) -> f64 {
+        let (top_price, top_quantity) = if order.is_buy() { (state.top_bid, state.top_bid_quantity) } else { (state.top_ask, state.top_ask_quantity) };
+        let improves = if order.is_buy() { order.get_price() > top_price } else { order.get_price() < top_price };
+        if (order.get_price() - top_price).abs() < self.settings.tick_size / 2.0 {
+            top_quantity
+        } else if improves {
+            0.0
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    // Removes a resting order, returns None if it was already filled, expired or canceled
+    pub fn cancel_by_id(&self, order_id: u32) -> Option<Order> {
+        let state = &mut self.state.lock().unwrap();
+        let index = state.resting_orders.iter().position(|resting| resting.get_id() == order_id)?;
+        let order = state.resting_orders.remove(index);
+        state.queue_ahead.remove(&order_id);
+        state.finished_orders.insert(order_id, self.order_response(&order, OrderStatus::Canceled));
+        let event = self.order_event(&order, OrderStatus::Canceled, 0.0, 0.0);
+        state.events.push(event);
+        Some(order)
+    }
+
+    // A GTX order at or through the best price of the other side would take liquidity
+    pub fn would_take(&self, order: &Order) -> bool {
+        crosses(&self.state.lock().unwrap(), order)
+    }
+
+    pub fn get_positions(&self) -> (f64, f64) {
+        let state = &self.state.lock().unwrap();
+        (state.long_quantity, state.short_quantity)
+    }
+
+    pub fn take_events(&self) -> Vec<UserDataEvent> {
+        std::mem::take(&mut self.state.lock().unwrap().events)
+    }
+
+    pub fn get_tick_size(&self) -> f64 {
+        self.settings.tick_size
+    }
+
+    pub fn get_step_size(&self) -> f64 {
+        self.settings.step_size
+    }
+
+    pub fn print_report(&self) {
+        let state = &self.state.lock().unwrap();
+        let mark_price = (state.top_ask + state.top_bid) / 2.0;
+        let un_pnl = (mark_price - state.long_entry_price) * state.long_quantity + (state.short_entry_price - mark_price) * state.short_quantity;
+        let mut str_list: Vec<String> = Vec::with_capacity(12);
+        str_list.push(String::from("----------- Backtest ------------"));
+        str_list.push(format!("Fills: {}", state.fills));
+        str_list.push(format!("Expired: {}", state.expired));
+        str_list.push(format!("Volume: {:.2}", state.volume));
+        str_list.push(format!("Commission: {:.4}", -state.commission));
+        str_list.push(format!("Realized PNL: {:.4}", state.realized_profit));
+        str_list.push(format!("Funding: {:.4}", state.funding));
+        str_list.push(format!("Unrealized PNL: {:.4}", un_pnl));
+        str_list.push(format!("Final PNL: {:.4}", state.realized_profit - state.commission + state.funding + un_pnl));
+        str_list.push(format!("Final balance: {:.4}", state.balance));
+        str_list.push(format!("Max long position: {0:.1$}", state.max_long, decimal_count(self.settings.step_size) as usize));
+        str_list.push(format!("Max short position: {0:.1$}", state.max_short, decimal_count(self.settings.step_size) as usize));
+        str_list.push(String::from("---------------------------------"));
+        println!("\x1b[96m{}\x1b[0m", str_list.join("\n"));
+    }
+
+    fn fill_order(&self, state: &mut SimulatorState, order: &Order, fee: f64) {
+        let price = order.get_price();
+        let quantity = order.get_quantity();
+        let commission = price * quantity * fee;
+        let mut pnl = 0.0;
+        match (order.is_open(), order.is_long()) {
+            (true, true) => {
+                state.long_entry_price = (state.long_entry_price * state.long_quantity + price * quantity) / (state.long_quantity + quantity);
+                state.long_quantity += quantity;
+                state.max_long = state.max_long.max(state.long_quantity);
+            },
+            (false, true) => {
+                pnl = (price - state.long_entry_price) * quantity;
+                state.long_quantity = (state.long_quantity - quantity).max(0.0);
+            },
+            (true, false) => {
+                state.short_entry_price = (state.short_entry_price * state.short_quantity + price * quantity) / (state.short_quantity + quantity);
+                state.short_quantity += quantity;
+                state.max_short = state.max_short.max(state.short_quantity);
+            },
+            (false, false) => {
+                pnl = (state.short_entry_price - price) * quantity;
+                state.short_quantity = (state.short_quantity - quantity).max(0.0);
+            }
+        }
+        state.fills += 1;
+        state.volume += price * quantity;
+        state.commission += commission;
+        state.realized_profit += pnl;
+        state.balance += pnl - commission;
+        let mut response = self.order_response(order, OrderStatus::Filled);
+        response.executed_qty = quantity;
+        response.avg_price = price;
+        state.finished_orders.insert(order.get_id(), response);
+        let event = self.order_event(order, OrderStatus::Filled, commission, pnl);
+        state.events.push(event);
+        let event = self.account_event(state);
+        state.events.push(event);
+    }
+
+    pub fn order_response(&self, order: &Order, status: OrderStatus) -> OrderResponse {
+        OrderResponse {
+            symbol: self.pair.clone(),
+            status,
+            client_order_id: order.get_id().to_string(),
+            price: order.get_price(),
+            orig_qty: order.get_quantity(),
+            executed_qty: 0.0,
+            avg_price: 0.0,
+            update_time: unix_millis(),
+            side: order.get_side(),
+            position_side: order.get_position_side()
+        }
+    }
+
+    // Orders are always filled at once, last price and quantity are only set on fills
+    fn order_event(&self, order: &Order, status: OrderStatus, commission: f64, pnl: f64) -> UserDataEvent {
+        let filled = status == OrderStatus::Filled;
+        UserDataEvent::OrderTradeUpdate {
+            order: OrderUpdate {
+                symbol: self.pair.clone(),
+                client_order_id: order.get_id().to_string(),
+                side: order.get_side(),
+                position_side: order.get_position_side(),
+                status,
+                price: order.get_price(),
+                quantity: order.get_quantity(),
+                last_price: if filled { order.get_price() } else { 0.0 },
+                last_quantity: if filled { order.get_quantity() } else { 0.0 },
+                commission,
+                realized_profit: pnl,
+                trade_time: unix_millis()
+            }
+        }
+    }
+
+    fn account_event(&self, state: &SimulatorState) -> UserDataEvent {
+        UserDataEvent::AccountUpdate {
+            account: AccountUpdate {
+                balances: vec![BalanceUpdate { asset: self.settings.margin_asset.clone(), wallet_balance: state.balance }],
+                positions: self.positions(state).into_iter().map(|position| PositionUpdate {
+                    symbol: position.symbol,
+                    position_side: position.position_side,
+                    position_amount: position.position_amt,
+                    entry_price: position.entry_price,
+                    unrealized_profit: position.unrealized_profit
+                }).collect()
+            }
+        }
+    }
+
+    // A single tier, the simulated positions are far too small to leave it
+    fn brackets() -> Vec<LeverageBracket> {
+        vec![LeverageBracket { bracket: 1, initial_leverage: 125, notional_cap: 1e12, notional_floor: 0.0, maint_margin_ratio: SIMULATED_MAINT_MARGIN_RATIO, cum: 0.0 }]
+    }
+
+    fn positions(&self, state: &SimulatorState) -> Vec<AccountPosition> {
+        let mark_price = (state.top_ask + state.top_bid) / 2.0;
+        vec![
+            AccountPosition {
+                symbol: self.pair.clone(),
+                position_side: PositionSide::Long,
+                position_amt: state.long_quantity,
+                entry_price: state.long_entry_price,
+                unrealized_profit: (mark_price - state.long_entry_price) * state.long_quantity
+            },
+            AccountPosition {
+                symbol: self.pair.clone(),
+                position_side: PositionSide::Short,
+                position_amt: -state.short_quantity,
+                entry_price: state.short_entry_price,
+                unrealized_profit: (state.short_entry_price - mark_price) * state.short_quantity
+            }
+        ]
+    }
+}
+
+#[async_trait]
+impl Exchange for SimulatedExchange {
+    async fn get_exchange_info(&self) -> Result<ExchangeInfo, GateError> {
+        Ok(ExchangeInfo {
+            symbols: vec![SymbolInfo {
+                symbol: self.pair.clone(),
+                status: String::from("TRADING"),
+                margin_asset: self.settings.margin_asset.clone(),
+                price_precision: decimal_count(self.settings.tick_size),
+                quantity_precision: decimal_count(self.settings.step_size),
+                filters: vec![
+                    SymbolFilter::PriceFilter { tick_size: self.settings.tick_size },
+                    SymbolFilter::LotSize { step_size: self.settings.step_size, min_qty: self.settings.step_size, max_qty: 1000000.0 },
+                    SymbolFilter::MaxNumOrders { limit: 200 }
+                ],
+                order_types: vec![String::from("LIMIT")],
+                time_in_force: vec![String::from("GTX")]
+            }]
+        })
+    }
+
+    async fn get_account_info(&self) -> Result<AccountInfo, GateError> {
+        let state = &self.state.lock().unwrap();
+        let positions = self.positions(state);
+        let mark_price = (state.top_ask + state.top_bid) / 2.0;
+        Ok(AccountInfo {
+            fee_tier: 0,
+            can_trade: true,
+            total_maint_margin: (state.long_quantity + state.short_quantity) * mark_price * SIMULATED_MAINT_MARGIN_RATIO,
+            total_margin_balance: state.balance + positions.iter().map(|position| position.unrealized_profit).sum::<f64>(),
+            assets: vec![AccountAsset { asset: self.settings.margin_asset.clone(), wallet_balance: state.balance }],
+            positions
+        })
+    }
+
+    async fn get_leverage_brackets(&self, _pair: &str) -> Result<Vec<LeverageBracket>, GateError> {
+        Ok(SimulatedExchange::brackets())
+    }
+
+    async fn get_premium_index(&self, _pair: &str) -> Result<PremiumIndex, GateError> {
+        let state = &self.state.lock().unwrap();
+        Ok(PremiumIndex {
+            symbol: self.pair.clone(),
+            mark_price: (state.top_ask + state.top_bid) / 2.0,
+            funding_rate: self.settings.funding_rate,
+            next_funding_time: state.next_funding_time
+        })
+    }
+
+    async fn get_funding_income(&self, _pair: &str, start_time: u64) -> Result<Vec<Income>, GateError> {
+        let state = &self.state.lock().unwrap();
+        Ok(state.incomes.iter().filter(|income| income.time >= start_time).cloned().collect())
+    }
+
+    async fn get_listen_key(&self) -> Result<String, GateError> {
+        Ok(String::from("backtest"))
+    }
+
+    async fn keepalive_listen_key(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_leverage(&self, _pair: &str, _leverage: u64) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_position_mode(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_multiassets_mode(&self) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn set_margin_mode(&self, _pair: &str) -> Result<(), GateError> {
+        Ok(())
+    }
+
+    async fn cancel_all_orders(&self, _pair: &str) -> Result<(), GateError> {
+        let state = &mut self.state.lock().unwrap();
+        state.resting_orders.clear();
+        state.queue_ahead.clear();
+        Ok(())
+    }
+
+    async fn get_open_orders(&self, _pair: &str) -> Result<Vec<OrderResponse>, GateError> {
+        let state = &self.state.lock().unwrap();
+        Ok(state.resting_orders.iter().map(|order| self.order_response(order, OrderStatus::New)).collect())
+    }
+
+    async fn get_order(&self, _pair: &str, order_id: u32) -> Result<OrderResponse, GateError> {
+        let state = &self.state.lock().unwrap();
+        if let Some(order) = state.resting_orders.iter().find(|order| order.get_id() == order_id) {
+            return Ok(self.order_response(order, OrderStatus::New))
+        }
+        match state.finished_orders.get(&order_id) {
+            Some(response) => Ok(response.clone()),
+            None => Err(GateError::Api(ApiCode::OrderNotFound, String::from("Order does not exist.")))
+        }
+    }
+
+    // Filled at the top of the book with the taker fee
+    async fn close_position(&self, _pair: &str, is_long: bool, quantity: f64, _quantity_decimal_count: i64) -> Result<(), GateError> {
+        let state = &mut self.state.lock().unwrap();
+        let position = if is_long { state.long_quantity } else { state.short_quantity };
+        if quantity > position + self.settings.step_size / 2.0 {
+            return Err(GateError::Api(ApiCode::ReduceOnlyRejected, String::from("ReduceOnly Order is rejected.")))
+        }
+        let order = Order::new(if is_long { state.top_bid } else { state.top_ask }, quantity, false, is_long);
+        self.fill_order(state, &order, self.settings.taker_fee);
+        Ok(())
+    }
+
+    async fn place_order(&self, pair: &str, order: &Order, price_decimal_count: i64, quantity_decimal_count: i64) -> bool {
+        self.place_orders(pair, vec![order.clone()], price_decimal_count, quantity_decimal_count).await
+    }
+
+    async fn cancel_order(&self, pair: &str, order: &Order) -> bool {
+        self.cancel_orders(pair, vec![order.clone()]).await
+    }
+
+    // A GTX order that would cross the book is accepted then expired, like Binance does
+    async fn place_orders(&self, _pair: &str, orders: Vec<Order>, _price_decimal_count: i64, _quantity_decimal_count: i64) -> bool {
+        let state = &mut self.state.lock().unwrap();
+        for order in orders.into_iter().filter(|order| order.is_real()) {
+            if crosses(state, &order) {
+                state.expired += 1;
+                state.finished_orders.insert(order.get_id(), self.order_response(&order, OrderStatus::Expired));
+                let event = self.order_event(&order, OrderStatus::Expired, 0.0, 0.0);
+                state.events.push(event);
+            } else {
+                let event = self.order_event(&order, OrderStatus::New, 0.0, 0.0);
+                state.events.push(event);
+                if self.queue_model {
+                    let queue = self.initial_queue(state, &order);
+                    state.queue_ahead.insert(order.get_id(), queue);
+                }
+                state.resting_orders.push(order);
+            }
+        }
+        true
+    }
+
+    async fn cancel_orders(&self, _pair: &str, orders: Vec<Order>) -> bool {
+        let mut success = true;
+        for order in orders.iter().filter(|order| order.is_real()) {
+            if self.cancel_by_id(order.get_id()).is_none() {
+                success = false;
+            }
+        }
+        success
+    }
+}
+
+fn crosses(state: &SimulatorState, order: &Order) -> bool {
+    if order.is_buy() { order.get_price() >= state.top_ask } else { order.get_price() <= state.top_bid }
+}
+
+pub fn decimal_count(size: f64) -> i64 {
+    (-size.log10()).round().max(0.0) as i64
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// First settlement strictly after now, interval in seconds
+fn next_funding_time(now: u64, interval: u64) -> u64 {
+    (now / (interval * 1000) + 1) * interval * 1000
+}
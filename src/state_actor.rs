@@ -0,0 +1,220 @@
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{sleep_until, Instant};
+use std::sync::Arc;
+use crate::memory_manager::MemoryManager;
+use crate::models::UserDataEvent;
+
+// What the websocket (or the backtest) feeds to a grid
+pub enum StateEvent {
+    // Event time in ms, 0 when the source has none and the local clock is used
+    Tick { top_ask: f64, top_bid: f64, time: u64 },
+    // Predicted rate and time of the coming funding settlement
+    Funding { rate: f64, next_funding_time: u64 },
+    User(UserDataEvent)
+}
+
+// Runs on the actor task, returns true when the grid has to react to the change
+type Job = Box<dyn FnOnce(&mut MemoryManager) -> bool + Send>;
+
+enum Message {
+    Event(StateEvent),
+    Job(Job)
+}
+
+// The MemoryManager of a grid lives on its own task and is only reached through this handle.
+// Every relevant change bumps a version the trader waits on, instead of polling the state.
+#[derive(Clone)]
+pub struct MemHandle {
+    sender: mpsc::UnboundedSender<Message>,
+    version: watch::Receiver<u64>,
+    passes: Arc<watch::Sender<u64>>
+}
+
+impl MemHandle {
+    pub fn spawn(memory_manager: MemoryManager) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (version_sender, version) = watch::channel(0);
+        tokio::spawn(run(memory_manager, receiver, version_sender));
+        MemHandle {
+            sender,
+            version,
+            passes: Arc::new(watch::channel(0).0)
+        }
+    }
+
+    pub fn send(&self, event: StateEvent) {
+        let _ = self.sender.send(Message::Event(event));
+    }
+
+    // Reads the state without waking the trader
+    pub async fn query<T: Send + 'static>(&self, query: impl FnOnce(&mut MemoryManager) -> T + Send + 'static) -> T {
+        self.run_job(query, false).await
+    }
+
+    // Changes the state and wakes the trader
+    pub async fn update<T: Send + 'static>(&self, update: impl FnOnce(&mut MemoryManager) -> T + Send + 'static) -> T {
+        self.run_job(update, true).await
+    }
+
+    async fn run_job<T: Send + 'static>(&self, job: impl FnOnce(&mut MemoryManager) -> T + Send + 'static, notify: bool) -> T {
+        let (reply_sender, reply) = oneshot::channel();
+        let job: Job = Box::new(move |memory_manager| {
+            let _ = reply_sender.send(job(memory_manager));
+            notify
+        });
+        if self.sender.send(Message::Job(job)).is_err() {
+            panic!("\x1b[91mERROR: The state actor stopped\x1b[0m");
+        }
+        reply.await.unwrap_or_else(|_| panic!("\x1b[91mERROR: The state actor stopped\x1b[0m"))
+    }
+
+    // Resolves on the first version where the condition holds
+    pub async fn wait_for<T: Send + 'static>(&self, condition: impl Fn(&mut MemoryManager) -> Option<T> + Clone + Send + 'static) -> T {
+        let mut version = self.version.clone();
+        loop {
+            version.borrow_and_update();
+            if let Some(value) = self.query(condition.clone()).await {
+                return value
+            }
+            let _ = version.changed().await;
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.version.clone()
+    }
+
+    // Called by the trader once it has reacted to every change up to version
+    pub fn pass_done(&self, version: u64) {
+        self.passes.send_replace(version);
+    }
+
+    // Waits until the trader has reacted to everything sent so far
+    pub async fn wait_pass(&self) {
+        self.query(|_| ()).await;
+        let target = *self.version.borrow();
+        let mut passes = self.passes.subscribe();
+        while *passes.borrow_and_update() < target {
+            let _ = passes.changed().await;
+        }
+    }
+}
+
+async fn run(mut memory_manager: MemoryManager, mut receiver: mpsc::UnboundedReceiver<Message>, version: watch::Sender<u64>) {
+    loop {
+        let deadline = memory_manager.next_pending_open();
+        let changed = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Event(event)) => memory_manager.apply_event(event),
+                Some(Message::Job(job)) => job(&mut memory_manager),
+                None => return
+            },
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => memory_manager.apply_pending_opens()
+        };
+        if changed {
+            version.send_modify(|version| *version += 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+    use crate::memory_manager::{load_grids, Venue};
+    use crate::models::{OrderStatus, OrderUpdate, PositionSide, Side};
+    use crate::secrets::Secrets;
+
+    // A grid on a REST client that is never called, nothing is written to disk
+    fn spawn_grid() -> MemHandle {
+        let config = crate::config::parse("test.json", r#"{
+            "testnet": true, "pair": "BTCUSDT", "leverage": 20, "close_only": false,
+            "margin": 1, "increment": 1, "acceleration": 0.1, "close_diff": 6, "order_amount": 5,
+            "order_quantity": 0.002, "max_position": 2.0, "soft_position": 0.75,
+            "reconcile_interval": 0, "journal": "", "stats_file": ""
+        }"#).unwrap_or_else(|errors| panic!("{:?}", errors));
+        load_grids(&config, &Secrets::default(), &Venue::Live).remove(0)
+    }
+
+    fn open_long_fill(price: f64) -> StateEvent {
+        StateEvent::User(UserDataEvent::OrderTradeUpdate {
+            order: OrderUpdate {
+                symbol: String::from("BTCUSDT"),
+                client_order_id: String::from("1"),
+                side: Side::Buy,
+                position_side: PositionSide::Long,
+                status: OrderStatus::Filled,
+                price,
+                quantity: 0.002,
+                last_price: price,
+                last_quantity: 0.002,
+                commission: 0.0,
+                realized_profit: 0.0,
+                trade_time: 0
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn query_does_not_wake_and_update_does() {
+        let mem = spawn_grid();
+        let changes = mem.subscribe();
+        mem.query(|local_mem| local_mem.get_pair()).await;
+        assert_eq!(*changes.borrow(), 0);
+        mem.update(|local_mem| local_mem.set_open_scale(0.5)).await;
+        assert_eq!(*changes.borrow(), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_for_resolves_once_the_condition_holds() {
+        let mem = spawn_grid();
+        let waiter = tokio::spawn({
+            let mem = mem.clone();
+            async move { mem.wait_for(|local_mem| Some(local_mem.get_top_ask()).filter(|price| *price > 0.0)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        mem.send(StateEvent::Tick { top_ask: 101.0, top_bid: 99.0, time: 0 });
+        assert_eq!(timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap(), 101.0);
+    }
+
+    #[tokio::test]
+    async fn unchanged_tick_does_not_wake() {
+        let mem = spawn_grid();
+        let changes = mem.subscribe();
+        mem.send(StateEvent::Tick { top_ask: 101.0, top_bid: 99.0, time: 0 });
+        mem.send(StateEvent::Tick { top_ask: 101.0, top_bid: 99.0, time: 0 });
+        mem.query(|_| ()).await;
+        assert_eq!(*changes.borrow(), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_pass_waits_for_the_trader() {
+        let mem = spawn_grid();
+        mem.send(StateEvent::Tick { top_ask: 101.0, top_bid: 99.0, time: 0 });
+        let waiter = tokio::spawn({
+            let mem = mem.clone();
+            async move { mem.wait_pass().await }
+        });
+        assert!(timeout(Duration::from_millis(50), mem.wait_pass()).await.is_err());
+        assert!(!waiter.is_finished());
+        mem.pass_done(1);
+        timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+        // Nothing new was sent since
+        timeout(Duration::from_secs(5), mem.wait_pass()).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_open_is_applied_at_its_deadline() {
+        let mem = spawn_grid();
+        let mut changes = mem.subscribe();
+        let start = Instant::now();
+        mem.send(open_long_fill(100.0));
+        changes.changed().await.unwrap();
+        assert!(mem.query(|local_mem| local_mem.next_pending_open()).await.is_some());
+        changes.changed().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(*changes.borrow(), 2);
+        assert!(mem.query(|local_mem| local_mem.next_pending_open()).await.is_none());
+    }
+}
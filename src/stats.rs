@@ -0,0 +1,140 @@
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StatsTotals {
+    pub volume: f64,
+    pub commission: f64,
+    pub realized_profit: f64,
+    // Received when positive, missing from the checkpoints written before it was tracked
+    #[serde(default)]
+    pub funding: f64
+}
+
+impl StatsTotals {
+    fn add(&mut self, other: &StatsTotals) {
+        self.volume += other.volume;
+        self.commission += other.commission;
+        self.realized_profit += other.realized_profit;
+        self.funding += other.funding;
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct StatsBucket {
+    hour: u64,
+    #[serde(flatten)]
+    totals: StatsTotals
+}
+
+#[derive(Serialize, Deserialize)]
+struct StatsCheckpoint {
+    started: u64,
+    all_time: StatsTotals,
+    buckets: VecDeque<StatsBucket>
+}
+
+// Volume, commission, realized profit and funding kept across restarts, as all-time totals
+// and as hourly buckets covering the rolling window
+pub struct HistoryStats {
+    path: String,
+    window: Duration,
+    started: u64,
+    all_time: StatsTotals,
+    buckets: VecDeque<StatsBucket>
+}
+
+impl HistoryStats {
+    // Starts from the checkpoint at path if there is one, an empty path keeps the stats in memory only
+    pub fn restore(path: &str, window_days: u64) -> Self {
+        let mut history = HistoryStats {
+            path: String::from(path),
+            window: Duration::from_secs(window_days * 86400),
+            started: unix_now(),
+            all_time: StatsTotals::default(),
+            buckets: VecDeque::new()
+        };
+        if path.is_empty() {
+            return history
+        }
+        let checkpoint_str = match std::fs::read_to_string(path) {
+            Ok(checkpoint_str) => checkpoint_str,
+            Err(_) => return history
+        };
+        match serde_json::from_str::<StatsCheckpoint>(&checkpoint_str) {
+            Ok(checkpoint) => {
+                let started = Local.timestamp_opt(checkpoint.started as i64, 0).single().map(|started| started.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                println!("\x1b[94mRestored stats since {} from {}\x1b[0m", started, path);
+                history.started = checkpoint.started;
+                history.all_time = checkpoint.all_time;
+                history.buckets = checkpoint.buckets;
+                history.prune();
+            },
+            Err(error) => println!("\x1b[91mFailed to restore stats from {}: {}, starting from scratch\x1b[0m", path, error)
+        }
+        history
+    }
+
+    pub fn add(&mut self, volume: f64, commission: f64, realized_profit: f64) {
+        self.record(StatsTotals { volume, commission, realized_profit, funding: 0.0 });
+    }
+
+    pub fn add_funding(&mut self, funding: f64) {
+        self.record(StatsTotals { funding, ..StatsTotals::default() });
+    }
+
+    fn record(&mut self, totals: StatsTotals) {
+        self.all_time.add(&totals);
+        let hour = unix_now() / 3600;
+        match self.buckets.back_mut() {
+            Some(bucket) if bucket.hour == hour => bucket.totals.add(&totals),
+            _ => self.buckets.push_back(StatsBucket { hour, totals })
+        }
+        self.prune();
+    }
+
+    // Time covered by the rolling window, capped by the first start, and the totals over it
+    pub fn get_window(&self) -> (Duration, StatsTotals) {
+        let now = unix_now();
+        let start = self.started.max(now.saturating_sub(self.window.as_secs()));
+        let mut totals = StatsTotals::default();
+        for bucket in self.buckets.iter().filter(|bucket| bucket.hour * 3600 + 3600 > start) {
+            totals.add(&bucket.totals);
+        }
+        (Duration::from_secs(now - start), totals)
+    }
+
+    pub fn get_all_time(&self) -> (Duration, StatsTotals) {
+        (Duration::from_secs(unix_now().saturating_sub(self.started)), self.all_time)
+    }
+
+    // Written to a temporary file first so a crash never leaves a truncated checkpoint
+    pub fn save(&self) {
+        if self.path.is_empty() {
+            return
+        }
+        let checkpoint = StatsCheckpoint {
+            started: self.started,
+            all_time: self.all_time,
+            buckets: self.buckets.clone()
+        };
+        let tmp_path = format!("{}.tmp", self.path);
+        let result = std::fs::write(&tmp_path, serde_json::to_string(&checkpoint).unwrap()).and_then(|_| std::fs::rename(&tmp_path, &self.path));
+        if let Err(error) = result {
+            println!("\x1b[91mFailed to save stats to {}: {}\x1b[0m", self.path, error);
+        }
+    }
+
+    fn prune(&mut self) {
+        let oldest_hour = unix_now().saturating_sub(self.window.as_secs()) / 3600;
+        while self.buckets.front().is_some_and(|bucket| bucket.hour < oldest_hour) {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
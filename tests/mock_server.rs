@@ -0,0 +1,187 @@
+use futures::StreamExt;
+use program::binance::BinanceExchange;
+use program::error::ApiCode;
+use program::exchange::Exchange;
+use program::mock_server::{self, MockSettings};
+use program::models::{BookTicker, OrderStatus, OrderUpdate, PositionSide, StreamMessage, UserDataEvent};
+use program::order_manager::Order;
+use program::rate_limiter::RateLimitSettings;
+use program::retry::RetrySettings;
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+const PAIR: &str = "BTCUSDT";
+const TICK: f64 = 0.1;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Every test runs its own server, on its own ports, and talks to it with the real REST client
+async fn start(rest_port: u16, ws_port: u16, volatility: f64) -> (BinanceExchange, Socket) {
+    let settings: MockSettings = serde_json::from_value(json!({
+        "rest_port": rest_port,
+        "ws_port": ws_port,
+        "start_price": 20000.0,
+        "tick_interval": 5,
+        "volatility": volatility
+    })).unwrap();
+    mock_server::start(vec![String::from(PAIR)], settings.clone()).await;
+    let exchange = BinanceExchange::new(settings.get_url_request(), "key", "secret", RetrySettings::default(), RateLimitSettings::default());
+    let listen_key = exchange.get_listen_key().await.unwrap();
+    let url = url::Url::parse(&format!("{}/stream?streams={}@bookTicker/{}", settings.get_url_websocket(), PAIR.to_lowercase(), listen_key)).unwrap();
+    let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    (exchange, socket)
+}
+
+enum Pushed {
+    Book(BookTicker),
+    User(UserDataEvent)
+}
+
+async fn next_message(socket: &mut Socket) -> Pushed {
+    loop {
+        let text = match timeout(Duration::from_secs(30), socket.next()).await.expect("nothing pushed for 30s") {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(_)) => continue,
+            Some(Err(error)) => panic!("websocket error: {}", error),
+            None => panic!("websocket closed")
+        };
+        let message: StreamMessage = serde_json::from_str(&text).unwrap();
+        if message.stream.ends_with("@bookTicker") {
+            return Pushed::Book(serde_json::from_value(message.data).unwrap())
+        }
+        return Pushed::User(serde_json::from_value(message.data).unwrap())
+    }
+}
+
+// The book once the market task has run, orders placed before would all take
+async fn next_book(socket: &mut Socket) -> BookTicker {
+    loop {
+        if let Pushed::Book(book) = next_message(socket).await {
+            return book
+        }
+    }
+}
+
+async fn next_order_update(socket: &mut Socket, matches: impl Fn(&OrderUpdate) -> bool) -> OrderUpdate {
+    loop {
+        if let Pushed::User(UserDataEvent::OrderTradeUpdate { order }) = next_message(socket).await {
+            if matches(&order) {
+                return order
+            }
+        }
+    }
+}
+
+fn is_update_of(order: &Order, status: OrderStatus) -> impl Fn(&OrderUpdate) -> bool {
+    let client_order_id = order.get_id().to_string();
+    move |update: &OrderUpdate| update.client_order_id == client_order_id && update.status == status
+}
+
+async fn open_ids(exchange: &BinanceExchange) -> Vec<String> {
+    let mut ids: Vec<String> = exchange.get_open_orders(PAIR).await.unwrap().into_iter().map(|order| order.client_order_id).collect();
+    ids.sort();
+    ids
+}
+
+fn ids_of(orders: &[&Order]) -> Vec<String> {
+    let mut ids: Vec<String> = orders.iter().map(|order| order.get_id().to_string()).collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn place_batch_and_cancel() {
+    let (exchange, mut socket) = start(28110, 28111, 0.0).await;
+    let book = next_book(&mut socket).await;
+
+    let single = Order::new(book.best_bid - 10.0 * TICK, 0.002, true, true);
+    assert!(exchange.place_order(PAIR, &single, 1, 3).await);
+    let update = next_order_update(&mut socket, is_update_of(&single, OrderStatus::New)).await;
+    assert_eq!(update.side, single.get_side());
+    assert_eq!(update.position_side, PositionSide::Long);
+    let open_orders = exchange.get_open_orders(PAIR).await.unwrap();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].status, OrderStatus::New);
+    assert!((open_orders[0].price - single.get_price()).abs() < TICK / 2.0);
+    assert!((open_orders[0].orig_qty - 0.002).abs() < 1e-9);
+
+    let batch = vec![
+        Order::new(book.best_bid - 20.0 * TICK, 0.002, true, true),
+        Order::new(book.best_ask + 10.0 * TICK, 0.002, true, false),
+        Order::new(book.best_ask + 20.0 * TICK, 0.003, true, false)
+    ];
+    assert!(exchange.place_orders(PAIR, batch.clone(), 1, 3).await);
+    assert_eq!(open_ids(&exchange).await, ids_of(&[&single, &batch[0], &batch[1], &batch[2]]));
+
+    assert!(exchange.cancel_order(PAIR, &single).await);
+    next_order_update(&mut socket, is_update_of(&single, OrderStatus::Canceled)).await;
+    assert!(exchange.cancel_orders(PAIR, vec![batch[0].clone(), batch[2].clone()]).await);
+    assert_eq!(open_ids(&exchange).await, ids_of(&[&batch[1]]));
+    assert_eq!(exchange.get_order(PAIR, single.get_id()).await.unwrap().status, OrderStatus::Canceled);
+    assert_eq!(exchange.get_order(PAIR, batch[1].get_id()).await.unwrap().status, OrderStatus::New);
+
+    // Already gone
+    assert!(!exchange.cancel_order(PAIR, &single).await);
+    exchange.cancel_all_orders(PAIR).await.unwrap();
+    assert!(open_ids(&exchange).await.is_empty());
+}
+
+#[tokio::test]
+async fn fills_push_order_and_account_updates() {
+    let (exchange, mut socket) = start(28120, 28121, 1.0).await;
+    let book = next_book(&mut socket).await;
+
+    // Whichever way the market walks, one of the two opens is filled
+    let long = Order::new(book.best_bid - 2.0 * TICK, 0.002, true, true);
+    let short = Order::new(book.best_ask + 2.0 * TICK, 0.002, true, false);
+    assert!(exchange.place_orders(PAIR, vec![long.clone(), short.clone()], 1, 3).await);
+    let (long_id, short_id) = (long.get_id().to_string(), short.get_id().to_string());
+    let fill = next_order_update(&mut socket, |update| update.status == OrderStatus::Filled && (update.client_order_id == long_id || update.client_order_id == short_id)).await;
+    assert!((fill.last_quantity - 0.002).abs() < 1e-9);
+    assert!(fill.commission > 0.0);
+    assert!(fill.trade_time > 0);
+
+    let position_side = fill.position_side;
+    let account = loop {
+        if let Pushed::User(UserDataEvent::AccountUpdate { account }) = next_message(&mut socket).await {
+            break account
+        }
+    };
+    let position = account.positions.iter().find(|position| position.symbol == PAIR && position.position_side == position_side).unwrap();
+    assert!((position.position_amount.abs() - 0.002).abs() < 1e-9);
+    assert!(!account.balances.is_empty());
+
+    let filled = exchange.get_order(PAIR, fill.client_order_id.parse::<u32>().unwrap()).await.unwrap();
+    assert_eq!(filled.status, OrderStatus::Filled);
+    assert!((filled.executed_qty - 0.002).abs() < 1e-9);
+    let account_info = exchange.get_account_info().await.unwrap();
+    assert!(account_info.positions.iter().any(|position| position.symbol == PAIR && position.position_side == position_side && (position.position_amt.abs() - 0.002).abs() < 1e-9));
+}
+
+#[tokio::test]
+async fn crossing_gtx_order_is_rejected() {
+    let (exchange, mut socket) = start(28130, 28131, 0.0).await;
+    let book = next_book(&mut socket).await;
+
+    // The raw answer of the mock to a buy at the ask
+    let crossing = Order::new(book.best_ask, 0.002, true, true);
+    let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:28130/fapi/v1/order?{}", crossing.to_single_url_string(PAIR, 1, 3)))
+        .send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(body["code"], -5022);
+
+    // The client takes it as not posted rather than failed, and the order never existed
+    let crossing = Order::new(book.best_bid, 0.002, true, false);
+    let resting = Order::new(book.best_bid - 10.0 * TICK, 0.002, true, true);
+    assert!(exchange.place_orders(PAIR, vec![crossing.clone(), resting.clone()], 1, 3).await);
+    assert_eq!(open_ids(&exchange).await, ids_of(&[&resting]));
+    match exchange.get_order(PAIR, crossing.get_id()).await {
+        Err(error) => assert!(error.is_api(ApiCode::OrderNotFound), "{}", error),
+        Ok(order) => panic!("crossing order known as {:?}", order.status)
+    }
+}